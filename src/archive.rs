@@ -0,0 +1,587 @@
+use crate::clips::{ClipEvent, ClipKind};
+use crate::progress::Progress;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const MIB: usize = 1024 * 1024;
+
+// Kept in the destination folder; records every file copied so far.
+pub const MANIFEST_NAME: &str = "teslausb-archive.json";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveOptions {
+    pub destination: String,
+    // Event ids from the clip index. Without a selection every event is
+    // archived, skipping files the manifest already has.
+    #[serde(default)]
+    pub events: Option<Vec<String>>,
+    // Remove an event from the stick once all its files are copied and
+    // verified.
+    #[serde(default)]
+    pub delete_after_copy: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ArchiveManifest {
+    pub last_run: Option<u64>,
+    // Keyed by path relative to the destination.
+    pub files: BTreeMap<String, ArchivedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ArchivedFile {
+    pub event: String,
+    pub size: u64,
+    pub sha256: String,
+    pub archived_at: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchiveReport {
+    pub job_id: String,
+    pub destination: String,
+    pub events_archived: usize,
+    pub events_deleted: usize,
+    pub files_copied: usize,
+    pub files_skipped: usize,
+    pub bytes_copied: u64,
+    pub failures: Vec<ArchiveFailure>,
+    pub elapsed_secs: f64,
+}
+
+// One file of an event, relative to the TeslaCam folder and to the
+// destination.
+struct PlannedFile {
+    source: String,
+    target: String,
+    size: u64,
+}
+
+pub async fn archive_events(teslacam: &Path, options: &ArchiveOptions, progress: &Progress) -> Result<ArchiveReport> {
+    let teslacam = teslacam.to_path_buf();
+    let options = options.clone();
+    let progress = progress.clone();
+    
+    tokio::task::spawn_blocking(move || {
+        let index = crate::clips::build_index(&teslacam)?;
+        let events: Vec<ClipEvent> = match &options.events {
+            Some(ids) => {
+                let missing: Vec<&String> = ids.iter().filter(|id| !index.events.iter().any(|e| &e.id == *id)).collect();
+                if !missing.is_empty() {
+                    return Err(anyhow::anyhow!("Events not found on the drive: {:?}", missing));
+                }
+                index.events.into_iter().filter(|e| ids.contains(&e.id)).collect()
+            }
+            None => index.events,
+        };
+        
+        archive(&teslacam, &events, &options, &progress)
+    }).await?
+}
+
+// Frees space on the stick: deletes events of the given kinds whose every
+// file is in the destination's manifest, still has its copy there, and
+// still hashes to what was archived. Returns how many events were removed.
+pub async fn remove_archived(teslacam: &Path, destination: &Path, kinds: &[ClipKind]) -> Result<usize> {
+    let teslacam = teslacam.to_path_buf();
+    let destination = destination.to_path_buf();
+    let kinds = kinds.to_vec();
+    
+    tokio::task::spawn_blocking(move || {
+        let index = crate::clips::build_index(&teslacam)?;
+        let manifest = load_manifest(&destination)?;
+        let mut removed = 0;
+        
+        for event in index.events.iter().filter(|e| kinds.contains(&e.kind)) {
+            let files = plan_event(&teslacam, event);
+            let verified = files.iter().all(|file| {
+                already_archived(&manifest, &destination, file) && source_matches(&manifest, &teslacam, file)
+            });
+            
+            if verified {
+                delete_event(&teslacam, event, &files)?;
+                removed += 1;
+            }
+        }
+        
+        Ok(removed)
+    }).await?
+}
+
+fn archive(teslacam: &Path, events: &[ClipEvent], options: &ArchiveOptions, progress: &Progress) -> Result<ArchiveReport> {
+    let started = Instant::now();
+    let destination = Path::new(&options.destination);
+    std::fs::create_dir_all(destination)
+        .map_err(|e| anyhow::anyhow!("Cannot create {}: {}", destination.display(), e))?;
+    
+    let mut manifest = load_manifest(destination)?;
+    let mut report = ArchiveReport {
+        job_id: progress.job_id().to_string(),
+        destination: options.destination.clone(),
+        events_archived: 0,
+        events_deleted: 0,
+        files_copied: 0,
+        files_skipped: 0,
+        bytes_copied: 0,
+        failures: Vec::new(),
+        elapsed_secs: 0.0,
+    };
+    
+    let plans: Vec<(&ClipEvent, Vec<PlannedFile>)> = events.iter().map(|e| (e, plan_event(teslacam, e))).collect();
+    let total: u64 = plans.iter()
+        .flat_map(|(_, files)| files)
+        .filter(|f| !already_archived(&manifest, destination, f))
+        .map(|f| f.size)
+        .sum();
+    let mut done = 0;
+    progress.update("copy", 0, total);
+    
+    for (event, files) in &plans {
+        let mut copied_any = false;
+        let mut complete = true;
+        
+        for file in files {
+            if already_archived(&manifest, destination, file) {
+                report.files_skipped += 1;
+                // Skipping only compares sizes, so check the contents
+                // before letting the event be deleted.
+                if options.delete_after_copy && !source_matches(&manifest, teslacam, file) {
+                    complete = false;
+                    report.failures.push(ArchiveFailure {
+                        path: file.source.clone(),
+                        error: "differs from the archived copy, so it was kept on the drive".to_string(),
+                    });
+                }
+                continue;
+            }
+            
+            let target = destination.join(&file.target);
+            let result = copy_verified(&teslacam.join(&file.source), &target, &mut |n| {
+                done += n;
+                progress.update("copy", done, total);
+            });
+            
+            match result {
+                Ok(sha256) => {
+                    manifest.files.insert(file.target.clone(), ArchivedFile {
+                        event: event.id.clone(),
+                        size: file.size,
+                        sha256,
+                        archived_at: now_secs(),
+                    });
+                    report.files_copied += 1;
+                    report.bytes_copied += file.size;
+                    copied_any = true;
+                }
+                Err(e) => {
+                    complete = false;
+                    report.failures.push(ArchiveFailure {
+                        path: file.source.clone(),
+                        error: e.to_string(),
+                    });
+                }
+            }
+        }
+        
+        // Saved after every event, so an interrupted run keeps what it
+        // already verified.
+        if copied_any {
+            report.events_archived += 1;
+            save_manifest(destination, &manifest)?;
+        }
+        
+        if options.delete_after_copy && complete {
+            match delete_event(teslacam, event, files) {
+                Ok(()) => report.events_deleted += 1,
+                Err(e) => report.failures.push(ArchiveFailure {
+                    path: event.id.clone(),
+                    error: format!("copied, but could not delete from the drive: {}", e),
+                }),
+            }
+        }
+    }
+    
+    manifest.last_run = Some(now_secs());
+    save_manifest(destination, &manifest)?;
+    
+    report.elapsed_secs = started.elapsed().as_secs_f64();
+    Ok(report)
+}
+
+// Saved and Sentry events land in "<ClipsFolder>/<YYYY-MM-DD_HH-MM-SS>/",
+// the layout the car itself uses. Recent clips go under the day of each
+// clip instead: the car recycles the oldest ones, which moves the start of
+// the drive they belong to, and the archived copies must not move with it.
+fn plan_event(teslacam: &Path, event: &ClipEvent) -> Vec<PlannedFile> {
+    let mut sources: Vec<(String, String)> = event.segments.iter()
+        .flat_map(|segment| {
+            let folder = match &event.folder {
+                Some(folder) => folder.clone(),
+                None => format!("{}/{}", event.kind.folder(), segment.timestamp.get(..10).unwrap_or(&segment.timestamp)),
+            };
+            segment.files.iter().map(move |f| (folder.clone(), f.path.clone()))
+        })
+        .collect();
+    if let Some(folder) = &event.folder {
+        for name in ["event.json", "thumb.png"] {
+            if teslacam.join(folder).join(name).is_file() {
+                sources.push((folder.clone(), format!("{}/{}", folder, name)));
+            }
+        }
+    }
+    
+    sources.into_iter()
+        .map(|(folder, source)| {
+            let name = source.rsplit('/').next().unwrap_or(&source).to_string();
+            PlannedFile {
+                size: std::fs::metadata(teslacam.join(&source)).map(|m| m.len()).unwrap_or(0),
+                target: format!("{}/{}", folder, name),
+                source,
+            }
+        })
+        .collect()
+}
+
+fn already_archived(manifest: &ArchiveManifest, destination: &Path, file: &PlannedFile) -> bool {
+    manifest.files.get(&file.target).is_some_and(|archived| {
+        archived.size == file.size
+            && std::fs::metadata(destination.join(&file.target)).is_ok_and(|m| m.len() == file.size)
+    })
+}
+
+fn source_matches(manifest: &ArchiveManifest, teslacam: &Path, file: &PlannedFile) -> bool {
+    manifest.files.get(&file.target).is_some_and(|archived| {
+        hash_file(&teslacam.join(&file.source)).is_ok_and(|sha256| sha256 == archived.sha256)
+    })
+}
+
+// Copies through "<target>.part", picking up where an earlier, interrupted
+// copy left off, then reads the result back and compares SHA-256 with the
+// source before renaming it into place. A resumed copy that fails
+// verification is redone from scratch once.
+fn copy_verified(source: &Path, target: &Path, on_copied: &mut dyn FnMut(u64)) -> Result<String> {
+    if let Some(parent) = target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut part = target.as_os_str().to_owned();
+    part.push(".part");
+    let part = PathBuf::from(part);
+    
+    let source_size = std::fs::metadata(source)?.len();
+    let resumable = std::fs::metadata(&part).map(|m| m.len()).unwrap_or(0);
+    
+    let mut resume = resumable <= source_size;
+    loop {
+        let expected = copy_into(source, &part, resume, on_copied)?;
+        let actual = hash_file(&part)?;
+        
+        if actual == expected {
+            std::fs::rename(&part, target)?;
+            return Ok(expected);
+        }
+        if !resume {
+            let _ = std::fs::remove_file(&part);
+            return Err(anyhow::anyhow!("Checksum mismatch after copying to {}", target.display()));
+        }
+        resume = false;
+    }
+}
+
+// Returns the SHA-256 of the source. When resuming, the source bytes the
+// part file already covers are hashed but not copied again.
+fn copy_into(source: &Path, part: &Path, resume: bool, on_copied: &mut dyn FnMut(u64)) -> Result<String> {
+    let mut input = File::open(source)?;
+    let mut output = OpenOptions::new().create(true).write(true).truncate(!resume).open(part)?;
+    let existing = output.seek(SeekFrom::End(0))?;
+    
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; MIB];
+    let mut offset = 0u64;
+    
+    loop {
+        let read = input.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+        
+        // Only the part beyond what the file already holds is written.
+        let skip = existing.saturating_sub(offset).min(read as u64) as usize;
+        output.write_all(&buffer[skip..read])?;
+        offset += read as u64;
+        on_copied(read as u64);
+    }
+    
+    output.set_len(offset)?;
+    output.sync_all()?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn hash_file(path: &Path) -> Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buffer = vec![0u8; MIB];
+    
+    loop {
+        let read = file.read(&mut buffer)?;
+        if read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..read]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+fn delete_event(teslacam: &Path, event: &ClipEvent, files: &[PlannedFile]) -> Result<()> {
+    for file in files {
+        std::fs::remove_file(teslacam.join(&file.source))?;
+    }
+    if let Some(folder) = &event.folder {
+        // Fails, harmlessly, if the car put anything else in there.
+        let _ = std::fs::remove_dir(teslacam.join(folder));
+    }
+    Ok(())
+}
+
+pub fn load_manifest(destination: &Path) -> Result<ArchiveManifest> {
+    match std::fs::read_to_string(destination.join(MANIFEST_NAME)) {
+        Ok(text) => serde_json::from_str(&text)
+            .map_err(|e| anyhow::anyhow!("Archive manifest in {} is corrupt: {}", destination.display(), e)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(ArchiveManifest::default()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Written next to the real one and renamed over it, so a crash never leaves
+// a half-written manifest on the NAS.
+fn save_manifest(destination: &Path, manifest: &ArchiveManifest) -> Result<()> {
+    let temp = destination.join(format!("{}.tmp", MANIFEST_NAME));
+    std::fs::write(&temp, serde_json::to_string_pretty(manifest)?)?;
+    std::fs::rename(&temp, destination.join(MANIFEST_NAME))?;
+    Ok(())
+}
+
+fn now_secs() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    struct Fixture {
+        root: PathBuf,
+        teslacam: PathBuf,
+        destination: PathBuf,
+    }
+    
+    impl Fixture {
+        fn new(files: &[(&str, &[u8])]) -> Self {
+            let root = std::env::temp_dir().join(format!("teslausb-archive-{}", uuid::Uuid::new_v4()));
+            let fixture = Fixture {
+                teslacam: root.join("TeslaCam"),
+                destination: root.join("nas"),
+                root,
+            };
+            for (file, contents) in files {
+                fixture.add(file, contents);
+            }
+            fixture
+        }
+        
+        fn add(&self, file: &str, contents: &[u8]) {
+            let path = self.teslacam.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, contents).unwrap();
+        }
+        
+        fn run(&self, events: Option<Vec<&str>>, delete_after_copy: bool) -> ArchiveReport {
+            let options = ArchiveOptions {
+                destination: self.destination.to_string_lossy().into_owned(),
+                events: events.map(|ids| ids.iter().map(|id| id.to_string()).collect()),
+                delete_after_copy,
+            };
+            let index = crate::clips::build_index(&self.teslacam).unwrap();
+            let selected: Vec<ClipEvent> = index.events.into_iter()
+                .filter(|e| options.events.as_ref().is_none_or(|ids| ids.contains(&e.id)))
+                .collect();
+            archive(&self.teslacam, &selected, &options, &Progress::recording("archive").0).unwrap()
+        }
+    }
+    
+    impl Drop for Fixture {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.root);
+        }
+    }
+    
+    const SENTRY: &str = "SentryClips/2024-03-02_18-04-11";
+    
+    #[test]
+    fn copies_events_with_metadata_and_records_checksums() {
+        let fixture = Fixture::new(&[
+            ("SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-front.mp4", b"front"),
+            ("SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-back.mp4", b"back"),
+            ("SentryClips/2024-03-02_18-04-11/event.json", b"{}"),
+            ("SentryClips/2024-03-02_18-04-11/thumb.png", b"png"),
+            ("RecentClips/2024-03-03_09-00-00-front.mp4", b"recent"),
+        ]);
+        
+        let report = fixture.run(Some(vec![SENTRY]), false);
+        
+        assert_eq!((report.events_archived, report.files_copied, report.bytes_copied), (1, 4, 14));
+        let copied = std::fs::read(fixture.destination.join(SENTRY).join("2024-03-02_18-03-20-back.mp4")).unwrap();
+        assert_eq!(copied, b"back");
+        
+        let manifest = load_manifest(&fixture.destination).unwrap();
+        assert_eq!(manifest.files.len(), 4);
+        assert_eq!(
+            manifest.files[&format!("{}/event.json", SENTRY)].sha256,
+            "44136fa355b3678a1146ad16f7e8649e94fb4fc21fe77e8310c060f61caaff8a"
+        );
+        assert!(!fixture.destination.join("RecentClips").exists());
+    }
+    
+    #[test]
+    fn resumes_partial_copies_and_redoes_corrupt_ones() {
+        let fixture = Fixture::new(&[]);
+        let source = fixture.root.join("clip.mp4");
+        let target = fixture.destination.join("clip.mp4");
+        let part = fixture.destination.join("clip.mp4.part");
+        let contents: Vec<u8> = (0..3 * MIB).map(|i| (i % 251) as u8).collect();
+        std::fs::create_dir_all(&fixture.destination).unwrap();
+        std::fs::write(&source, &contents).unwrap();
+        
+        std::fs::write(&part, &contents[..MIB + 7]).unwrap();
+        let mut written = 0;
+        copy_verified(&source, &target, &mut |n| written += n).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), contents);
+        assert!(!part.exists());
+        
+        // A part file whose bytes don't match the source.
+        std::fs::write(&part, vec![0u8; 2 * MIB]).unwrap();
+        copy_verified(&source, &target, &mut |_| {}).unwrap();
+        assert_eq!(std::fs::read(&target).unwrap(), contents);
+        assert_eq!(written, 3 * MIB as u64);
+    }
+    
+    #[test]
+    fn incremental_runs_only_copy_new_files_and_can_delete() {
+        let fixture = Fixture::new(&[
+            ("SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-front.mp4", b"front"),
+            ("RecentClips/2024-03-03_09-00-00-front.mp4", b"first"),
+        ]);
+        assert_eq!(fixture.run(None, false).files_copied, 2);
+        
+        // The drive carried on: a new minute in the same drive.
+        fixture.add("RecentClips/2024-03-03_09-01-00-front.mp4", b"second");
+        let report = fixture.run(None, true);
+        
+        assert_eq!((report.files_copied, report.files_skipped, report.events_archived), (1, 2, 1));
+        assert!(fixture.destination.join("RecentClips/2024-03-03/2024-03-03_09-01-00-front.mp4").is_file());
+        assert_eq!(report.events_deleted, 2);
+        assert!(!fixture.teslacam.join(SENTRY).exists());
+        assert!(crate::clips::build_index(&fixture.teslacam).unwrap().events.is_empty());
+    }
+    
+    #[test]
+    fn recycled_recent_clips_are_not_copied_again() {
+        let fixture = Fixture::new(&[
+            ("RecentClips/2024-03-03_09-00-00-front.mp4", b"first"),
+            ("RecentClips/2024-03-03_09-01-00-front.mp4", b"second"),
+            ("RecentClips/2024-03-03_09-02-00-front.mp4", b"third"),
+        ]);
+        assert_eq!(fixture.run(None, false).files_copied, 3);
+        
+        // The car recycled the oldest clip and recorded another minute, so
+        // the drive now starts a minute later.
+        std::fs::remove_file(fixture.teslacam.join("RecentClips/2024-03-03_09-00-00-front.mp4")).unwrap();
+        fixture.add("RecentClips/2024-03-03_09-03-00-front.mp4", b"fourth");
+        let report = fixture.run(None, false);
+        
+        assert_eq!((report.files_copied, report.files_skipped), (1, 2));
+        let mut archived: Vec<String> = std::fs::read_dir(fixture.destination.join("RecentClips/2024-03-03"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        archived.sort();
+        assert_eq!(archived, vec![
+            "2024-03-03_09-00-00-front.mp4",
+            "2024-03-03_09-01-00-front.mp4",
+            "2024-03-03_09-02-00-front.mp4",
+            "2024-03-03_09-03-00-front.mp4",
+        ]);
+        assert_eq!(std::fs::read_dir(fixture.destination.join("RecentClips")).unwrap().count(), 1);
+    }
+    
+    #[tokio::test]
+    async fn only_removes_events_that_match_the_archive() {
+        let fixture = Fixture::new(&[
+            ("SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-front.mp4", b"front"),
+            ("SavedClips/2024-03-01_08-00-00/2024-03-01_07-59-00-front.mp4", b"saved"),
+            ("RecentClips/2024-03-03_09-00-00-front.mp4", b"recent"),
+        ]);
+        fixture.run(None, false);
+        // Changed on the stick since it was archived.
+        fixture.add("SavedClips/2024-03-01_08-00-00/2024-03-01_07-59-00-front.mp4", b"SAVED");
+        
+        let removed = remove_archived(&fixture.teslacam, &fixture.destination, &[ClipKind::Saved, ClipKind::Sentry])
+            .await
+            .unwrap();
+        
+        assert_eq!(removed, 1);
+        assert!(!fixture.teslacam.join(SENTRY).exists());
+        assert!(fixture.teslacam.join("SavedClips/2024-03-01_08-00-00").is_dir());
+        assert!(fixture.teslacam.join("RecentClips/2024-03-03_09-00-00-front.mp4").is_file());
+    }
+    
+    #[test]
+    fn delete_after_copy_keeps_events_changed_since_they_were_archived() {
+        let fixture = Fixture::new(&[
+            ("SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-front.mp4", b"front"),
+            ("SavedClips/2024-03-01_08-00-00/2024-03-01_07-59-00-front.mp4", b"saved"),
+        ]);
+        fixture.run(None, false);
+        // Same size, so the incremental run skips it without copying.
+        fixture.add("SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-front.mp4", b"FRONT");
+        
+        let report = fixture.run(None, true);
+        
+        assert_eq!((report.files_skipped, report.events_deleted), (2, 1));
+        assert_eq!(report.failures.len(), 1);
+        assert_eq!(report.failures[0].path, format!("{}/2024-03-02_18-03-20-front.mp4", SENTRY));
+        assert!(fixture.teslacam.join(SENTRY).join("2024-03-02_18-03-20-front.mp4").is_file());
+        assert!(!fixture.teslacam.join("SavedClips/2024-03-01_08-00-00").exists());
+    }
+    
+    #[tokio::test]
+    async fn only_removes_events_still_present_at_the_destination() {
+        let fixture = Fixture::new(&[
+            ("SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-front.mp4", b"front"),
+            ("SavedClips/2024-03-01_08-00-00/2024-03-01_07-59-00-front.mp4", b"saved"),
+        ]);
+        fixture.run(None, false);
+        // Pruned from the NAS after archiving; the manifest still lists it.
+        std::fs::remove_file(fixture.destination.join(SENTRY).join("2024-03-02_18-03-20-front.mp4")).unwrap();
+        
+        let removed = remove_archived(&fixture.teslacam, &fixture.destination, &[ClipKind::Saved, ClipKind::Sentry])
+            .await
+            .unwrap();
+        
+        assert_eq!(removed, 1);
+        assert!(fixture.teslacam.join(SENTRY).is_dir());
+        assert!(!fixture.teslacam.join("SavedClips/2024-03-01_08-00-00").exists());
+    }
+}
\ No newline at end of file
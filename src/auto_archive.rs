@@ -0,0 +1,262 @@
+use crate::UsbDevice;
+use crate::archive::{ArchiveOptions, ArchiveReport};
+use crate::clips::ClipKind;
+use crate::command::CommandRunner;
+use crate::progress::Progress;
+use crate::usb::DeviceIdentity;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+// Event the UI listens on for archive-on-insert runs.
+pub const AUTO_ARCHIVE_EVENT: &str = "auto-archive";
+
+const POLICY_FILE: &str = "auto-archive.json";
+const POLL_INTERVAL: Duration = Duration::from_secs(3);
+// Polls to wait for the OS to mount a newly inserted drive.
+const MOUNT_ATTEMPTS: u32 = 10;
+
+static WATCHING: AtomicBool = AtomicBool::new(false);
+
+// What to do when a particular drive, identified by its serial number, is
+// plugged in.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct DrivePolicy {
+    pub serial: String,
+    #[serde(default)]
+    pub name: Option<String>,
+    pub enabled: bool,
+    pub destination: String,
+    // Remove SavedClips and SentryClips events once they are archived.
+    // RecentClips are left for the car to recycle.
+    #[serde(default)]
+    pub free_space: bool,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct PolicyFile {
+    drives: Vec<DrivePolicy>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AutoArchiveState {
+    Started,
+    Finished,
+    Failed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoArchiveStatus {
+    pub device_path: String,
+    pub serial: String,
+    pub state: AutoArchiveState,
+    pub report: Option<ArchiveReport>,
+    pub removed_events: usize,
+    pub error: Option<String>,
+}
+
+pub fn load_policies(config_dir: &Path) -> Result<Vec<DrivePolicy>> {
+    match std::fs::read_to_string(config_dir.join(POLICY_FILE)) {
+        Ok(text) => Ok(serde_json::from_str::<PolicyFile>(&text)?.drives),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Vec::new()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Adds the policy, replacing any earlier one for the same drive.
+pub fn save_policy(config_dir: &Path, policy: DrivePolicy) -> Result<()> {
+    if policy.serial.trim().is_empty() {
+        return Err(anyhow::anyhow!("Drives without a serial number can't be recognised on insert"));
+    }
+    if policy.destination.trim().is_empty() {
+        return Err(anyhow::anyhow!("An archive destination is required"));
+    }
+    
+    let mut drives = load_policies(config_dir)?;
+    match drives.iter_mut().find(|d| d.serial == policy.serial) {
+        Some(existing) => *existing = policy,
+        None => drives.push(policy),
+    }
+    write_policies(config_dir, drives)
+}
+
+pub fn remove_policy(config_dir: &Path, serial: &str) -> Result<()> {
+    let mut drives = load_policies(config_dir)?;
+    drives.retain(|d| d.serial != serial);
+    write_policies(config_dir, drives)
+}
+
+fn write_policies(config_dir: &Path, drives: Vec<DrivePolicy>) -> Result<()> {
+    std::fs::create_dir_all(config_dir)?;
+    std::fs::write(config_dir.join(POLICY_FILE), serde_json::to_string_pretty(&PolicyFile { drives })?)?;
+    Ok(())
+}
+
+// Only one watcher runs however often the UI asks for it.
+pub fn claim_watcher() -> bool {
+    !WATCHING.swap(true, Ordering::SeqCst)
+}
+
+// A drive this tool prepared: it has a partition labelled TeslaCam, or the
+// marker file next to its TeslaCam folder.
+pub fn is_prepared_drive(identity: &DeviceIdentity, teslacam: &Path) -> bool {
+    let labelled = identity.layout.iter()
+        .any(|p| p.label.as_deref().is_some_and(|l| l.eq_ignore_ascii_case("TeslaCam")));
+    let marked = teslacam.parent().is_some_and(|root| root.join(crate::tesla::MARKER_FILE).is_file());
+    labelled || marked
+}
+
+// Polls for removable drives and archives each prepared drive that has an
+// enabled policy once per insertion.
+pub async fn watch(runner: &impl CommandRunner, config_dir: PathBuf, window: tauri::Window) {
+    // Attempts so far for every drive currently plugged in.
+    let mut attempts: HashMap<String, u32> = HashMap::new();
+    
+    loop {
+        if let Ok(devices) = crate::usb::list_usb_devices(runner).await {
+            attempts.retain(|path, _| devices.iter().any(|d| &d.path == path));
+            
+            for device in &devices {
+                let tries = attempts.entry(device.path.clone()).or_insert(0);
+                if *tries >= MOUNT_ATTEMPTS {
+                    continue;
+                }
+                *tries += 1;
+                
+                match match_drive(runner, &config_dir, device).await {
+                    Ok(Some((policy, teslacam))) => {
+                        *tries = MOUNT_ATTEMPTS;
+                        run_policy_for_window(&policy, device, &teslacam, &window).await;
+                    }
+                    Ok(None) => *tries = MOUNT_ATTEMPTS,
+                    // Most likely not mounted yet; look again next poll.
+                    Err(_) => {}
+                }
+            }
+        }
+        
+        tokio::time::sleep(POLL_INTERVAL).await;
+    }
+}
+
+async fn match_drive(
+    runner: &impl CommandRunner,
+    config_dir: &Path,
+    device: &UsbDevice,
+) -> Result<Option<(DrivePolicy, PathBuf)>> {
+    let identity = crate::usb::read_device_identity(runner, &device.path).await?;
+    let Some(serial) = identity.serial.as_deref() else {
+        return Ok(None);
+    };
+    let Some(policy) = load_policies(config_dir)?.into_iter().find(|p| p.serial == serial && p.enabled) else {
+        return Ok(None);
+    };
+    
+    let teslacam = crate::clips::find_teslacam_folder(runner, device).await?;
+    if !is_prepared_drive(&identity, &teslacam) {
+        return Ok(None);
+    }
+    Ok(Some((policy, teslacam)))
+}
+
+async fn run_policy_for_window(policy: &DrivePolicy, device: &UsbDevice, teslacam: &Path, window: &tauri::Window) {
+    let status = |state, report, removed_events, error| AutoArchiveStatus {
+        device_path: device.path.clone(),
+        serial: policy.serial.clone(),
+        state,
+        report,
+        removed_events,
+        error,
+    };
+    
+    let _ = window.emit(AUTO_ARCHIVE_EVENT, status(AutoArchiveState::Started, None, 0, None));
+    
+    let progress = Progress::for_window("auto-archive", window.clone());
+    let finished = match run_policy(policy, teslacam, &progress).await {
+        Ok((report, removed)) => status(AutoArchiveState::Finished, Some(report), removed, None),
+        Err(e) => status(AutoArchiveState::Failed, None, 0, Some(e.to_string())),
+    };
+    let _ = window.emit(AUTO_ARCHIVE_EVENT, finished);
+}
+
+pub async fn run_policy(policy: &DrivePolicy, teslacam: &Path, progress: &Progress) -> Result<(ArchiveReport, usize)> {
+    let options = ArchiveOptions {
+        destination: policy.destination.clone(),
+        events: None,
+        delete_after_copy: false,
+    };
+    let report = crate::archive::archive_events(teslacam, &options, progress).await?;
+    
+    let removed = if policy.free_space {
+        crate::archive::remove_archived(teslacam, Path::new(&policy.destination), &[ClipKind::Saved, ClipKind::Sentry]).await?
+    } else {
+        0
+    };
+    
+    Ok((report, removed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usb::PartitionLayout;
+    
+    fn policy(serial: &str, destination: &str) -> DrivePolicy {
+        DrivePolicy {
+            serial: serial.to_string(),
+            name: None,
+            enabled: true,
+            destination: destination.to_string(),
+            free_space: false,
+        }
+    }
+    
+    #[test]
+    fn policies_are_kept_per_serial() {
+        let dir = std::env::temp_dir().join(format!("teslausb-policies-{}", uuid::Uuid::new_v4()));
+        assert_eq!(load_policies(&dir).unwrap(), vec![]);
+        
+        save_policy(&dir, policy("AA01", "/nas/model3")).unwrap();
+        save_policy(&dir, policy("BB02", "/nas/modely")).unwrap();
+        save_policy(&dir, policy("AA01", "/nas/model3-new")).unwrap();
+        assert!(save_policy(&dir, policy(" ", "/nas")).is_err());
+        
+        let serials: Vec<(String, String)> = load_policies(&dir).unwrap().into_iter().map(|p| (p.serial, p.destination)).collect();
+        assert_eq!(serials, vec![
+            ("AA01".to_string(), "/nas/model3-new".to_string()),
+            ("BB02".to_string(), "/nas/modely".to_string()),
+        ]);
+        
+        remove_policy(&dir, "AA01").unwrap();
+        assert_eq!(load_policies(&dir).unwrap(), vec![policy("BB02", "/nas/modely")]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn recognises_prepared_drives_by_label_or_marker() {
+        let root = std::env::temp_dir().join(format!("teslausb-mount-{}", uuid::Uuid::new_v4()));
+        let teslacam = root.join("TeslaCam");
+        std::fs::create_dir_all(&teslacam).unwrap();
+        
+        let identity = |label: &str| DeviceIdentity {
+            serial: Some("AA01".to_string()),
+            size: 64 << 30,
+            layout: vec![PartitionLayout {
+                size: 64 << 30,
+                filesystem: Some("exfat".to_string()),
+                label: Some(label.to_string()),
+            }],
+        };
+        
+        assert!(is_prepared_drive(&identity("TESLACAM"), &teslacam));
+        assert!(!is_prepared_drive(&identity("Untitled"), &teslacam));
+        
+        std::fs::write(root.join(crate::tesla::MARKER_FILE), b"Prepared by Tesla USB Tool\r\n").unwrap();
+        assert!(is_prepared_drive(&identity("Untitled"), &teslacam));
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
\ No newline at end of file
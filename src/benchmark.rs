@@ -0,0 +1,403 @@
+use crate::UsbDevice;
+use crate::capacity::open_raw;
+use crate::command::CommandRunner;
+use crate::usb::DeviceIdentity;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+const MIB: u64 = 1024 * 1024;
+
+// The car writes one stream per camera in small pieces and relies on the
+// stick keeping up once its write cache is full, so every chunk is flushed
+// to the device before the next one is timed.
+const SEQUENTIAL_CHUNK: u64 = 4 * MIB;
+const STREAM_CHUNK: u64 = 512 * 1024;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum BenchmarkTarget {
+    // Scratch files on a mounted partition of the device; nothing else on
+    // the partition is touched.
+    Partition { mount_point: String },
+    // Raw writes from the start of the device. This destroys whatever is on
+    // it, so it is meant for sticks that are about to be formatted anyway.
+    Device,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BenchmarkOptions {
+    pub sequential_mb: u64,
+    pub streams: u32,
+    pub stream_mb: u64,
+}
+
+impl Default for BenchmarkOptions {
+    fn default() -> Self {
+        Self {
+            sequential_mb: 1024,
+            streams: 4,
+            stream_mb: 128,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThroughputSample {
+    pub elapsed_secs: f64,
+    pub mbps: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LatencyPercentiles {
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    pub max_ms: f64,
+}
+
+impl LatencyPercentiles {
+    pub fn from_latencies(mut latencies: Vec<Duration>) -> Self {
+        latencies.sort();
+        
+        Self {
+            p50_ms: percentile(&latencies, 50.0),
+            p95_ms: percentile(&latencies, 95.0),
+            p99_ms: percentile(&latencies, 99.0),
+            max_ms: percentile(&latencies, 100.0),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PatternResult {
+    pub bytes_written: u64,
+    pub elapsed_secs: f64,
+    pub average_mbps: f64,
+    pub initial_mbps: f64,
+    // Throughput over the second half of the run, after the stick's write
+    // cache has had a chance to fill up.
+    pub sustained_mbps: f64,
+    pub minimum_mbps: f64,
+    pub latency: LatencyPercentiles,
+    pub samples: Vec<ThroughputSample>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BenchmarkReport {
+    pub required_mbps: u32,
+    pub sequential: PatternResult,
+    pub multi_stream: PatternResult,
+    pub passed: bool,
+}
+
+pub async fn run_benchmark(
+    runner: &impl CommandRunner,
+    device: &UsbDevice,
+    expected: &DeviceIdentity,
+    target: &BenchmarkTarget,
+    options: &BenchmarkOptions,
+) -> Result<BenchmarkReport> {
+    if options.sequential_mb == 0 || options.streams == 0 || options.stream_mb == 0 {
+        return Err(anyhow::anyhow!("Benchmark sizes and stream count must be greater than zero"));
+    }
+    
+    let required_mbps = crate::tesla::get_tesla_requirements().recommended_write_speed_mbps;
+    let options = options.clone();
+    
+    let (sequential, multi_stream) = match target {
+        BenchmarkTarget::Partition { mount_point } => {
+            let mount_points = crate::tesla::get_device_mount_points(runner, device).await?;
+            if !mount_points.iter().any(|m| m == mount_point) {
+                return Err(anyhow::anyhow!("{} is not a mounted partition of {}", mount_point, device.path));
+            }
+            
+            let dir = PathBuf::from(mount_point);
+            tokio::task::spawn_blocking(move || benchmark_directory(&dir, &options)).await??
+        }
+        BenchmarkTarget::Device => {
+            let total = (options.sequential_mb + options.streams as u64 * options.stream_mb) * MIB;
+            if total > device.size {
+                return Err(anyhow::anyhow!(
+                    "Benchmark needs {} MB but {} only holds {} MB",
+                    total / MIB,
+                    device.path,
+                    device.size / MIB
+                ));
+            }
+            
+            crate::usb::verify_device_unchanged(runner, &device.path, expected).await?;
+            
+            if crate::target::is_image_file(&device.path) {
+                let path = device.path.clone();
+                tokio::task::spawn_blocking(move || benchmark_raw(&path, &options)).await??
+            } else {
+                let lock = crate::mounts::prepare_device_for_write(runner, device).await?;
+                
+                let path = device.path.clone();
+                let result = tokio::task::spawn_blocking(move || benchmark_raw(&path, &options)).await?;
+                drop(lock);
+                result?
+            }
+        }
+    };
+    
+    Ok(BenchmarkReport {
+        required_mbps,
+        passed: sequential.sustained_mbps >= required_mbps as f64
+            && multi_stream.sustained_mbps >= required_mbps as f64,
+        sequential,
+        multi_stream,
+    })
+}
+
+// Scratch files are removed again however the benchmark ends.
+struct ScratchFiles(Vec<PathBuf>);
+
+impl Drop for ScratchFiles {
+    fn drop(&mut self) {
+        for path in &self.0 {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+fn benchmark_directory(dir: &std::path::Path, options: &BenchmarkOptions) -> Result<(PatternResult, PatternResult)> {
+    let run_id = uuid::Uuid::new_v4().simple().to_string();
+    let mut scratch = ScratchFiles(Vec::new());
+    
+    let mut create = |name: String| -> Result<(File, u64)> {
+        let path = dir.join(format!(".teslausb-benchmark-{}-{}.tmp", run_id, name));
+        scratch.0.push(path.clone());
+        let file = File::create(&path)
+            .map_err(|e| anyhow::anyhow!("Failed to create {}: {}", path.display(), e))?;
+        Ok((file, 0))
+    };
+    
+    let mut sequential_files = vec![create("sequential".to_string())?];
+    let sequential = measure(&mut sequential_files, SEQUENTIAL_CHUNK, options.sequential_mb * MIB)?;
+    drop(sequential_files);
+    
+    let mut stream_files = (0..options.streams)
+        .map(|i| create(format!("camera{}", i)))
+        .collect::<Result<Vec<_>>>()?;
+    let multi_stream = measure(&mut stream_files, STREAM_CHUNK, options.stream_mb * MIB)?;
+    
+    Ok((sequential, multi_stream))
+}
+
+fn benchmark_raw(path: &str, options: &BenchmarkOptions) -> Result<(PatternResult, PatternResult)> {
+    // The uncached /dev/rdiskN node on macOS, as the capacity check uses.
+    let file = open_raw(path, true)?;
+    
+    let mut sequential_region = vec![(file.try_clone()?, 0)];
+    let sequential = measure(&mut sequential_region, SEQUENTIAL_CHUNK, options.sequential_mb * MIB)?;
+    
+    // Each simulated camera gets its own region after the sequential one.
+    let stream_bytes = options.stream_mb * MIB;
+    let mut stream_regions = (0..options.streams as u64)
+        .map(|i| Ok((file.try_clone()?, options.sequential_mb * MIB + i * stream_bytes)))
+        .collect::<Result<Vec<_>>>()?;
+    let multi_stream = measure(&mut stream_regions, STREAM_CHUNK, stream_bytes)?;
+    
+    Ok((sequential, multi_stream))
+}
+
+// Writes `bytes_per_stream` to every stream, interleaving the streams one
+// chunk at a time, and times each write together with its flush.
+fn measure(streams: &mut [(File, u64)], chunk: u64, bytes_per_stream: u64) -> Result<PatternResult> {
+    let chunk = chunk.min(bytes_per_stream);
+    let mut buffer = fill_pattern(chunk as usize);
+    let mut recorder = Recorder::new();
+    let mut written = 0;
+    
+    while written < bytes_per_stream {
+        let len = chunk.min(bytes_per_stream - written) as usize;
+        
+        for (file, base) in streams.iter_mut() {
+            // Stamp each chunk so controllers that deduplicate data still
+            // have to write it.
+            recorder.ops += 1;
+            buffer[..8].copy_from_slice(&recorder.ops.to_le_bytes());
+            
+            let started = Instant::now();
+            file.seek(SeekFrom::Start(*base + written))?;
+            file.write_all(&buffer[..len])?;
+            file.sync_data()?;
+            recorder.record(len as u64, started.elapsed());
+        }
+        
+        written += len as u64;
+    }
+    
+    Ok(recorder.finish())
+}
+
+// Incompressible filler, so compressing controllers can't inflate the result.
+fn fill_pattern(len: usize) -> Vec<u8> {
+    let mut state: u64 = 0x9E37_79B9_7F4A_7C15;
+    let mut buffer = Vec::with_capacity(len + 8);
+    
+    while buffer.len() < len.max(8) {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        buffer.extend_from_slice(&state.to_le_bytes());
+    }
+    
+    buffer.truncate(len.max(8));
+    buffer
+}
+
+const SAMPLE_WINDOW: Duration = Duration::from_secs(1);
+
+struct Recorder {
+    started: Instant,
+    ops: u64,
+    latencies: Vec<Duration>,
+    windows: Vec<(Duration, u64, Duration)>,
+    window_started: Instant,
+    window_bytes: u64,
+}
+
+impl Recorder {
+    fn new() -> Self {
+        let now = Instant::now();
+        Self {
+            started: now,
+            ops: 0,
+            latencies: Vec::new(),
+            windows: Vec::new(),
+            window_started: now,
+            window_bytes: 0,
+        }
+    }
+    
+    fn record(&mut self, bytes: u64, latency: Duration) {
+        self.latencies.push(latency);
+        self.window_bytes += bytes;
+        
+        let window = self.window_started.elapsed();
+        if window >= SAMPLE_WINDOW {
+            self.close_window(window);
+        }
+    }
+    
+    fn close_window(&mut self, window: Duration) {
+        self.windows.push((self.started.elapsed(), self.window_bytes, window));
+        self.window_started = Instant::now();
+        self.window_bytes = 0;
+    }
+    
+    fn finish(mut self) -> PatternResult {
+        if self.window_bytes > 0 {
+            let window = self.window_started.elapsed();
+            self.close_window(window);
+        }
+        summarize(self.started.elapsed(), &self.windows, self.latencies)
+    }
+}
+
+// Windows are (end of window since start, bytes written, window length).
+fn summarize(elapsed: Duration, windows: &[(Duration, u64, Duration)], latencies: Vec<Duration>) -> PatternResult {
+    let bytes_written = windows.iter().map(|(_, bytes, _)| bytes).sum();
+    let samples: Vec<ThroughputSample> = windows.iter()
+        .map(|(end, bytes, window)| ThroughputSample {
+            elapsed_secs: end.as_secs_f64(),
+            mbps: mbps(*bytes, *window),
+        })
+        .collect();
+    
+    let later = &windows[windows.len() / 2..];
+    let sustained_mbps = mbps(
+        later.iter().map(|(_, bytes, _)| bytes).sum(),
+        later.iter().map(|(_, _, window)| *window).sum(),
+    );
+    
+    PatternResult {
+        bytes_written,
+        elapsed_secs: elapsed.as_secs_f64(),
+        average_mbps: mbps(bytes_written, elapsed),
+        initial_mbps: samples.first().map(|s| s.mbps).unwrap_or(0.0),
+        sustained_mbps,
+        minimum_mbps: samples.iter().map(|s| s.mbps).reduce(f64::min).unwrap_or(0.0),
+        latency: LatencyPercentiles::from_latencies(latencies),
+        samples,
+    }
+}
+
+fn mbps(bytes: u64, duration: Duration) -> f64 {
+    let secs = duration.as_secs_f64();
+    if secs > 0.0 {
+        bytes as f64 / MIB as f64 / secs
+    } else {
+        0.0
+    }
+}
+
+// Nearest-rank percentile of already sorted latencies, in milliseconds.
+fn percentile(sorted: &[Duration], p: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+    
+    let rank = ((p / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1].as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn ms(value: u64) -> Duration {
+        Duration::from_millis(value)
+    }
+    
+    #[test]
+    fn sustained_speed_ignores_the_cached_start() {
+        // 40 MiB/s for two seconds while the cache absorbs writes, then 2 MiB/s.
+        let windows = vec![
+            (ms(1000), 40 * MIB, ms(1000)),
+            (ms(2000), 40 * MIB, ms(1000)),
+            (ms(3000), 2 * MIB, ms(1000)),
+            (ms(4000), 2 * MIB, ms(1000)),
+        ];
+        let latencies = (1..=100).map(ms).collect();
+        
+        let result = summarize(ms(4000), &windows, latencies);
+        
+        assert_eq!(result.bytes_written, 84 * MIB);
+        assert_eq!(result.average_mbps, 21.0);
+        assert_eq!(result.initial_mbps, 40.0);
+        assert_eq!(result.sustained_mbps, 2.0);
+        assert_eq!(result.minimum_mbps, 2.0);
+        assert_eq!(result.latency.p50_ms, 50.0);
+        assert_eq!(result.latency.p95_ms, 95.0);
+        assert_eq!(result.latency.p99_ms, 99.0);
+        assert_eq!(result.latency.max_ms, 100.0);
+        assert_eq!(result.samples.len(), 4);
+    }
+    
+    #[test]
+    fn writes_and_removes_scratch_files() {
+        let dir = std::env::temp_dir().join(format!("teslausb-benchmark-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir(&dir).unwrap();
+        let options = BenchmarkOptions {
+            sequential_mb: 2,
+            streams: 3,
+            stream_mb: 1,
+        };
+        
+        let (sequential, multi_stream) = benchmark_directory(&dir, &options).unwrap();
+        let leftovers = std::fs::read_dir(&dir).unwrap().count();
+        std::fs::remove_dir(&dir).unwrap();
+        
+        assert_eq!(sequential.bytes_written, 2 * MIB);
+        assert_eq!(multi_stream.bytes_written, 3 * MIB);
+        assert_eq!(leftovers, 0);
+    }
+}
\ No newline at end of file
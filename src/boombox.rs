@@ -0,0 +1,294 @@
+use crate::command::CommandRunner;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+pub const BOOMBOX_FOLDER: &str = "Boombox";
+
+// The car lists at most this many custom sounds and skips larger files.
+const MAX_SOUNDS: usize = 5;
+const MAX_FILE_BYTES: u64 = 1_000_000;
+const SOUND_EXTENSIONS: &[&str] = &["mp3", "wav"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoomboxSound {
+    pub file: String,
+    pub size: u64,
+    pub format: Option<String>,
+    pub errors: Vec<String>,
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoomboxReport {
+    pub folder: String,
+    pub sounds: Vec<BoomboxSound>,
+    // Problems with the folder as a whole, such as too many sounds.
+    pub errors: Vec<String>,
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BoomboxImport {
+    pub name: String,
+    pub folder: String,
+    pub file: String,
+    pub sound: BoomboxSound,
+    pub remaining_slots: usize,
+    pub free_bytes: u64,
+}
+
+pub fn validate_sound(file_name: &str, bytes: &[u8]) -> BoomboxSound {
+    let extension = Path::new(file_name).extension().map(|e| e.to_string_lossy().to_ascii_lowercase());
+    let mut sound = BoomboxSound {
+        file: file_name.to_string(),
+        size: bytes.len() as u64,
+        format: extension.filter(|e| SOUND_EXTENSIONS.contains(&e.as_str())),
+        errors: Vec::new(),
+        valid: false,
+    };
+    
+    match sound.format.as_deref() {
+        Some("wav") => match crate::lockchime::parse_wav(bytes) {
+            Ok(info) if info.format != crate::lockchime::FORMAT_PCM => {
+                sound.errors.push("WAV audio must be uncompressed PCM".to_string());
+            }
+            Ok(_) => {}
+            Err(e) => sound.errors.push(e.to_string()),
+        },
+        Some(_) if !is_mp3(bytes) => sound.errors.push("Not an MP3 file".to_string()),
+        Some(_) => {}
+        None => sound.errors.push("The car only plays MP3 and WAV files".to_string()),
+    }
+    if sound.size > MAX_FILE_BYTES {
+        sound.errors.push(format!("File is {} KB; the car allows {} KB", sound.size / 1000, MAX_FILE_BYTES / 1000));
+    }
+    
+    sound.valid = sound.errors.is_empty();
+    sound
+}
+
+// An ID3 tag, or an MPEG audio frame sync, at the start of the file.
+fn is_mp3(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"ID3") || (bytes.len() >= 2 && bytes[0] == 0xFF && bytes[1] & 0xE0 == 0xE0)
+}
+
+pub async fn validate_boombox_folder(folder: &Path) -> Result<BoomboxReport> {
+    let folder = folder.to_path_buf();
+    tokio::task::spawn_blocking(move || validate_folder(&folder)).await?
+}
+
+// Accepts the Boombox folder itself or the drive root that holds it.
+pub fn resolve_boombox_folder(path: &Path) -> Result<PathBuf> {
+    if path.file_name().is_some_and(|n| n.eq_ignore_ascii_case(BOOMBOX_FOLDER)) && path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    
+    let nested = path.join(BOOMBOX_FOLDER);
+    if nested.is_dir() {
+        Ok(nested)
+    } else {
+        Err(anyhow::anyhow!("{} does not contain a {} folder", path.display(), BOOMBOX_FOLDER))
+    }
+}
+
+pub async fn find_boombox_folder(runner: &impl CommandRunner, device: &crate::UsbDevice) -> Result<PathBuf> {
+    let mount_points = crate::tesla::get_device_mount_points(runner, device).await?;
+    
+    mount_points.iter()
+        .find_map(|m| resolve_boombox_folder(Path::new(m)).ok())
+        .ok_or_else(|| anyhow::anyhow!("No mounted {} folder found on {}", BOOMBOX_FOLDER, device.path))
+}
+
+fn sound_files(folder: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(folder)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?
+        .into_iter()
+        .filter(|p| p.is_file() && !p.file_name().unwrap_or_default().to_string_lossy().starts_with('.'))
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+fn validate_folder(folder: &Path) -> Result<BoomboxReport> {
+    let mut sounds = Vec::new();
+    for path in sound_files(folder)? {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        let sound = match std::fs::read(&path) {
+            Ok(bytes) => validate_sound(&file_name, &bytes),
+            Err(e) => BoomboxSound {
+                file: file_name.clone(),
+                size: 0,
+                format: None,
+                errors: vec![format!("Cannot read {}: {}", file_name, e)],
+                valid: false,
+            },
+        };
+        sounds.push(sound);
+    }
+    
+    let mut errors = Vec::new();
+    let playable = sounds.iter().filter(|s| s.format.is_some()).count();
+    if playable > MAX_SOUNDS {
+        errors.push(format!("{} sounds found; the car only offers {}", playable, MAX_SOUNDS));
+    }
+    
+    Ok(BoomboxReport {
+        folder: folder.to_string_lossy().into_owned(),
+        valid: errors.is_empty() && sounds.iter().all(|s| s.valid),
+        sounds,
+        errors,
+    })
+}
+
+pub async fn import_boombox_sound(
+    runner: &impl CommandRunner,
+    device: &crate::UsbDevice,
+    source: &Path,
+    name: Option<&str>,
+) -> Result<BoomboxImport> {
+    let source = source.to_path_buf();
+    let name = name.map(str::to_string);
+    
+    let (mut report, free_bytes) = crate::lightshow_import::import_to_device(runner, device, BOOMBOX_FOLDER, resolve_boombox_folder, move |folder, available| {
+        import_into(&source, folder, name.as_deref(), available)
+    }).await?;
+    report.free_bytes = free_bytes;
+    Ok(report)
+}
+
+// Validates the sound at `source` and copies it into `folder`, refusing
+// invalid files, duplicates, a full folder and files that don't fit in
+// `available`.
+pub fn import_into(source: &Path, folder: &Path, name: Option<&str>, available: u64) -> Result<BoomboxImport> {
+    let source_name = source.file_name().unwrap_or_default().to_string_lossy().into_owned();
+    let bytes = std::fs::read(source)?;
+    let sound = validate_sound(&source_name, &bytes);
+    if !sound.valid {
+        return Err(anyhow::anyhow!("{} can't be used as a Boombox sound: {}", source_name, sound.errors.join("; ")));
+    }
+    let extension = sound.format.clone().unwrap_or_default();
+    
+    let requested = match name {
+        Some(name) => name.to_string(),
+        None => source.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+    };
+    let name = crate::lightshow_import::sanitize_name(&requested)
+        .ok_or_else(|| anyhow::anyhow!("\"{}\" can't be used as a Boombox sound name", requested))?;
+    
+    std::fs::create_dir_all(folder)?;
+    let existing = sound_files(folder)?;
+    let mut used = 0;
+    for path in &existing {
+        let Some(format) = path.extension().map(|e| e.to_string_lossy().to_ascii_lowercase()) else {
+            continue;
+        };
+        if !SOUND_EXTENSIONS.contains(&format.as_str()) {
+            continue;
+        }
+        used += 1;
+        
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        if path.file_stem().unwrap_or_default().to_string_lossy().eq_ignore_ascii_case(&name) {
+            return Err(anyhow::anyhow!("A Boombox sound named {} is already on the drive", name));
+        }
+        if std::fs::metadata(path)?.len() == bytes.len() as u64 && std::fs::read(path)? == bytes {
+            return Err(anyhow::anyhow!("This sound is already on the drive as {}", file_name));
+        }
+    }
+    if used >= MAX_SOUNDS {
+        return Err(anyhow::anyhow!("The {} folder already holds {} sounds, the most the car offers", BOOMBOX_FOLDER, MAX_SOUNDS));
+    }
+    
+    let needed = bytes.len() as u64;
+    if needed > available {
+        return Err(anyhow::anyhow!("{} needs {} KB but only {} KB is free on the drive", name, needed.div_ceil(1000), available / 1000));
+    }
+    
+    let file = format!("{}.{}", name, extension);
+    let target = folder.join(&file);
+    if let Err(e) = std::fs::write(&target, &bytes) {
+        let _ = std::fs::remove_file(&target);
+        return Err(anyhow::anyhow!("Failed to copy {} to {}: {}", source.display(), target.display(), e));
+    }
+    
+    Ok(BoomboxImport {
+        name,
+        folder: folder.to_string_lossy().into_owned(),
+        file,
+        sound,
+        remaining_slots: MAX_SOUNDS - used - 1,
+        free_bytes: available - needed,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lightshow_import::scratch;
+    
+    fn mp3(seed: u8) -> Vec<u8> {
+        let mut bytes = b"ID3\x04\0\0\0\0\0\0\xFF\xFB\x90\x64".to_vec();
+        bytes.push(seed);
+        bytes
+    }
+    
+    #[test]
+    fn validates_sounds_in_the_folder() {
+        let folder = scratch("boombox").join(BOOMBOX_FOLDER);
+        std::fs::create_dir_all(&folder).unwrap();
+        
+        std::fs::write(folder.join("horn.mp3"), mp3(1)).unwrap();
+        std::fs::write(folder.join("fake.mp3"), b"not audio").unwrap();
+        std::fs::write(folder.join("notes.txt"), b"hello").unwrap();
+        std::fs::write(folder.join("big.mp3"), [mp3(2), vec![0; 1_000_000]].concat()).unwrap();
+        std::fs::write(folder.join("._horn.mp3"), b"resource fork").unwrap();
+        
+        let report = validate_folder(&folder).unwrap();
+        let invalid: Vec<&str> = report.sounds.iter().filter(|s| !s.valid).map(|s| s.file.as_str()).collect();
+        assert_eq!(report.sounds.len(), 4);
+        assert_eq!(invalid, vec!["big.mp3", "fake.mp3", "notes.txt"]);
+        assert!(report.errors.is_empty());
+        assert!(!report.valid);
+        
+        for i in 0..4 {
+            std::fs::write(folder.join(format!("extra{}.mp3", i)), mp3(10 + i)).unwrap();
+        }
+        assert_eq!(validate_folder(&folder).unwrap().errors.len(), 1);
+        
+        std::fs::remove_dir_all(folder.parent().unwrap()).unwrap();
+    }
+    
+    #[test]
+    fn imports_sounds_until_the_folder_is_full() {
+        let dir = scratch("boombox-import");
+        let folder = dir.join(BOOMBOX_FOLDER);
+        
+        let source = dir.join("Goat Scream.MP3");
+        std::fs::write(&source, mp3(0)).unwrap();
+        let report = import_into(&source, &folder, None, 1 << 30).unwrap();
+        assert_eq!((report.file.as_str(), report.remaining_slots), ("Goat_Scream.mp3", 4));
+        assert_eq!(std::fs::read(folder.join("Goat_Scream.mp3")).unwrap(), mp3(0));
+        
+        // Same name, or the same sound renamed, is refused.
+        assert!(import_into(&source, &folder, None, 1 << 30).is_err());
+        let error = import_into(&source, &folder, Some("Other"), 1 << 30).unwrap_err().to_string();
+        assert!(error.contains("as Goat_Scream.mp3"), "{}", error);
+        
+        for i in 1..5 {
+            std::fs::write(&source, mp3(i)).unwrap();
+            import_into(&source, &folder, Some(&format!("sound {}", i)), 1 << 30).unwrap();
+        }
+        std::fs::write(&source, mp3(9)).unwrap();
+        assert!(import_into(&source, &folder, Some("sixth"), 1 << 30).unwrap_err().to_string().contains("already holds 5"));
+        
+        let wav = dir.join("float.wav");
+        let mut header = b"RIFF\x24\0\0\0WAVEfmt \x10\0\0\0\x03\0\x01\0\x44\xAC\0\0\x10\xB1\x02\0\x04\0\x20\0data\0\0\0\0".to_vec();
+        header.truncate(44);
+        std::fs::write(&wav, header).unwrap();
+        assert!(import_into(&wav, &dir.join("empty"), None, 1 << 30).unwrap_err().to_string().contains("PCM"));
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
\ No newline at end of file
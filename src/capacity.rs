@@ -0,0 +1,425 @@
+use crate::UsbDevice;
+use crate::command::CommandRunner;
+use crate::usb::DeviceIdentity;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+const MIB: u64 = 1024 * 1024;
+pub const BLOCK_SIZE: u64 = MIB;
+const SECTOR_SIZE: usize = 512;
+const SAMPLE_BLOCKS: u64 = 256;
+// Low blocks whose power-of-two aliases the sampled check also writes, and
+// the smallest real capacity it looks for.
+const ANCHOR_BLOCKS: u64 = 4;
+const MIN_WRAP: u64 = 16 * MIB;
+const MAGIC: &[u8; 8] = b"TUSBCAP1";
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CapacityMode {
+    // Every block on the device; exact, but takes as long as filling it.
+    Full,
+    // Blocks spread across the device, plus the power-of-two aliases of the
+    // first few; catches wrap-around in minutes.
+    Sampled,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CapacityReport {
+    pub mode: CapacityMode,
+    pub reported_bytes: u64,
+    pub tested_bytes: u64,
+    pub usable_bytes: u64,
+    pub first_bad_offset: Option<u64>,
+    pub bad_blocks: u64,
+    pub passed: bool,
+}
+
+// Writes position-tagged blocks to the device, reads them back and works out
+// how much of the advertised capacity actually stores data. Like the
+// benchmark's raw mode, this destroys everything on the device.
+pub async fn check_capacity(
+    runner: &impl CommandRunner,
+    device: &UsbDevice,
+    expected: &DeviceIdentity,
+    mode: CapacityMode,
+) -> Result<CapacityReport> {
+    let size = device.size;
+    let path = device.path.clone();
+    
+    crate::usb::verify_device_unchanged(runner, &device.path, expected).await?;
+    
+    if crate::target::is_image_file(&device.path) {
+        return tokio::task::spawn_blocking(move || check_path(&path, size, mode)).await?;
+    }
+    
+    let lock = crate::mounts::prepare_device_for_write(runner, device).await?;
+    
+    let result = tokio::task::spawn_blocking(move || check_path(&path, size, mode)).await?;
+    drop(lock);
+    result
+}
+
+pub fn describe_failure(report: &CapacityReport) -> String {
+    format!(
+        "Device reports {} GB but only the first {} MB store data reliably (first bad block at offset {}). \
+         It is most likely a counterfeit stick and will corrupt recordings.",
+        report.reported_bytes / (1024 * MIB),
+        report.usable_bytes / MIB,
+        report.first_bad_offset.unwrap_or(0)
+    )
+}
+
+fn check_path(path: &str, size: u64, mode: CapacityMode) -> Result<CapacityReport> {
+    let mut device = UncachedFile(open_raw(path, true)?);
+    let offsets = block_offsets(size, mode);
+    let nonce = uuid::Uuid::new_v4().as_u64_pair().0;
+    
+    let readings = write_and_read_back(&mut device, &offsets, nonce)?;
+    Ok(evaluate(mode, size, &offsets, &readings))
+}
+
+// Opens the whole device, bypassing the OS cache where the platform has a
+// separate raw node for that.
+pub fn open_raw(path: &str, write: bool) -> Result<File> {
+    #[cfg(target_os = "windows")]
+    {
+        if !crate::target::is_image_file(path) && !path.starts_with("\\\\") {
+            return Ok(std::fs::OpenOptions::new().read(true).write(write).open(format!("\\\\.\\{}", path.trim_end_matches('\\')))?);
+        }
+    }
+    
+    // The buffered /dev/diskN node would answer reads from the cache.
+    #[cfg(target_os = "macos")]
+    {
+        if let Some(disk) = path.strip_prefix("/dev/disk") {
+            return Ok(std::fs::OpenOptions::new().read(true).write(write).open(format!("/dev/rdisk{}", disk))?);
+        }
+    }
+    
+    Ok(std::fs::OpenOptions::new().read(true).write(write).open(path)?)
+}
+
+// Flushing pushes the written blocks to the device and, on Linux, drops them
+// from the page cache, so the read-back has to come from the stick itself.
+pub struct UncachedFile(pub File);
+
+impl UncachedFile {
+    pub fn drop_cache(&self) {
+        #[cfg(target_os = "linux")]
+        {
+            use std::os::unix::io::AsRawFd;
+            unsafe {
+                libc::posix_fadvise(self.0.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED);
+            }
+        }
+    }
+}
+
+impl Read for UncachedFile {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+impl Write for UncachedFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.write(buf)
+    }
+    
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.0.sync_all()?;
+        self.drop_cache();
+        Ok(())
+    }
+}
+
+impl Seek for UncachedFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.0.seek(pos)
+    }
+}
+
+fn block_offsets(size: u64, mode: CapacityMode) -> Vec<u64> {
+    let blocks = size / BLOCK_SIZE;
+    
+    match mode {
+        CapacityMode::Full => (0..blocks).map(|b| b * BLOCK_SIZE).collect(),
+        CapacityMode::Sampled if blocks <= SAMPLE_BLOCKS => (0..blocks).map(|b| b * BLOCK_SIZE).collect(),
+        // Evenly spaced, always including the first and the last block.
+        // Counterfeits drop the address bits above their real capacity,
+        // which evenly spaced samples rarely line up with. So the first few
+        // blocks are tagged too, along with their images at every
+        // power-of-two distance: a write past the real size then lands on
+        // a block that gets read back.
+        CapacityMode::Sampled => {
+            let mut offsets: Vec<u64> = (0..SAMPLE_BLOCKS)
+                .map(|i| i * (blocks - 1) / (SAMPLE_BLOCKS - 1) * BLOCK_SIZE)
+                .collect();
+            
+            let mut distance = MIN_WRAP;
+            while distance < size {
+                offsets.extend((0..ANCHOR_BLOCKS)
+                    .map(|a| (distance + a * BLOCK_SIZE, a * BLOCK_SIZE))
+                    .filter(|(offset, _)| offset / BLOCK_SIZE < blocks)
+                    .flat_map(|(offset, anchor)| [offset, anchor]));
+                distance *= 2;
+            }
+            
+            offsets.sort_unstable();
+            offsets.dedup();
+            offsets
+        }
+    }
+}
+
+// Every sector carries the magic, the run's nonce, the offset of its block
+// and its index, followed by filler derived from all three, so a block read
+// back from the wrong place can be told apart from a damaged one.
+pub fn block_pattern(nonce: u64, offset: u64) -> Vec<u8> {
+    let mut block = vec![0u8; BLOCK_SIZE as usize];
+    
+    for (index, sector) in block.chunks_mut(SECTOR_SIZE).enumerate() {
+        sector[..8].copy_from_slice(MAGIC);
+        sector[8..16].copy_from_slice(&nonce.to_le_bytes());
+        sector[16..24].copy_from_slice(&offset.to_le_bytes());
+        sector[24..32].copy_from_slice(&(index as u64).to_le_bytes());
+        
+        let mut state = nonce ^ offset.rotate_left(17) ^ (index as u64 + 1).wrapping_mul(0x9E37_79B9_7F4A_7C15);
+        for word in sector[32..].chunks_mut(8) {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            word.copy_from_slice(&state.to_le_bytes()[..word.len()]);
+        }
+    }
+    
+    block
+}
+
+// The offset whose block this is, if it is one of ours and intact.
+fn identify_block(block: &[u8], nonce: u64) -> Option<u64> {
+    if &block[..8] != MAGIC || block[8..16] != nonce.to_le_bytes() {
+        return None;
+    }
+    
+    let offset = u64::from_le_bytes(block[16..24].try_into().ok()?);
+    (block == block_pattern(nonce, offset).as_slice()).then_some(offset)
+}
+
+// Writes every block before reading any back: on a fake stick the later
+// writes land on top of the earlier ones, which is exactly what the
+// read-back has to see.
+fn write_and_read_back<T: Read + Write + Seek>(device: &mut T, offsets: &[u64], nonce: u64) -> Result<Vec<Option<u64>>> {
+    let mut write_failed = vec![false; offsets.len()];
+    
+    for (i, &offset) in offsets.iter().enumerate() {
+        let block = block_pattern(nonce, offset);
+        let written = device.seek(SeekFrom::Start(offset)).and_then(|_| device.write_all(&block));
+        write_failed[i] = written.is_err();
+    }
+    device.flush()?;
+    
+    let mut buffer = vec![0u8; BLOCK_SIZE as usize];
+    let readings = offsets.iter()
+        .zip(write_failed)
+        .map(|(&offset, failed)| {
+            let read = device.seek(SeekFrom::Start(offset)).and_then(|_| device.read_exact(&mut buffer));
+            match (failed, read) {
+                (false, Ok(())) => identify_block(&buffer, nonce),
+                _ => None,
+            }
+        })
+        .collect();
+    
+    Ok(readings)
+}
+
+// A block is good when it reads back as one of ours that no lower offset
+// already returned. When several offsets share the same storage, the lowest
+// of them is the real one and the rest are aliases past the true capacity.
+fn evaluate(mode: CapacityMode, size: u64, offsets: &[u64], readings: &[Option<u64>]) -> CapacityReport {
+    let mut claimed = HashMap::new();
+    let good: Vec<bool> = offsets.iter()
+        .zip(readings)
+        .map(|(&offset, reading)| match reading {
+            Some(tag) => *claimed.entry(*tag).or_insert(offset) == offset,
+            None => false,
+        })
+        .collect();
+    
+    let first_bad = good.iter().position(|g| !g);
+    let usable_bytes = match first_bad {
+        Some(0) => 0,
+        Some(i) => offsets[i - 1] + BLOCK_SIZE,
+        None => size,
+    };
+    
+    CapacityReport {
+        mode,
+        reported_bytes: size,
+        tested_bytes: offsets.len() as u64 * BLOCK_SIZE,
+        usable_bytes,
+        first_bad_offset: first_bad.map(|i| offsets[i]),
+        bad_blocks: good.iter().filter(|g| !**g).count() as u64,
+        passed: first_bad.is_none(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // A stick that advertises `reported` bytes but wraps around after the
+    // storage it really has, like most counterfeits. Only written blocks
+    // are kept, and only by their tag, so sticks of hundreds of GiB fit in
+    // memory. Reads and writes are whole blocks, as in the check.
+    struct WrappingStick {
+        blocks: HashMap<u64, u64>,
+        real: u64,
+        reported: u64,
+        position: u64,
+    }
+    
+    impl WrappingStick {
+        fn new(real: u64, reported: u64) -> Self {
+            Self {
+                blocks: HashMap::new(),
+                real,
+                reported,
+                position: 0,
+            }
+        }
+        
+        fn physical_block(&self, len: usize) -> u64 {
+            assert_eq!((self.position % BLOCK_SIZE, len as u64), (0, BLOCK_SIZE));
+            self.position % self.real
+        }
+    }
+    
+    impl Read for WrappingStick {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let block = self.physical_block(buf.len());
+            match self.blocks.get(&block) {
+                Some(&tag) => buf.copy_from_slice(&block_pattern(42, tag)),
+                None => buf.fill(0),
+            }
+            self.position += buf.len() as u64;
+            Ok(buf.len())
+        }
+    }
+    
+    impl Write for WrappingStick {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let block = self.physical_block(buf.len());
+            let tag = u64::from_le_bytes(buf[16..24].try_into().unwrap());
+            self.blocks.insert(block, tag);
+            self.position += buf.len() as u64;
+            Ok(buf.len())
+        }
+        
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    impl Seek for WrappingStick {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            if let SeekFrom::Start(offset) = pos {
+                if offset < self.reported {
+                    self.position = offset;
+                }
+            }
+            Ok(self.position)
+        }
+    }
+    
+    fn run(real: u64, reported: u64, mode: CapacityMode) -> CapacityReport {
+        let mut stick = WrappingStick::new(real, reported);
+        let offsets = block_offsets(reported, mode);
+        let readings = write_and_read_back(&mut stick, &offsets, 42).unwrap();
+        evaluate(mode, reported, &offsets, &readings)
+    }
+    
+    #[test]
+    fn genuine_stick_passes() {
+        let report = run(8 * MIB, 8 * MIB, CapacityMode::Full);
+        
+        assert!(report.passed);
+        assert_eq!(report.usable_bytes, 8 * MIB);
+        assert_eq!(report.first_bad_offset, None);
+        assert_eq!(report.bad_blocks, 0);
+    }
+    
+    #[test]
+    fn full_check_finds_the_wrap_around() {
+        let report = run(16 * MIB, 64 * MIB, CapacityMode::Full);
+        
+        assert!(!report.passed);
+        assert_eq!(report.usable_bytes, 16 * MIB);
+        assert_eq!(report.first_bad_offset, Some(16 * MIB));
+        assert_eq!(report.bad_blocks, 48);
+    }
+    
+    #[test]
+    fn sampled_check_bounds_the_real_capacity() {
+        let report = run(40 * MIB, 1024 * MIB, CapacityMode::Sampled);
+        
+        assert!(!report.passed);
+        assert!(report.usable_bytes <= 40 * MIB, "{:?}", report);
+        assert!(report.usable_bytes >= 36 * MIB, "{:?}", report);
+    }
+    
+    const GIB: u64 = 1024 * MIB;
+    
+    fn assert_counterfeit_caught(real: u64, reported: u64) {
+        let report = run(real * GIB, reported * GIB, CapacityMode::Sampled);
+        
+        assert!(!report.passed, "{} GiB sold as {} GiB: {:?}", real, reported, report);
+        assert_eq!(report.first_bad_offset, Some(real * GIB));
+        assert!(report.usable_bytes <= real * GIB, "{:?}", report);
+        // Within one sample spacing of the real size.
+        assert!(report.usable_bytes > real * GIB - reported * GIB / (SAMPLE_BLOCKS - 1), "{:?}", report);
+    }
+    
+    #[test]
+    fn sampled_check_catches_16_gib_sold_as_256() {
+        assert_counterfeit_caught(16, 256);
+    }
+    
+    #[test]
+    fn sampled_check_catches_8_gib_sold_as_128() {
+        assert_counterfeit_caught(8, 128);
+    }
+    
+    #[test]
+    fn sampled_check_catches_4_gib_sold_as_64() {
+        assert_counterfeit_caught(4, 64);
+    }
+    
+    #[test]
+    fn sampled_check_catches_32_gib_sold_as_256() {
+        assert_counterfeit_caught(32, 256);
+    }
+    
+    #[test]
+    fn sampled_check_passes_a_large_genuine_stick() {
+        assert!(run(64 * GIB, 64 * GIB, CapacityMode::Sampled).passed);
+    }
+    
+    #[test]
+    fn samples_cover_both_ends_and_power_of_two_aliases() {
+        let offsets = block_offsets(1024 * MIB, CapacityMode::Sampled);
+        
+        assert_eq!(offsets[..4], [0, MIB, 2 * MIB, 3 * MIB]);
+        assert_eq!(*offsets.last().unwrap(), 1023 * MIB);
+        for distance in [16, 32, 64, 128, 256, 512] {
+            assert!(offsets.contains(&(distance * MIB + 3 * MIB)), "{}", distance);
+        }
+        assert!(offsets.windows(2).all(|w| w[0] < w[1]));
+        assert_eq!(block_offsets(3 * MIB + 5, CapacityMode::Sampled), vec![0, MIB, 2 * MIB]);
+    }
+}
\ No newline at end of file
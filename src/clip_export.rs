@@ -0,0 +1,191 @@
+use crate::clips::ClipEvent;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    Csv,
+    Json,
+    Geojson,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExportSummary {
+    pub path: String,
+    pub format: ExportFormat,
+    pub exported: usize,
+    // GeoJSON can only hold events the car recorded a location for.
+    pub skipped: usize,
+}
+
+pub async fn export_events(events: &[ClipEvent], format: ExportFormat, destination: &Path) -> Result<ExportSummary> {
+    let (contents, exported) = render(events, format)?;
+    tokio::fs::write(destination, contents)
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to write {}: {}", destination.display(), e))?;
+    
+    Ok(ExportSummary {
+        path: destination.to_string_lossy().into_owned(),
+        format,
+        exported,
+        skipped: events.len() - exported,
+    })
+}
+
+// The rendered file and how many events made it in.
+pub fn render(events: &[ClipEvent], format: ExportFormat) -> Result<(String, usize)> {
+    match format {
+        ExportFormat::Json => Ok((serde_json::to_string_pretty(events)?, events.len())),
+        ExportFormat::Csv => Ok((to_csv(events), events.len())),
+        ExportFormat::Geojson => {
+            let features: Vec<serde_json::Value> = events.iter().filter_map(to_feature).collect();
+            let count = features.len();
+            let collection = json!({
+                "type": "FeatureCollection",
+                "features": features,
+            });
+            Ok((serde_json::to_string_pretty(&collection)?, count))
+        }
+    }
+}
+
+const CSV_HEADER: &[&str] = &[
+    "id", "kind", "timestamp", "start", "end", "duration_secs", "reason", "city",
+    "latitude", "longitude", "camera", "cameras", "total_bytes",
+];
+
+fn to_csv(events: &[ClipEvent]) -> String {
+    let mut csv = CSV_HEADER.join(",");
+    csv.push_str("\r\n");
+    
+    for event in events {
+        let metadata = event.metadata.clone().unwrap_or_default();
+        let cameras: Vec<String> = event.cameras.iter().map(label).collect();
+        let row = [
+            event.id.clone(),
+            label(&event.kind),
+            event.timestamp.clone(),
+            event.start.clone(),
+            event.end.clone(),
+            event.duration_secs.to_string(),
+            metadata.reason.unwrap_or_default(),
+            metadata.city.unwrap_or_default(),
+            metadata.latitude.map(|l| l.to_string()).unwrap_or_default(),
+            metadata.longitude.map(|l| l.to_string()).unwrap_or_default(),
+            metadata.camera.unwrap_or_default(),
+            cameras.join(";"),
+            event.total_bytes.to_string(),
+        ];
+        
+        csv.push_str(&row.iter().map(|field| csv_field(field)).collect::<Vec<_>>().join(","));
+        csv.push_str("\r\n");
+    }
+    
+    csv
+}
+
+// RFC 4180: quote fields holding separators, quotes or line breaks.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\r', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+fn to_feature(event: &ClipEvent) -> Option<serde_json::Value> {
+    let metadata = event.metadata.as_ref()?;
+    let (latitude, longitude) = (metadata.latitude?, metadata.longitude?);
+    
+    Some(json!({
+        "type": "Feature",
+        // GeoJSON puts longitude first.
+        "geometry": {
+            "type": "Point",
+            "coordinates": [longitude, latitude],
+        },
+        "properties": {
+            "id": event.id,
+            "kind": event.kind,
+            "timestamp": event.timestamp,
+            "duration_secs": event.duration_secs,
+            "reason": metadata.reason,
+            "city": metadata.city,
+            "camera": metadata.camera,
+            "cameras": event.cameras,
+            "thumbnail": event.thumbnail,
+        },
+    }))
+}
+
+// The name the value serializes to, so CSV matches the JSON export.
+fn label<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clips::{parse_event_metadata, Camera, ClipKind};
+    
+    fn event(id: &str, metadata: Option<&str>) -> ClipEvent {
+        ClipEvent {
+            id: id.to_string(),
+            kind: ClipKind::Sentry,
+            folder: Some(id.to_string()),
+            timestamp: "2024-03-02T18:04:11".to_string(),
+            start: "2024-03-02T17:54:20".to_string(),
+            end: "2024-03-02T18:04:20".to_string(),
+            duration_secs: 600,
+            cameras: vec![Camera::Front, Camera::LeftRepeater],
+            total_bytes: 1024,
+            thumbnail: None,
+            metadata: metadata.map(|m| parse_event_metadata(m).unwrap()),
+            segments: Vec::new(),
+        }
+    }
+    
+    #[test]
+    fn csv_quotes_fields_that_need_it() {
+        let events = [
+            event("SentryClips/a", Some(r#"{"city":"Washington, \"DC\"","est_lat":"38.9","est_lon":"-77.03","reason":"user_interaction_honk","camera":"0"}"#)),
+            event("SentryClips/b", None),
+        ];
+        
+        let (csv, count) = render(&events, ExportFormat::Csv).unwrap();
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        
+        assert_eq!(count, 2);
+        assert_eq!(lines[0], CSV_HEADER.join(","));
+        assert_eq!(
+            lines[1],
+            "SentryClips/a,sentry,2024-03-02T18:04:11,2024-03-02T17:54:20,2024-03-02T18:04:20,600,user_interaction_honk,\"Washington, \"\"DC\"\"\",38.9,-77.03,0,front;left_repeater,1024"
+        );
+        assert_eq!(lines[2], "SentryClips/b,sentry,2024-03-02T18:04:11,2024-03-02T17:54:20,2024-03-02T18:04:20,600,,,,,,front;left_repeater,1024");
+    }
+    
+    #[test]
+    fn geojson_only_holds_located_events() {
+        let events = [
+            event("SentryClips/a", Some(r#"{"est_lat":37.7749,"est_lon":-122.4194,"reason":"sentry_aware_object_detection"}"#)),
+            event("SentryClips/b", Some(r#"{"city":"Nowhere"}"#)),
+        ];
+        
+        let (geojson, count) = render(&events, ExportFormat::Geojson).unwrap();
+        let value: serde_json::Value = serde_json::from_str(&geojson).unwrap();
+        
+        assert_eq!(count, 1);
+        assert_eq!(value["type"], "FeatureCollection");
+        let feature = &value["features"][0];
+        assert_eq!(feature["geometry"]["coordinates"], json!([-122.4194, 37.7749]));
+        assert_eq!(feature["properties"]["id"], "SentryClips/a");
+        assert_eq!(feature["properties"]["kind"], "sentry");
+        assert_eq!(feature["properties"]["cameras"], json!(["front", "left_repeater"]));
+    }
+}
\ No newline at end of file
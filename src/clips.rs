@@ -0,0 +1,607 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+// The car writes one file per camera for every minute of footage.
+const SEGMENT_SECS: i64 = 60;
+// Recent clips further apart than this belong to separate drives.
+const MAX_GAP_SECS: i64 = SEGMENT_SECS + 30;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipKind {
+    Recent,
+    Saved,
+    Sentry,
+}
+
+impl ClipKind {
+    pub fn folder(self) -> &'static str {
+        match self {
+            ClipKind::Recent => "RecentClips",
+            ClipKind::Saved => "SavedClips",
+            ClipKind::Sentry => "SentryClips",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Camera {
+    Front,
+    Back,
+    LeftRepeater,
+    RightRepeater,
+    LeftPillar,
+    RightPillar,
+}
+
+impl Camera {
+    fn from_suffix(suffix: &str) -> Option<Self> {
+        match suffix {
+            "front" => Some(Camera::Front),
+            "back" => Some(Camera::Back),
+            "left_repeater" => Some(Camera::LeftRepeater),
+            "right_repeater" => Some(Camera::RightRepeater),
+            "left_pillar" => Some(Camera::LeftPillar),
+            "right_pillar" => Some(Camera::RightPillar),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipFile {
+    pub camera: Camera,
+    pub path: String,
+    pub size: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipSegment {
+    pub timestamp: String,
+    pub files: Vec<ClipFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipEvent {
+    // Relative to the TeslaCam folder, e.g. "SentryClips/2024-03-02_18-04-11".
+    // Recent clips have no event folder and are grouped into drives instead.
+    pub id: String,
+    pub kind: ClipKind,
+    pub folder: Option<String>,
+    pub timestamp: String,
+    pub start: String,
+    pub end: String,
+    pub duration_secs: u64,
+    pub cameras: Vec<Camera>,
+    pub total_bytes: u64,
+    pub thumbnail: Option<String>,
+    // From the event folder's event.json, when there is one and it parses.
+    pub metadata: Option<EventMetadata>,
+    pub segments: Vec<ClipSegment>,
+}
+
+// What the car records in event.json. Every field is optional: older
+// firmware leaves some out, and coordinates have been written both as
+// strings and as numbers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EventMetadata {
+    pub timestamp: Option<String>,
+    pub city: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    // e.g. "sentry_aware_object_detection", "user_interaction_honk"
+    pub reason: Option<String>,
+    pub camera: Option<String>,
+    // Fields newer firmware added, kept as written.
+    pub extra: BTreeMap<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipIndex {
+    pub root: String,
+    pub events: Vec<ClipEvent>,
+    // Files in the clip folders that don't follow the car's naming.
+    pub unrecognized: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ClipFilter {
+    pub kind: Option<ClipKind>,
+    // "YYYY-MM-DD"
+    pub date: Option<String>,
+    pub min_duration_secs: Option<u64>,
+    // Events without metadata never match these.
+    pub reason: Option<String>,
+    pub city: Option<String>,
+    pub near: Option<GeoRadius>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GeoRadius {
+    pub latitude: f64,
+    pub longitude: f64,
+    pub radius_km: f64,
+}
+
+impl ClipFilter {
+    fn matches(&self, event: &ClipEvent) -> bool {
+        let metadata = event.metadata.as_ref();
+        
+        self.kind.is_none_or(|kind| event.kind == kind)
+            && self.date.as_ref().is_none_or(|date| event.timestamp.starts_with(date.as_str()))
+            && self.min_duration_secs.is_none_or(|min| event.duration_secs >= min)
+            && self.reason.as_ref().is_none_or(|reason| {
+                metadata.and_then(|m| m.reason.as_ref()).is_some_and(|r| r.eq_ignore_ascii_case(reason))
+            })
+            && self.city.as_ref().is_none_or(|city| {
+                metadata.and_then(|m| m.city.as_ref()).is_some_and(|c| c.trim().eq_ignore_ascii_case(city.trim()))
+            })
+            && self.near.as_ref().is_none_or(|near| {
+                metadata
+                    .and_then(|m| Some(distance_km(m.latitude?, m.longitude?, near.latitude, near.longitude)))
+                    .is_some_and(|d| d <= near.radius_km)
+            })
+    }
+}
+
+// Great-circle distance, which is plenty for "near home" style filters.
+fn distance_km(lat1: f64, lon1: f64, lat2: f64, lon2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let d_lat = lat2 - lat1;
+    let d_lon = (lon2 - lon1).to_radians();
+    
+    let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+    6371.0 * 2.0 * a.sqrt().min(1.0).asin()
+}
+
+const METADATA_FIELDS: &[&str] = &["timestamp", "city", "est_lat", "est_lon", "reason", "camera"];
+
+pub fn parse_event_metadata(text: &str) -> Result<EventMetadata> {
+    let value: serde_json::Value = serde_json::from_str(text)?;
+    let object = value.as_object().ok_or_else(|| anyhow::anyhow!("event.json is not a JSON object"))?;
+    
+    let text = |key: &str| match object.get(key) {
+        Some(serde_json::Value::String(s)) if !s.trim().is_empty() => Some(s.trim().to_string()),
+        Some(serde_json::Value::Number(n)) => Some(n.to_string()),
+        _ => None,
+    };
+    let number = |key: &str, limit: f64| {
+        match object.get(key) {
+            Some(serde_json::Value::Number(n)) => n.as_f64(),
+            Some(serde_json::Value::String(s)) => s.trim().parse::<f64>().ok(),
+            _ => None,
+        }
+        .filter(|n| n.is_finite() && n.abs() <= limit)
+    };
+    
+    Ok(EventMetadata {
+        timestamp: text("timestamp"),
+        city: text("city"),
+        latitude: number("est_lat", 90.0),
+        longitude: number("est_lon", 180.0),
+        reason: text("reason"),
+        camera: text("camera"),
+        extra: object.iter()
+            .filter(|(key, _)| !METADATA_FIELDS.contains(&key.as_str()))
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect(),
+    })
+}
+
+// A missing or corrupt event.json (the car was unplugged mid-write) only
+// costs the event its metadata.
+fn read_event_metadata(folder: &Path) -> Option<EventMetadata> {
+    let text = std::fs::read_to_string(folder.join("event.json")).ok()?;
+    parse_event_metadata(&text).ok()
+}
+
+// The TeslaCam folder on whichever of the device's partitions has one.
+pub async fn find_teslacam_folder(runner: &impl crate::command::CommandRunner, device: &crate::UsbDevice) -> Result<PathBuf> {
+    let mount_points = crate::tesla::get_device_mount_points(runner, device).await?;
+    
+    mount_points.iter()
+        .map(|m| Path::new(m).join("TeslaCam"))
+        .find(|p| p.is_dir())
+        .ok_or_else(|| anyhow::anyhow!("No mounted TeslaCam folder found on {}", device.path))
+}
+
+// Accepts the TeslaCam folder itself or the folder containing it, such as a
+// mount point or a copy on another disk.
+pub fn resolve_teslacam_folder(path: &Path) -> Result<PathBuf> {
+    if path.file_name().is_some_and(|n| n.eq_ignore_ascii_case("TeslaCam")) && path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    
+    let nested = path.join("TeslaCam");
+    if nested.is_dir() {
+        Ok(nested)
+    } else {
+        Err(anyhow::anyhow!("{} does not contain a TeslaCam folder", path.display()))
+    }
+}
+
+pub async fn index_clips(teslacam: &Path, filter: &ClipFilter) -> Result<ClipIndex> {
+    let teslacam = teslacam.to_path_buf();
+    let filter = filter.clone();
+    
+    tokio::task::spawn_blocking(move || {
+        let mut index = build_index(&teslacam)?;
+        index.events.retain(|e| filter.matches(e));
+        Ok(index)
+    }).await?
+}
+
+pub fn build_index(teslacam: &Path) -> Result<ClipIndex> {
+    let mut events = Vec::new();
+    let mut unrecognized = Vec::new();
+    
+    for kind in [ClipKind::Recent, ClipKind::Saved, ClipKind::Sentry] {
+        let dir = teslacam.join(kind.folder());
+        if !dir.is_dir() {
+            continue;
+        }
+        
+        // Saved and Sentry events get a folder each. Recent clips, and the
+        // loose files older firmware left in the other folders, are split
+        // into drives by the gaps between them.
+        let mut loose = BTreeMap::new();
+        for entry in sorted_entries(&dir)? {
+            if entry.is_dir() && kind != ClipKind::Recent {
+                let mut segments = BTreeMap::new();
+                collect_clips(teslacam, &entry, false, &mut segments, &mut unrecognized)?;
+                if !segments.is_empty() {
+                    events.push(make_event(teslacam, kind, Some(&entry), segments));
+                }
+            } else if entry.is_dir() {
+                collect_clips(teslacam, &entry, true, &mut loose, &mut unrecognized)?;
+            } else {
+                collect_file(teslacam, &entry, &mut loose, &mut unrecognized)?;
+            }
+        }
+        
+        for drive in split_drives(loose) {
+            events.push(make_event(teslacam, kind, None, drive));
+        }
+    }
+    
+    events.sort_by(|a, b| b.timestamp.cmp(&a.timestamp).then(a.kind.cmp(&b.kind)));
+    
+    Ok(ClipIndex {
+        root: teslacam.to_string_lossy().into_owned(),
+        events,
+        unrecognized,
+    })
+}
+
+type Segments = BTreeMap<i64, Vec<ClipFile>>;
+
+fn sorted_entries(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut entries = std::fs::read_dir(dir)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    entries.sort();
+    Ok(entries)
+}
+
+fn collect_clips(teslacam: &Path, dir: &Path, recursive: bool, segments: &mut Segments, unrecognized: &mut Vec<String>) -> Result<()> {
+    for entry in sorted_entries(dir)? {
+        if entry.is_dir() {
+            if recursive {
+                collect_clips(teslacam, &entry, true, segments, unrecognized)?;
+            }
+        } else {
+            collect_file(teslacam, &entry, segments, unrecognized)?;
+        }
+    }
+    Ok(())
+}
+
+fn collect_file(teslacam: &Path, path: &Path, segments: &mut Segments, unrecognized: &mut Vec<String>) -> Result<()> {
+    let name = path.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    
+    match parse_clip_name(&name) {
+        Some((timestamp, camera)) => {
+            segments.entry(timestamp).or_default().push(ClipFile {
+                camera,
+                path: relative(teslacam, path),
+                size: std::fs::metadata(path)?.len(),
+            });
+        }
+        // Metadata the car writes next to the clips.
+        None if name == "event.json" || name == "thumb.png" => {}
+        None => unrecognized.push(relative(teslacam, path)),
+    }
+    Ok(())
+}
+
+fn split_drives(segments: Segments) -> Vec<Segments> {
+    let mut drives: Vec<Segments> = Vec::new();
+    let mut previous = None;
+    
+    for (timestamp, files) in segments {
+        match (drives.last_mut(), previous) {
+            (Some(drive), Some(prev)) if timestamp - prev <= MAX_GAP_SECS => {
+                drive.insert(timestamp, files);
+            }
+            _ => drives.push(BTreeMap::from([(timestamp, files)])),
+        }
+        previous = Some(timestamp);
+    }
+    
+    drives
+}
+
+fn make_event(teslacam: &Path, kind: ClipKind, folder: Option<&Path>, segments: Segments) -> ClipEvent {
+    let first = *segments.keys().next().unwrap_or(&0);
+    let last = *segments.keys().last().unwrap_or(&0);
+    
+    // Event folders are named after the moment the event was triggered.
+    let timestamp = folder
+        .and_then(|f| f.file_name())
+        .and_then(|n| parse_timestamp(&n.to_string_lossy()))
+        .unwrap_or(first);
+    
+    let mut cameras: Vec<Camera> = segments.values().flatten().map(|f| f.camera).collect();
+    cameras.sort();
+    cameras.dedup();
+    
+    let total_bytes = segments.values().flatten().map(|f| f.size).sum();
+    let thumbnail = folder
+        .map(|f| f.join("thumb.png"))
+        .filter(|t| t.is_file())
+        .map(|t| relative(teslacam, &t));
+    
+    let id = match folder {
+        Some(f) => relative(teslacam, f),
+        None => format!("{}@{}", kind.folder(), format_timestamp(first)),
+    };
+    
+    ClipEvent {
+        id,
+        kind,
+        folder: folder.map(|f| relative(teslacam, f)),
+        timestamp: format_timestamp(timestamp),
+        start: format_timestamp(first),
+        end: format_timestamp(last + SEGMENT_SECS),
+        duration_secs: (last + SEGMENT_SECS - first) as u64,
+        cameras,
+        total_bytes,
+        thumbnail,
+        metadata: folder.and_then(read_event_metadata),
+        segments: segments.into_iter()
+            .map(|(timestamp, mut files)| {
+                files.sort_by_key(|f| f.camera);
+                ClipSegment {
+                    timestamp: format_timestamp(timestamp),
+                    files,
+                }
+            })
+            .collect(),
+    }
+}
+
+// Forward slashes on every platform, so ids are stable across machines.
+fn relative(teslacam: &Path, path: &Path) -> String {
+    path.strip_prefix(teslacam)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+// "2024-03-02_18-04-11-left_repeater.mp4"
+fn parse_clip_name(name: &str) -> Option<(i64, Camera)> {
+    let stem = name.strip_suffix(".mp4").or_else(|| name.strip_suffix(".MP4"))?;
+    let timestamp = parse_timestamp(stem.get(..19)?)?;
+    let camera = Camera::from_suffix(stem.get(19..)?.strip_prefix('-')?)?;
+    Some((timestamp, camera))
+}
+
+// "2024-03-02_18-04-11" as seconds since 1970. The car writes local time
+// without a zone, so these are only compared with each other.
+pub fn parse_timestamp(text: &str) -> Option<i64> {
+    let bytes = text.as_bytes();
+    if bytes.len() != 19 || bytes[4] != b'-' || bytes[7] != b'-' || bytes[10] != b'_' || bytes[13] != b'-' || bytes[16] != b'-' {
+        return None;
+    }
+    
+    let field = |range: std::ops::Range<usize>| text.get(range)?.parse::<i64>().ok();
+    let (year, month, day) = (field(0..4)?, field(5..7)?, field(8..10)?);
+    let (hour, minute, second) = (field(11..13)?, field(14..16)?, field(17..19)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 59 {
+        return None;
+    }
+    
+    Some(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second)
+}
+
+// ISO 8601 without a zone: "2024-03-02T18:04:11".
+pub fn format_timestamp(secs: i64) -> String {
+    let (year, month, day) = crate::volume::civil_from_days(secs.div_euclid(86400));
+    let time = secs.rem_euclid(86400);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// Howard Hinnant's days_from_civil, the inverse of
+// volume::civil_from_days.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    // Lays out a TeslaCam folder with empty clip files.
+    fn teslacam(files: &[&str]) -> PathBuf {
+        let root = std::env::temp_dir()
+            .join(format!("teslausb-clips-{}", uuid::Uuid::new_v4()))
+            .join("TeslaCam");
+        for file in files {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, b"clip").unwrap();
+        }
+        root
+    }
+    
+    fn cleanup(root: &Path) {
+        std::fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+    
+    #[test]
+    fn parses_clip_names_and_timestamps() {
+        let (secs, camera) = parse_clip_name("2024-03-02_18-04-11-left_repeater.mp4").unwrap();
+        
+        assert_eq!(camera, Camera::LeftRepeater);
+        assert_eq!(format_timestamp(secs), "2024-03-02T18:04:11");
+        assert_eq!(parse_timestamp("1970-01-01_00-01-00"), Some(60));
+        assert_eq!(parse_clip_name("2024-03-02_18-04-11-rear.mp4"), None);
+        assert_eq!(parse_clip_name("2024-03-02_18-04-11-front.mov"), None);
+        assert_eq!(parse_timestamp("2024-13-02_18-04-11"), None);
+    }
+    
+    #[test]
+    fn groups_event_folders_and_recent_drives() {
+        let root = teslacam(&[
+            "SentryClips/2024-03-02_18-04-11/2024-03-02_17-54-20-front.mp4",
+            "SentryClips/2024-03-02_18-04-11/2024-03-02_17-54-20-back.mp4",
+            "SentryClips/2024-03-02_18-04-11/2024-03-02_17-55-20-front.mp4",
+            "SentryClips/2024-03-02_18-04-11/2024-03-02_17-55-20-left_pillar.mp4",
+            "SentryClips/2024-03-02_18-04-11/event.json",
+            "SentryClips/2024-03-02_18-04-11/thumb.png",
+            "SavedClips/2024-03-01_08-00-00/2024-03-01_07-59-00-right_repeater.mp4",
+            "RecentClips/2024-03-03_09-00-00-front.mp4",
+            "RecentClips/2024-03-03_09-01-00-front.mp4",
+            "RecentClips/2024-03-03_12-30-00-front.mp4",
+            "RecentClips/desktop.ini",
+        ]);
+        
+        let index = build_index(&root).unwrap();
+        cleanup(&root);
+        
+        let events: Vec<(&str, ClipKind, &str, u64)> = index.events.iter()
+            .map(|e| (e.id.as_str(), e.kind, e.timestamp.as_str(), e.duration_secs))
+            .collect();
+        assert_eq!(events, vec![
+            ("RecentClips@2024-03-03T12:30:00", ClipKind::Recent, "2024-03-03T12:30:00", 60),
+            ("RecentClips@2024-03-03T09:00:00", ClipKind::Recent, "2024-03-03T09:00:00", 120),
+            ("SentryClips/2024-03-02_18-04-11", ClipKind::Sentry, "2024-03-02T18:04:11", 120),
+            ("SavedClips/2024-03-01_08-00-00", ClipKind::Saved, "2024-03-01T08:00:00", 60),
+        ]);
+        
+        let sentry = &index.events[2];
+        assert_eq!(sentry.cameras, vec![Camera::Front, Camera::Back, Camera::LeftPillar]);
+        assert_eq!(sentry.segments.len(), 2);
+        assert_eq!(sentry.segments[0].files[1].path, "SentryClips/2024-03-02_18-04-11/2024-03-02_17-54-20-back.mp4");
+        assert_eq!(sentry.thumbnail.as_deref(), Some("SentryClips/2024-03-02_18-04-11/thumb.png"));
+        assert_eq!(sentry.total_bytes, 16);
+        assert_eq!(index.unrecognized, vec!["RecentClips/desktop.ini"]);
+    }
+    
+    #[test]
+    fn filters_by_kind_date_and_duration() {
+        let root = teslacam(&[
+            "SentryClips/2024-03-02_18-04-11/2024-03-02_17-54-20-front.mp4",
+            "SentryClips/2024-03-02_18-04-11/2024-03-02_17-55-20-front.mp4",
+            "SentryClips/2024-03-04_10-00-00/2024-03-04_09-59-00-front.mp4",
+            "SavedClips/2024-03-02_08-00-00/2024-03-02_07-59-00-front.mp4",
+        ]);
+        let index = build_index(&root).unwrap();
+        cleanup(&root);
+        
+        let filter = ClipFilter {
+            kind: Some(ClipKind::Sentry),
+            date: Some("2024-03-02".to_string()),
+            min_duration_secs: Some(90),
+            ..ClipFilter::default()
+        };
+        let matching: Vec<&str> = index.events.iter()
+            .filter(|e| filter.matches(e))
+            .map(|e| e.id.as_str())
+            .collect();
+        
+        assert_eq!(matching, vec!["SentryClips/2024-03-02_18-04-11"]);
+        assert!(ClipFilter::default().matches(&index.events[0]));
+    }
+    
+    #[test]
+    fn finds_teslacam_below_a_mount_point() {
+        let root = teslacam(&["RecentClips/2024-03-03_09-00-00-front.mp4"]);
+        
+        assert_eq!(resolve_teslacam_folder(root.parent().unwrap()).unwrap(), root);
+        assert_eq!(resolve_teslacam_folder(&root).unwrap(), root);
+        assert!(resolve_teslacam_folder(&root.join("RecentClips")).is_err());
+        cleanup(&root);
+    }
+    
+    #[test]
+    fn parses_event_metadata_across_firmware_versions() {
+        let current = parse_event_metadata(include_str!("../tests/fixtures/teslacam/event.json")).unwrap();
+        assert_eq!(current.city.as_deref(), Some("San Francisco"));
+        assert_eq!((current.latitude, current.longitude), (Some(37.7749), Some(-122.4194)));
+        assert_eq!(current.reason.as_deref(), Some("sentry_aware_object_detection"));
+        assert_eq!(current.camera.as_deref(), Some("0"));
+        assert_eq!(current.extra.get("street"), Some(&serde_json::json!("Market St")));
+        
+        // Numeric coordinates, an empty city and no camera.
+        let legacy = parse_event_metadata(include_str!("../tests/fixtures/teslacam/event_legacy.json")).unwrap();
+        assert_eq!((legacy.latitude, legacy.longitude), (Some(51.5072), Some(-0.1276)));
+        assert_eq!((legacy.city, legacy.camera), (None, None));
+        assert!(legacy.extra.is_empty());
+        
+        assert_eq!(parse_event_metadata(r#"{"est_lat":"unknown","est_lon":"999"}"#).unwrap(), EventMetadata::default());
+        assert!(parse_event_metadata("{\"timestamp\":").is_err());
+    }
+    
+    #[test]
+    fn filters_sentry_events_by_reason_and_location() {
+        let root = teslacam(&[
+            "SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-front.mp4",
+            "SentryClips/2024-03-05_21-30-00/2024-03-05_21-29-00-front.mp4",
+            "SentryClips/2024-03-06_07-00-00/2024-03-06_06-59-00-front.mp4",
+        ]);
+        std::fs::write(root.join("SentryClips/2024-03-02_18-04-11/event.json"), include_str!("../tests/fixtures/teslacam/event.json")).unwrap();
+        std::fs::write(
+            root.join("SentryClips/2024-03-05_21-30-00/event.json"),
+            r#"{"city":"Oakland","est_lat":"37.8044","est_lon":"-122.2712","reason":"user_interaction_honk"}"#,
+        ).unwrap();
+        std::fs::write(root.join("SentryClips/2024-03-06_07-00-00/event.json"), "{\"city\":\"Oak").unwrap();
+        let index = build_index(&root).unwrap();
+        cleanup(&root);
+        
+        let ids = |filter: ClipFilter| -> Vec<String> {
+            index.events.iter().filter(|e| filter.matches(e)).map(|e| e.id.clone()).collect()
+        };
+        
+        assert!(index.events[0].metadata.is_none());
+        assert_eq!(ids(ClipFilter { reason: Some("SENTRY_AWARE_OBJECT_DETECTION".to_string()), ..ClipFilter::default() }), vec!["SentryClips/2024-03-02_18-04-11"]);
+        assert_eq!(ids(ClipFilter { city: Some("oakland".to_string()), ..ClipFilter::default() }), vec!["SentryClips/2024-03-05_21-30-00"]);
+        
+        // Oakland is about 13 km from downtown San Francisco.
+        let near = |radius_km| ClipFilter {
+            near: Some(GeoRadius { latitude: 37.7749, longitude: -122.4194, radius_km }),
+            ..ClipFilter::default()
+        };
+        assert_eq!(ids(near(5.0)), vec!["SentryClips/2024-03-02_18-04-11"]);
+        assert_eq!(ids(near(20.0)), vec!["SentryClips/2024-03-05_21-30-00", "SentryClips/2024-03-02_18-04-11"]);
+    }
+}
\ No newline at end of file
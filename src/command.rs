@@ -0,0 +1,110 @@
+use anyhow::Result;
+use std::future::Future;
+use tokio::process::Command as TokioCommand;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct CommandOutput {
+    pub success: bool,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+// Every external tool (lsblk, parted, mkfs, mount, diskutil, diskpart, ...)
+// is started through a runner, so the backends can be exercised against
+// recorded tool output instead of real disks.
+pub trait CommandRunner: Send + Sync {
+    fn run(&self, program: &str, args: &[&str]) -> impl Future<Output = Result<CommandOutput>> + Send;
+}
+
+pub struct SystemRunner;
+
+impl CommandRunner for SystemRunner {
+    async fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput> {
+        let mut command = TokioCommand::new(program);
+        command.args(args);
+        
+        #[cfg(target_os = "windows")]
+        {
+            command.creation_flags(0x08000000); // CREATE_NO_WINDOW
+        }
+        
+        let output = command.output()
+            .await
+            .map_err(|e| anyhow::anyhow!("Failed to run {}: {}", program, e))?;
+        
+        Ok(CommandOutput {
+            success: output.status.success(),
+            stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+            stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+        })
+    }
+}
+
+// Replays canned output for known command lines and records every
+// invocation, so tests can assert the exact sequence a backend produced.
+// Commands without a canned response succeed with empty output.
+#[cfg(test)]
+#[derive(Default)]
+pub struct FakeRunner {
+    responses: std::sync::Mutex<Vec<(Vec<String>, CommandOutput)>>,
+    calls: std::sync::Mutex<Vec<Vec<String>>>,
+}
+
+#[cfg(test)]
+impl FakeRunner {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    
+    pub fn respond(self, command: &[&str], stdout: &str) -> Self {
+        self.push_response(command, CommandOutput {
+            success: true,
+            stdout: stdout.to_string(),
+            stderr: String::new(),
+        })
+    }
+    
+    pub fn fail(self, command: &[&str], stderr: &str) -> Self {
+        self.push_response(command, CommandOutput {
+            success: false,
+            stdout: String::new(),
+            stderr: stderr.to_string(),
+        })
+    }
+    
+    fn push_response(self, command: &[&str], output: CommandOutput) -> Self {
+        let command = command.iter().map(|s| s.to_string()).collect();
+        self.responses.lock().unwrap().push((command, output));
+        self
+    }
+    
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+    
+    // Fixtures hold the expected invocations as a JSON array of argv arrays.
+    pub fn assert_calls(&self, fixture: &str) {
+        let expected: Vec<Vec<String>> = serde_json::from_str(fixture).expect("invalid command fixture");
+        assert_eq!(self.calls(), expected);
+    }
+}
+
+#[cfg(test)]
+impl CommandRunner for FakeRunner {
+    async fn run(&self, program: &str, args: &[&str]) -> Result<CommandOutput> {
+        let mut command = vec![program.to_string()];
+        command.extend(args.iter().map(|s| s.to_string()));
+        self.calls.lock().unwrap().push(command.clone());
+        
+        // Responses are consumed in order, so the same command line can
+        // return different output on each call.
+        let mut responses = self.responses.lock().unwrap();
+        match responses.iter().position(|(c, _)| *c == command) {
+            Some(i) => Ok(responses.remove(i).1),
+            None => Ok(CommandOutput {
+                success: true,
+                ..Default::default()
+            }),
+        }
+    }
+}
\ No newline at end of file
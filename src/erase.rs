@@ -0,0 +1,275 @@
+use crate::UsbDevice;
+use crate::capacity::{open_raw, UncachedFile};
+use crate::command::CommandRunner;
+use crate::progress::Progress;
+use crate::signatures::FoundSignature;
+use crate::usb::DeviceIdentity;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{Seek, SeekFrom, Write};
+use std::time::Instant;
+
+const MIB: u64 = 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EraseMode {
+    // The first and last MiB of the disk and of every partition on it:
+    // partition tables, boot sectors and filesystem headers.
+    Quick,
+    Zero,
+    Random,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EraseOptions {
+    pub mode: EraseMode,
+    // Also TRIM the whole device afterwards, so the flash controller drops
+    // the old blocks too.
+    #[serde(default)]
+    pub discard: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ErasedRange {
+    pub offset: u64,
+    pub length: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EraseReport {
+    pub job_id: String,
+    pub mode: EraseMode,
+    pub erased_ranges: Vec<ErasedRange>,
+    pub bytes_overwritten: u64,
+    pub signatures: Vec<FoundSignature>,
+    pub discarded: bool,
+    pub discard_error: Option<String>,
+    pub elapsed_secs: f64,
+}
+
+pub async fn erase_device(
+    runner: &impl CommandRunner,
+    device: &UsbDevice,
+    expected: &DeviceIdentity,
+    options: &EraseOptions,
+    progress: &Progress,
+) -> Result<EraseReport> {
+    let size = device.size;
+    let path = device.path.clone();
+    let options = options.clone();
+    let job = progress.clone();
+    
+    crate::usb::verify_device_unchanged(runner, &device.path, expected).await?;
+    
+    let report = if crate::target::is_image_file(&device.path) {
+        tokio::task::spawn_blocking(move || erase_path(&path, true, size, &options, &job)).await??
+    } else {
+        let lock = crate::mounts::prepare_device_for_write(runner, device).await?;
+        
+        let result = tokio::task::spawn_blocking(move || erase_path(&path, false, size, &options, &job)).await?;
+        drop(lock);
+        result?
+    };
+    
+    Ok(report)
+}
+
+pub fn summarize(report: &EraseReport) -> String {
+    let mode = match report.mode {
+        EraseMode::Quick => "quick erase",
+        EraseMode::Zero => "zero-fill",
+        EraseMode::Random => "random overwrite",
+    };
+    let trim = match (&report.discard_error, report.discarded) {
+        (_, true) => ", TRIM issued".to_string(),
+        (Some(e), false) => format!(", {}", e),
+        (None, false) => String::new(),
+    };
+    
+    format!(
+        "{}: {} MB overwritten in {} range(s), {} old signature(s) removed{}",
+        mode,
+        report.bytes_overwritten / MIB,
+        report.erased_ranges.len(),
+        report.signatures.len(),
+        trim
+    )
+}
+
+fn erase_path(path: &str, is_image: bool, size: u64, options: &EraseOptions, progress: &Progress) -> Result<EraseReport> {
+    let started = Instant::now();
+    let mut file = UncachedFile(open_raw(path, true)?);
+    
+    // Found up front: the overwrite may take the partition table with it.
+    let signatures = crate::signatures::find_signatures(&mut file, size);
+    
+    let ranges = match options.mode {
+        EraseMode::Quick => quick_ranges(size, &crate::signatures::partition_extents(&mut file)),
+        EraseMode::Zero | EraseMode::Random => vec![ErasedRange { offset: 0, length: size }],
+    };
+    
+    let seed = uuid::Uuid::new_v4().as_u64_pair().0;
+    let bytes_overwritten = overwrite(&mut file, &ranges, options.mode, seed, progress)?;
+    if options.mode == EraseMode::Quick {
+        // Some, like ISO9660 or the md 0.90 superblock, lie outside the
+        // erased ranges.
+        crate::signatures::clear_signatures(&mut file, size, &signatures)?;
+    }
+    file.flush()?;
+    
+    let (discarded, discard_error) = if options.discard {
+        match discard(&file.0, is_image, size) {
+            Ok(()) => (true, None),
+            Err(e) => (false, Some(e.to_string())),
+        }
+    } else {
+        (false, None)
+    };
+    
+    Ok(EraseReport {
+        job_id: progress.job_id().to_string(),
+        mode: options.mode,
+        erased_ranges: ranges,
+        bytes_overwritten,
+        signatures,
+        discarded,
+        discard_error,
+        elapsed_secs: started.elapsed().as_secs_f64(),
+    })
+}
+
+// The first and last MiB of the disk and of each partition, clamped to the
+// disk and merged where they overlap.
+fn quick_ranges(size: u64, partitions: &[(u64, u64)]) -> Vec<ErasedRange> {
+    let mut ranges = Vec::new();
+    
+    for (offset, length) in std::iter::once((0, size)).chain(partitions.iter().copied()) {
+        let end = (offset + length).min(size);
+        if offset >= end {
+            continue;
+        }
+        ranges.push((offset, (offset + MIB).min(end)));
+        ranges.push((end.saturating_sub(MIB).max(offset), end));
+    }
+    
+    ranges.sort();
+    let mut merged: Vec<ErasedRange> = Vec::new();
+    for (start, end) in ranges {
+        match merged.last_mut() {
+            Some(last) if start <= last.offset + last.length => {
+                last.length = last.length.max(end - last.offset);
+            }
+            _ => merged.push(ErasedRange { offset: start, length: end - start }),
+        }
+    }
+    merged
+}
+
+fn overwrite<T: Write + Seek>(device: &mut T, ranges: &[ErasedRange], mode: EraseMode, seed: u64, progress: &Progress) -> Result<u64> {
+    let total: u64 = ranges.iter().map(|r| r.length).sum();
+    let mut buffer = vec![0u8; MIB as usize];
+    let mut state = seed | 1;
+    let mut done = 0;
+    
+    for range in ranges {
+        device.seek(SeekFrom::Start(range.offset))?;
+        let mut remaining = range.length;
+        
+        while remaining > 0 {
+            let len = MIB.min(remaining) as usize;
+            if mode == EraseMode::Random {
+                for word in buffer.chunks_mut(8) {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    word.copy_from_slice(&state.to_le_bytes());
+                }
+            }
+            
+            device.write_all(&buffer[..len])
+                .map_err(|e| anyhow::anyhow!("Erase failed at offset {}: {}", range.offset + range.length - remaining, e))?;
+            remaining -= len as u64;
+            done += len as u64;
+            progress.update("erase", done, total);
+        }
+    }
+    
+    Ok(done)
+}
+
+#[cfg(target_os = "linux")]
+const BLKDISCARD: u64 = 0x1277;
+
+fn discard(file: &File, is_image: bool, size: u64) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+        
+        // Image files get their blocks deallocated instead.
+        let result = if is_image {
+            unsafe {
+                libc::fallocate(
+                    file.as_raw_fd(),
+                    libc::FALLOC_FL_PUNCH_HOLE | libc::FALLOC_FL_KEEP_SIZE,
+                    0,
+                    size as libc::off_t,
+                )
+            }
+        } else {
+            let range: [u64; 2] = [0, size];
+            unsafe { libc::ioctl(file.as_raw_fd(), BLKDISCARD as _, &range) }
+        };
+        
+        if result != 0 {
+            return Err(anyhow::anyhow!("TRIM failed: {}", std::io::Error::last_os_error()));
+        }
+        Ok(())
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = (file, is_image, size);
+        Err(anyhow::anyhow!("TRIM is not supported on this platform"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    
+    fn range(offset: u64, length: u64) -> ErasedRange {
+        ErasedRange { offset, length }
+    }
+    
+    #[test]
+    fn quick_erase_covers_disk_and_partition_edges() {
+        let ranges = quick_ranges(64 * MIB, &[(MIB, 31 * MIB), (32 * MIB, 40 * MIB)]);
+        
+        assert_eq!(ranges, vec![
+            range(0, 2 * MIB),
+            range(31 * MIB, 2 * MIB),
+            range(63 * MIB, MIB),
+        ]);
+    }
+    
+    #[test]
+    fn zero_and_random_passes_overwrite_everything() {
+        let (progress, events) = Progress::recording("erase");
+        let whole = [range(0, 3 * MIB)];
+        
+        let mut disk = Cursor::new(vec![0xA5u8; 3 * MIB as usize]);
+        assert_eq!(overwrite(&mut disk, &whole, EraseMode::Zero, 1, &progress).unwrap(), 3 * MIB);
+        assert!(disk.get_ref().iter().all(|b| *b == 0));
+        
+        let mut disk = Cursor::new(vec![0u8; 3 * MIB as usize]);
+        overwrite(&mut disk, &whole, EraseMode::Random, 1, &progress).unwrap();
+        let zeros = disk.get_ref().iter().filter(|b| **b == 0).count();
+        assert!(zeros < disk.get_ref().len() / 100, "{} zero bytes", zeros);
+        
+        let last = events.lock().unwrap().last().cloned().unwrap();
+        assert_eq!((last.done, last.total), (3 * MIB, 3 * MIB));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,317 @@
+use anyhow::Result;
+use std::io::{Read, Seek, SeekFrom, Write};
+
+pub const SECTOR_SIZE: u64 = 512;
+const ENTRY_COUNT: u64 = 128;
+const ENTRY_SIZE: u64 = 128;
+const ENTRY_SECTORS: u64 = ENTRY_COUNT * ENTRY_SIZE / SECTOR_SIZE;
+const ALIGNMENT: u64 = 1024 * 1024 / SECTOR_SIZE;
+
+// Microsoft Basic Data, which is what Windows, macOS and the car expect for
+// exFAT and FAT32 partitions.
+pub const BASIC_DATA_TYPE: &str = "EBD0A0A2-B9E5-4433-87C0-68B6B72699C7";
+
+#[derive(Debug, Clone)]
+pub struct GptPartition {
+    pub name: String,
+    pub first_lba: u64,
+    pub last_lba: u64,
+}
+
+impl GptPartition {
+    pub fn offset(&self) -> u64 {
+        self.first_lba * SECTOR_SIZE
+    }
+    
+    pub fn size(&self) -> u64 {
+        (self.last_lba - self.first_lba + 1) * SECTOR_SIZE
+    }
+}
+
+// Lays out partitions of the given byte sizes back to back on 1 MiB
+// boundaries, the same alignment parted and diskpart use.
+pub fn plan_partitions(disk_size: u64, sizes: &[(String, u64)]) -> Result<Vec<GptPartition>> {
+    let total_sectors = disk_size / SECTOR_SIZE;
+    let last_usable = total_sectors.saturating_sub(ENTRY_SECTORS + 2);
+    let mut next = ALIGNMENT;
+    let mut partitions = Vec::new();
+    
+    for (name, size) in sizes {
+        let sectors = size / SECTOR_SIZE;
+        let last_lba = next + sectors - 1;
+        if sectors == 0 || last_lba > last_usable {
+            return Err(anyhow::anyhow!(
+                "Partition {} ({} bytes) does not fit on a {} byte disk",
+                name,
+                size,
+                disk_size
+            ));
+        }
+        
+        partitions.push(GptPartition {
+            name: name.clone(),
+            first_lba: next,
+            last_lba,
+        });
+        next = (last_lba + 1).div_ceil(ALIGNMENT) * ALIGNMENT;
+    }
+    
+    Ok(partitions)
+}
+
+pub fn write_gpt<T: Read + Write + Seek>(disk: &mut T, disk_size: u64, partitions: &[GptPartition]) -> Result<()> {
+    let total_sectors = disk_size / SECTOR_SIZE;
+    let disk_guid = uuid::Uuid::new_v4();
+    let type_guid = guid_bytes(&uuid::Uuid::parse_str(BASIC_DATA_TYPE)?);
+    
+    let mut entries = vec![0u8; (ENTRY_COUNT * ENTRY_SIZE) as usize];
+    for (i, partition) in partitions.iter().enumerate() {
+        let entry = &mut entries[i * ENTRY_SIZE as usize..(i + 1) * ENTRY_SIZE as usize];
+        entry[0..16].copy_from_slice(&type_guid);
+        entry[16..32].copy_from_slice(&guid_bytes(&uuid::Uuid::new_v4()));
+        entry[32..40].copy_from_slice(&partition.first_lba.to_le_bytes());
+        entry[40..48].copy_from_slice(&partition.last_lba.to_le_bytes());
+        for (j, unit) in partition.name.encode_utf16().take(36).enumerate() {
+            entry[56 + j * 2..58 + j * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+    }
+    let entries_crc = crc32(&entries);
+    
+    let backup_header_lba = total_sectors - 1;
+    let backup_entries_lba = backup_header_lba - ENTRY_SECTORS;
+    let primary = gpt_header(1, backup_header_lba, 2, total_sectors, &disk_guid, entries_crc);
+    let backup = gpt_header(backup_header_lba, 1, backup_entries_lba, total_sectors, &disk_guid, entries_crc);
+    
+    // Protective MBR so MBR-only tools see the disk as in use.
+    let mut mbr = vec![0u8; SECTOR_SIZE as usize];
+    mbr[446 + 2] = 0x02;
+    mbr[446 + 4] = 0xEE;
+    mbr[446 + 5..446 + 8].copy_from_slice(&[0xFF, 0xFF, 0xFF]);
+    mbr[446 + 8..446 + 12].copy_from_slice(&1u32.to_le_bytes());
+    mbr[446 + 12..446 + 16].copy_from_slice(&((total_sectors - 1).min(u32::MAX as u64) as u32).to_le_bytes());
+    mbr[510] = 0x55;
+    mbr[511] = 0xAA;
+    
+    write_sectors(disk, 0, &mbr)?;
+    write_sectors(disk, 1, &primary)?;
+    write_sectors(disk, 2, &entries)?;
+    write_sectors(disk, backup_entries_lba, &entries)?;
+    write_sectors(disk, backup_header_lba, &backup)?;
+    disk.flush()?;
+    
+    Ok(())
+}
+
+pub fn read_gpt<T: Read + Seek>(disk: &mut T) -> Result<Vec<GptPartition>> {
+    let mut header = vec![0u8; SECTOR_SIZE as usize];
+    disk.seek(SeekFrom::Start(SECTOR_SIZE))?;
+    disk.read_exact(&mut header)?;
+    
+    if &header[0..8] != b"EFI PART" {
+        return Err(anyhow::anyhow!("No GPT partition table found"));
+    }
+    
+    let header_size = u32::from_le_bytes(header[12..16].try_into()?) as usize;
+    if !(92..=SECTOR_SIZE as usize).contains(&header_size) {
+        return Err(anyhow::anyhow!("Invalid GPT header size: {} bytes", header_size));
+    }
+    let stored_crc = u32::from_le_bytes(header[16..20].try_into()?);
+    let mut check = header[..header_size].to_vec();
+    check[16..20].fill(0);
+    if crc32(&check) != stored_crc {
+        return Err(anyhow::anyhow!("GPT header checksum mismatch"));
+    }
+    
+    let entries_lba = u64::from_le_bytes(header[72..80].try_into()?);
+    let count = u32::from_le_bytes(header[80..84].try_into()?) as usize;
+    let entry_size = u32::from_le_bytes(header[84..88].try_into()?) as usize;
+    if !(128..=4096).contains(&entry_size) || count > 1024 {
+        return Err(anyhow::anyhow!("Unsupported GPT entry layout"));
+    }
+    
+    let mut entries = vec![0u8; count * entry_size];
+    disk.seek(SeekFrom::Start(entries_lba * SECTOR_SIZE))?;
+    disk.read_exact(&mut entries)?;
+    
+    let mut partitions = Vec::new();
+    for entry in entries.chunks(entry_size) {
+        if entry[0..16].iter().all(|b| *b == 0) {
+            continue;
+        }
+        
+        let units: Vec<u16> = entry[56..128]
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|u| *u != 0)
+            .collect();
+        
+        partitions.push(GptPartition {
+            name: String::from_utf16_lossy(&units),
+            first_lba: u64::from_le_bytes(entry[32..40].try_into()?),
+            last_lba: u64::from_le_bytes(entry[40..48].try_into()?),
+        });
+    }
+    
+    Ok(partitions)
+}
+
+// Checks both copies of the table the way firmware and fdisk do: header and
+// entry array CRCs, each header pointing at the other, matching entries, and
+// every partition 1 MiB aligned inside the usable area.
+#[cfg(test)]
+pub fn assert_valid_gpt<T: Read + Seek>(disk: &mut T, disk_size: u64) -> Vec<GptPartition> {
+    let total_sectors = disk_size / SECTOR_SIZE;
+    let read = |disk: &mut T, lba: u64, len: u64| {
+        let mut buf = vec![0u8; len as usize];
+        disk.seek(SeekFrom::Start(lba * SECTOR_SIZE)).unwrap();
+        disk.read_exact(&mut buf).unwrap();
+        buf
+    };
+    let field = |header: &[u8], pos: usize| u64::from_le_bytes(header[pos..pos + 8].try_into().unwrap());
+    
+    let mbr = read(disk, 0, SECTOR_SIZE);
+    assert_eq!((mbr[446 + 4], &mbr[510..]), (0xEE, &[0x55, 0xAA][..]), "protective MBR");
+    
+    let mut tables = Vec::new();
+    for (lba, alternate) in [(1, total_sectors - 1), (total_sectors - 1, 1)] {
+        let header = read(disk, lba, SECTOR_SIZE);
+        assert_eq!(&header[0..8], b"EFI PART", "header at LBA {}", lba);
+        let header_size = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+        assert!((92..=SECTOR_SIZE as usize).contains(&header_size), "header size at LBA {}", lba);
+        let mut check = header[..header_size].to_vec();
+        check[16..20].fill(0);
+        assert_eq!(crc32(&check).to_le_bytes(), header[16..20], "header CRC at LBA {}", lba);
+        assert_eq!((field(&header, 24), field(&header, 32)), (lba, alternate));
+        assert_eq!((field(&header, 40), field(&header, 48)), (2 + ENTRY_SECTORS, total_sectors - ENTRY_SECTORS - 2));
+        
+        let entries = read(disk, field(&header, 72), ENTRY_COUNT * ENTRY_SIZE);
+        assert_eq!(crc32(&entries).to_le_bytes(), header[88..92], "entry array CRC for LBA {}", lba);
+        tables.push((header[56..72].to_vec(), entries));
+    }
+    assert_eq!(tables[0], tables[1], "backup table differs from the primary");
+    
+    let partitions = read_gpt(disk).unwrap();
+    let mut next_free = 2 + ENTRY_SECTORS;
+    for partition in &partitions {
+        assert_eq!(partition.first_lba % ALIGNMENT, 0, "{} is not 1 MiB aligned", partition.name);
+        assert!(partition.first_lba >= next_free && partition.last_lba <= total_sectors - ENTRY_SECTORS - 2, "{:?}", partition);
+        next_free = partition.last_lba + 1;
+    }
+    partitions
+}
+
+fn gpt_header(my_lba: u64, alternate_lba: u64, entries_lba: u64, total_sectors: u64, disk_guid: &uuid::Uuid, entries_crc: u32) -> Vec<u8> {
+    let mut header = vec![0u8; SECTOR_SIZE as usize];
+    header[0..8].copy_from_slice(b"EFI PART");
+    header[8..12].copy_from_slice(&0x0001_0000u32.to_le_bytes());
+    header[12..16].copy_from_slice(&92u32.to_le_bytes());
+    header[24..32].copy_from_slice(&my_lba.to_le_bytes());
+    header[32..40].copy_from_slice(&alternate_lba.to_le_bytes());
+    header[40..48].copy_from_slice(&(2 + ENTRY_SECTORS).to_le_bytes());
+    header[48..56].copy_from_slice(&(total_sectors - ENTRY_SECTORS - 2).to_le_bytes());
+    header[56..72].copy_from_slice(&guid_bytes(disk_guid));
+    header[72..80].copy_from_slice(&entries_lba.to_le_bytes());
+    header[80..84].copy_from_slice(&(ENTRY_COUNT as u32).to_le_bytes());
+    header[84..88].copy_from_slice(&(ENTRY_SIZE as u32).to_le_bytes());
+    header[88..92].copy_from_slice(&entries_crc.to_le_bytes());
+    let crc = crc32(&header[..92]);
+    header[16..20].copy_from_slice(&crc.to_le_bytes());
+    header
+}
+
+fn write_sectors<T: Write + Seek>(disk: &mut T, lba: u64, data: &[u8]) -> Result<()> {
+    disk.seek(SeekFrom::Start(lba * SECTOR_SIZE))?;
+    disk.write_all(data)?;
+    Ok(())
+}
+
+// GUIDs are stored with the first three fields little-endian.
+fn guid_bytes(guid: &uuid::Uuid) -> [u8; 16] {
+    let (d1, d2, d3, d4) = guid.as_fields();
+    let mut bytes = [0u8; 16];
+    bytes[0..4].copy_from_slice(&d1.to_le_bytes());
+    bytes[4..6].copy_from_slice(&d2.to_le_bytes());
+    bytes[6..8].copy_from_slice(&d3.to_le_bytes());
+    bytes[8..16].copy_from_slice(d4);
+    bytes
+}
+
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    
+    const MIB: u64 = 1024 * 1024;
+    
+    #[test]
+    fn writes_matching_primary_and_backup_tables() {
+        let disk_size = 64 * MIB + 3 * SECTOR_SIZE;
+        let sizes = vec![
+            ("TeslaCam".to_string(), 20 * MIB + SECTOR_SIZE),
+            ("TeslaMusic".to_string(), 10 * MIB),
+            ("LightShow".to_string(), 30 * MIB),
+        ];
+        let layout = plan_partitions(disk_size, &sizes).unwrap();
+        let mut disk = Cursor::new(vec![0u8; disk_size as usize]);
+        
+        write_gpt(&mut disk, disk_size, &layout).unwrap();
+        
+        let partitions = assert_valid_gpt(&mut disk, disk_size);
+        let read: Vec<(String, u64, u64)> = partitions.iter().map(|p| (p.name.clone(), p.offset(), p.size())).collect();
+        assert_eq!(read, vec![
+            ("TeslaCam".to_string(), MIB, 20 * MIB + SECTOR_SIZE),
+            ("TeslaMusic".to_string(), 22 * MIB, 10 * MIB),
+            ("LightShow".to_string(), 32 * MIB, 30 * MIB),
+        ]);
+        let basic_data = guid_bytes(&uuid::Uuid::parse_str(BASIC_DATA_TYPE).unwrap());
+        assert_eq!(disk.get_ref()[2 * SECTOR_SIZE as usize..][..16], basic_data);
+    }
+    
+    #[test]
+    fn read_gpt_rejects_a_corrupt_header() {
+        let disk_size = 4 * MIB;
+        let layout = plan_partitions(disk_size, &[("Data".to_string(), MIB)]).unwrap();
+        let mut disk = Cursor::new(vec![0u8; disk_size as usize]);
+        write_gpt(&mut disk, disk_size, &layout).unwrap();
+        
+        disk.get_mut()[SECTOR_SIZE as usize + 80] ^= 1;
+        
+        assert!(read_gpt(&mut disk).is_err());
+    }
+    
+    #[test]
+    fn read_gpt_rejects_impossible_header_sizes() {
+        let disk_size = 4 * MIB;
+        let layout = plan_partitions(disk_size, &[("Data".to_string(), MIB)]).unwrap();
+        let mut disk = Cursor::new(vec![0u8; disk_size as usize]);
+        write_gpt(&mut disk, disk_size, &layout).unwrap();
+        
+        // Too short to hold the CRC field itself, and longer than the sector.
+        for header_size in [12u32, 91, 513] {
+            disk.get_mut()[SECTOR_SIZE as usize + 12..][..4].copy_from_slice(&header_size.to_le_bytes());
+            let error = read_gpt(&mut disk).unwrap_err().to_string();
+            assert!(error.contains("Invalid GPT header size"), "{}", error);
+        }
+    }
+    
+    #[test]
+    fn plan_rejects_partitions_past_the_backup_table() {
+        // The last 33 sectors hold the backup entries and header.
+        let disk_size = 4 * MIB;
+        assert!(plan_partitions(disk_size, &[("Data".to_string(), 3 * MIB - 33 * SECTOR_SIZE)]).is_ok());
+        assert!(plan_partitions(disk_size, &[("Data".to_string(), 3 * MIB - 32 * SECTOR_SIZE)]).is_err());
+        assert!(plan_partitions(disk_size, &[("Data".to_string(), 0)]).is_err());
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,393 @@
+use crate::UsbDevice;
+use crate::command::CommandRunner;
+use crate::mounts::MountedPartition;
+use crate::tesla::TeslaRequirements;
+use crate::volume::Volume;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Saved and Sentry clips are never recycled by the car; once they fill the
+// partition it stops saving new events.
+const CLIPS_FULL_FRACTION: f64 = 0.9;
+const LOW_FREE_FRACTION: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Category {
+    RecentClips,
+    SentryClips,
+    SavedClips,
+    Music,
+    LightShow,
+    Other,
+}
+
+impl Category {
+    fn of(relative: &Path) -> Self {
+        let mut parts = relative.components().map(|c| c.as_os_str().to_string_lossy().to_ascii_lowercase());
+        match (parts.next().as_deref(), parts.next().as_deref()) {
+            (Some("teslacam"), Some("recentclips")) => Category::RecentClips,
+            (Some("teslacam"), Some("sentryclips")) => Category::SentryClips,
+            (Some("teslacam"), Some("savedclips")) => Category::SavedClips,
+            (Some("music"), _) => Category::Music,
+            (Some("lightshow"), _) => Category::LightShow,
+            _ => Category::Other,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct CategoryUsage {
+    pub category: Category,
+    pub bytes: u64,
+    pub files: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionHealth {
+    pub source: String,
+    pub mount_point: String,
+    pub filesystem: String,
+    pub total_bytes: u64,
+    pub used_bytes: u64,
+    pub free_bytes: u64,
+    pub categories: Vec<CategoryUsage>,
+    pub has_teslacam: bool,
+    pub oldest_clip: Option<String>,
+    pub newest_clip: Option<String>,
+    // None when the raw partition can't be read, e.g. without root, or while
+    // it is mounted read-write and the OS itself holds the flag set.
+    pub dirty: Option<bool>,
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriveHealth {
+    pub device_path: String,
+    pub partitions: Vec<PartitionHealth>,
+    pub warnings: Vec<String>,
+}
+
+pub async fn drive_health(runner: &impl CommandRunner, device: &UsbDevice) -> Result<DriveHealth> {
+    let mounted = crate::mounts::find_mounted_partitions(runner, device).await?;
+    if mounted.is_empty() {
+        return Err(anyhow::anyhow!("No partitions of {} are mounted", device.path));
+    }
+    
+    let requirements = crate::tesla::get_tesla_requirements();
+    let mut partitions = Vec::new();
+    for partition in mounted {
+        let (total_bytes, free_bytes) = disk_space(runner, &partition.mount_point).await?;
+        let requirements = requirements.clone();
+        
+        let health = tokio::task::spawn_blocking(move || {
+            inspect_partition(partition, total_bytes, free_bytes, &requirements)
+        }).await??;
+        partitions.push(health);
+    }
+    
+    let mut warnings = Vec::new();
+    if !partitions.iter().any(|p| p.has_teslacam) {
+        warnings.push("No TeslaCam folder found; the car won't record to this drive".to_string());
+    }
+    
+    Ok(DriveHealth {
+        device_path: device.path.clone(),
+        partitions,
+        warnings,
+    })
+}
+
+fn inspect_partition(partition: MountedPartition, total_bytes: u64, free_bytes: u64, requirements: &TeslaRequirements) -> Result<PartitionHealth> {
+    let root = Path::new(&partition.mount_point);
+    let categories = usage_by_category(root)?;
+    
+    let teslacam = crate::clips::resolve_teslacam_folder(root).ok();
+    let (oldest_clip, newest_clip) = match &teslacam {
+        Some(teslacam) => {
+            let index = crate::clips::build_index(teslacam)?;
+            (
+                index.events.iter().map(|e| e.start.clone()).min(),
+                index.events.iter().map(|e| e.end.clone()).max(),
+            )
+        }
+        None => (None, None),
+    };
+    
+    let volume = crate::tesla::open_for_reading(&partition.source).and_then(|file| Volume::open(file, 0));
+    let (filesystem, dirty) = match volume {
+        Ok(mut volume) if partition.read_only => (volume.filesystem().to_string(), volume.is_dirty().ok()),
+        Ok(volume) => (volume.filesystem().to_string(), None),
+        Err(_) => (normalize_filesystem(&partition.filesystem), None),
+    };
+    
+    let mut health = PartitionHealth {
+        source: partition.source,
+        mount_point: partition.mount_point,
+        filesystem,
+        total_bytes,
+        used_bytes: total_bytes.saturating_sub(free_bytes),
+        free_bytes,
+        categories,
+        has_teslacam: teslacam.is_some(),
+        oldest_clip,
+        newest_clip,
+        dirty,
+        warnings: Vec::new(),
+    };
+    health.warnings = partition_warnings(&health, requirements, teslacam.as_deref());
+    Ok(health)
+}
+
+fn usage_by_category(root: &Path) -> Result<Vec<CategoryUsage>> {
+    let mut usage: Vec<CategoryUsage> = Vec::new();
+    let mut pending = vec![root.to_path_buf()];
+    
+    while let Some(dir) = pending.pop() {
+        // Folders the OS keeps to itself, like "System Volume Information",
+        // can't always be listed; they simply don't count.
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        
+        for entry in entries {
+            let entry = entry?;
+            let metadata = entry.metadata()?;
+            if metadata.is_dir() {
+                pending.push(entry.path());
+                continue;
+            }
+            
+            let category = Category::of(entry.path().strip_prefix(root).unwrap_or(&entry.path()));
+            match usage.iter_mut().find(|u| u.category == category) {
+                Some(u) => {
+                    u.bytes += metadata.len();
+                    u.files += 1;
+                }
+                None => usage.push(CategoryUsage {
+                    category,
+                    bytes: metadata.len(),
+                    files: 1,
+                }),
+            }
+        }
+    }
+    
+    usage.sort_by_key(|u| u.category);
+    Ok(usage)
+}
+
+fn partition_warnings(health: &PartitionHealth, requirements: &TeslaRequirements, teslacam: Option<&Path>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let share = |category: Category| {
+        let bytes = health.categories.iter().find(|u| u.category == category).map_or(0, |u| u.bytes);
+        bytes as f64 / health.total_bytes.max(1) as f64
+    };
+    
+    if health.dirty == Some(true) {
+        warnings.push(format!(
+            "{} was not cleanly unmounted; check it (chkdsk or fsck) before it goes back in the car",
+            health.mount_point
+        ));
+    }
+    if !health.filesystem.is_empty() && !requirements.supported_filesystems.contains(&health.filesystem) {
+        warnings.push(format!("{} is formatted {}, which the car can't use", health.mount_point, health.filesystem));
+    }
+    
+    if let Some(teslacam) = teslacam {
+        let root = teslacam.parent().unwrap_or(teslacam);
+        for folder in &requirements.required_folders {
+            if !root.join(folder).is_dir() {
+                warnings.push(format!("{} is missing", folder));
+            }
+        }
+        
+        if health.total_bytes < requirements.min_dashcam_size_gb as u64 * 1024 * 1024 * 1024 {
+            warnings.push(format!(
+                "TeslaCam partition is smaller than the {} GB the car requires",
+                requirements.min_dashcam_size_gb
+            ));
+        }
+        
+        let (sentry, saved) = (share(Category::SentryClips), share(Category::SavedClips));
+        if sentry >= CLIPS_FULL_FRACTION {
+            warnings.push(format!("SentryClips is {:.0}% of TeslaCam, car will stop saving", sentry * 100.0));
+        } else if saved >= CLIPS_FULL_FRACTION {
+            warnings.push(format!("SavedClips is {:.0}% of TeslaCam, car will stop saving", saved * 100.0));
+        } else if sentry + saved >= CLIPS_FULL_FRACTION {
+            warnings.push(format!(
+                "SentryClips and SavedClips are {:.0}% of TeslaCam, car will stop saving",
+                (sentry + saved) * 100.0
+            ));
+        }
+    }
+    
+    if (health.free_bytes as f64) < health.total_bytes as f64 * LOW_FREE_FRACTION {
+        warnings.push(format!(
+            "Only {} MB free on {}",
+            health.free_bytes / (1024 * 1024),
+            health.mount_point
+        ));
+    }
+    
+    warnings
+}
+
+// Mount tables name FAT32 differently on every platform.
+fn normalize_filesystem(filesystem: &str) -> String {
+    match filesystem.to_ascii_lowercase().as_str() {
+        "vfat" | "msdos" | "fat" | "fat32" => "fat32".to_string(),
+        other => other.to_string(),
+    }
+}
+
+// Total and available bytes of the filesystem mounted at `mount_point`.
+pub async fn disk_space(runner: &impl CommandRunner, mount_point: &str) -> Result<(u64, u64)> {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::ffi::OsStrExt;
+        use winapi::um::fileapi::GetDiskFreeSpaceExW;
+        use winapi::um::winnt::ULARGE_INTEGER;
+        
+        let _ = runner;
+        let wide: Vec<u16> = std::ffi::OsStr::new(mount_point)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
+        
+        unsafe {
+            let mut available: ULARGE_INTEGER = std::mem::zeroed();
+            let mut total: ULARGE_INTEGER = std::mem::zeroed();
+            if GetDiskFreeSpaceExW(wide.as_ptr(), &mut available, &mut total, std::ptr::null_mut()) == 0 {
+                return Err(anyhow::anyhow!("{}: {}", mount_point, std::io::Error::last_os_error()));
+            }
+            Ok((*total.QuadPart(), *available.QuadPart()))
+        }
+    }
+    
+    #[cfg(not(target_os = "windows"))]
+    {
+        let output = runner.run("df", &["-Pk", mount_point]).await?;
+        if !output.success {
+            return Err(anyhow::anyhow!("df failed for {}: {}", mount_point, output.stderr.trim()));
+        }
+        parse_df(&output.stdout).ok_or_else(|| anyhow::anyhow!("Unexpected df output for {}", mount_point))
+    }
+}
+
+// POSIX `df -Pk`: one header line, then
+// "Filesystem 1024-blocks Used Available Capacity Mounted-on".
+#[cfg(any(not(target_os = "windows"), test))]
+fn parse_df(output: &str) -> Option<(u64, u64)> {
+    let line = output.lines().nth(1)?;
+    let fields: Vec<&str> = line.split_whitespace().collect();
+    let total = fields.get(1)?.parse::<u64>().ok()?;
+    let available = fields.get(3)?.parse::<u64>().ok()?;
+    Some((total * 1024, available * 1024))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    
+    const GIB: u64 = 1024 * 1024 * 1024;
+    
+    #[test]
+    fn parses_posix_df_output() {
+        let output = "Filesystem     1024-blocks     Used Available Capacity Mounted on\n\
+                      /dev/sdb1         61049728 54321000   6728728      89% /media/user/Tesla Cam\n";
+        
+        assert_eq!(parse_df(output), Some((61049728 * 1024, 6728728 * 1024)));
+        assert_eq!(parse_df("Filesystem 1024-blocks Used Available Capacity Mounted on\n"), None);
+    }
+    
+    #[test]
+    fn sums_usage_and_warns_when_events_fill_teslacam() {
+        let root = std::env::temp_dir().join(format!("teslausb-health-{}", uuid::Uuid::new_v4()));
+        for (file, size) in [
+            ("TeslaCam/SentryClips/2024-03-02_18-04-11/2024-03-02_18-03-20-front.mp4", 900),
+            ("TeslaCam/SentryClips/2024-03-02_18-04-11/event.json", 20),
+            ("TeslaCam/RecentClips/2024-03-03_09-00-00-front.mp4", 50),
+            ("Music/song.mp3", 10),
+            ("TeslaUSBTool.txt", 5),
+        ] {
+            let path = root.join(file);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(&path, vec![0u8; size]).unwrap();
+        }
+        
+        let partition = MountedPartition {
+            source: "/dev/nonexistent".to_string(),
+            mount_point: root.to_string_lossy().into_owned(),
+            filesystem: "vfat".to_string(),
+            read_only: false,
+        };
+        let health = inspect_partition(partition, 1000, 15, &crate::tesla::get_tesla_requirements()).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        
+        let usage: Vec<(Category, u64, u64)> = health.categories.iter().map(|u| (u.category, u.bytes, u.files)).collect();
+        assert_eq!(usage, vec![
+            (Category::RecentClips, 50, 1),
+            (Category::SentryClips, 920, 2),
+            (Category::Music, 10, 1),
+            (Category::Other, 5, 1),
+        ]);
+        assert_eq!((health.filesystem.as_str(), health.dirty), ("fat32", None));
+        assert_eq!(health.oldest_clip.as_deref(), Some("2024-03-02T18:03:20"));
+        assert_eq!(health.newest_clip.as_deref(), Some("2024-03-03T09:01:00"));
+        assert_eq!(health.warnings, vec![
+            "TeslaCam/SavedClips is missing".to_string(),
+            "TeslaCam partition is smaller than the 32 GB the car requires".to_string(),
+            "SentryClips is 92% of TeslaCam, car will stop saving".to_string(),
+            format!("Only 0 MB free on {}", root.display()),
+        ]);
+    }
+    
+    #[test]
+    fn reads_the_dirty_flag() {
+        for filesystem in ["exfat", "fat32"] {
+            let mut disk = Cursor::new(vec![0u8; (GIB / 16) as usize]);
+            crate::volume::format_volume(&mut disk, 0, GIB / 16, filesystem, "TESLACAM").unwrap();
+            assert!(!Volume::open(&mut disk, 0).unwrap().is_dirty().unwrap(), "{}", filesystem);
+            
+            // What a car or a Windows PC leaves behind when unplugged mid-write.
+            match filesystem {
+                "exfat" => disk.get_mut()[106] |= 0x02,
+                _ => disk.get_mut()[65] |= 0x01,
+            }
+            assert!(Volume::open(&mut disk, 0).unwrap().is_dirty().unwrap(), "{}", filesystem);
+        }
+    }
+    
+    #[test]
+    fn dirty_flag_is_only_trusted_when_mounted_read_only() {
+        let mut disk = Cursor::new(vec![0u8; (GIB / 16) as usize]);
+        crate::volume::format_volume(&mut disk, 0, GIB / 16, "exfat", "TESLACAM").unwrap();
+        disk.get_mut()[106] |= 0x02;
+        let image = std::env::temp_dir().join(format!("teslausb-{}.img", uuid::Uuid::new_v4()));
+        std::fs::write(&image, disk.into_inner()).unwrap();
+        let root = std::env::temp_dir().join(format!("teslausb-health-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&root).unwrap();
+        
+        let inspect = |read_only: bool| {
+            let partition = MountedPartition {
+                source: image.to_string_lossy().into_owned(),
+                mount_point: root.to_string_lossy().into_owned(),
+                filesystem: "exfat".to_string(),
+                read_only,
+            };
+            inspect_partition(partition, GIB / 16, GIB / 32, &crate::tesla::get_tesla_requirements()).unwrap()
+        };
+        let read_write = inspect(false);
+        let read_only = inspect(true);
+        std::fs::remove_file(&image).unwrap();
+        std::fs::remove_dir_all(&root).unwrap();
+        
+        assert_eq!((read_write.filesystem.as_str(), read_write.dirty), ("exfat", None));
+        assert_eq!(read_only.dirty, Some(true));
+        assert!(read_only.warnings.iter().any(|w| w.contains("not cleanly unmounted")));
+        assert!(!read_write.warnings.iter().any(|w| w.contains("not cleanly unmounted")));
+    }
+}
\ No newline at end of file
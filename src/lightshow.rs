@@ -0,0 +1,430 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+// The limits Tesla's own validator enforces.
+const SUPPORTED_CHANNEL_COUNTS: &[u32] = &[48, 200];
+const MIN_STEP_MS: u8 = 15;
+const MAX_STEP_MS: u8 = 100;
+const MAX_DURATION_SECS: f64 = 5.0 * 60.0;
+// The car stores a show as commands issued whenever a group of channels
+// changes state, and has room for this many.
+const MEMORY_LIMIT: u32 = 681;
+
+pub const LIGHTSHOW_FOLDER: &str = "LightShow";
+pub const AUDIO_EXTENSIONS: &[&str] = &["mp3", "wav"];
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FseqHeader {
+    pub channel_data_offset: u16,
+    pub minor_version: u8,
+    pub major_version: u8,
+    pub channel_count: u32,
+    pub frame_count: u32,
+    pub step_time_ms: u8,
+    pub compression: u8,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FseqReport {
+    pub header: Option<FseqHeader>,
+    pub duration_secs: f64,
+    pub commands: u32,
+    // Share of the car's show memory; over 1.0 is rejected.
+    pub memory_usage: f64,
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightShow {
+    // The base name shared by the sequence and its audio.
+    pub name: String,
+    pub fseq: Option<String>,
+    pub audio: Option<String>,
+    pub sequence: Option<FseqReport>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    pub valid: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LightShowReport {
+    pub folder: String,
+    pub shows: Vec<LightShow>,
+}
+
+pub fn parse_fseq_header(bytes: &[u8]) -> Result<FseqHeader> {
+    if bytes.len() < 32 || &bytes[0..4] != b"PSEQ" {
+        return Err(anyhow::anyhow!("Not an FSEQ file"));
+    }
+    
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    
+    Ok(FseqHeader {
+        channel_data_offset: u16_at(4),
+        minor_version: bytes[6],
+        major_version: bytes[7],
+        channel_count: u32_at(10),
+        frame_count: u32_at(14),
+        step_time_ms: bytes[18],
+        // v2 keeps the upper bits of the block count in the high nibble.
+        compression: bytes[20] & 0x0F,
+    })
+}
+
+pub fn validate_fseq<T: Read + Seek>(file: &mut T) -> FseqReport {
+    let mut report = FseqReport {
+        header: None,
+        duration_secs: 0.0,
+        commands: 0,
+        memory_usage: 0.0,
+        errors: Vec::new(),
+    };
+    
+    let mut head = [0u8; 32];
+    let header = match file.read_exact(&mut head).map_err(anyhow::Error::from).and_then(|_| parse_fseq_header(&head)) {
+        Ok(header) => header,
+        Err(e) => {
+            report.errors.push(e.to_string());
+            return report;
+        }
+    };
+    report.header = Some(header.clone());
+    
+    if header.major_version != 2 || header.minor_version != 0 || header.channel_data_offset < 24 {
+        report.errors.push(format!(
+            "Unknown file format v{}.{}, expected FSEQ v2.0",
+            header.major_version,
+            header.minor_version
+        ));
+        return report;
+    }
+    if header.compression != 0 {
+        report.errors.push("Sequence is compressed; export it as FSEQ v2 uncompressed".to_string());
+    }
+    if !SUPPORTED_CHANNEL_COUNTS.contains(&header.channel_count) {
+        report.errors.push(format!(
+            "Expected {} channels, got {}",
+            SUPPORTED_CHANNEL_COUNTS.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(" or "),
+            header.channel_count
+        ));
+    }
+    if !(MIN_STEP_MS..=MAX_STEP_MS).contains(&header.step_time_ms) {
+        report.errors.push(format!(
+            "Expected a frame step between {} and {} ms, got {} ms",
+            MIN_STEP_MS,
+            MAX_STEP_MS,
+            header.step_time_ms
+        ));
+    }
+    if header.frame_count == 0 {
+        report.errors.push("Sequence has no frames".to_string());
+    }
+    
+    report.duration_secs = header.frame_count as f64 * header.step_time_ms as f64 / 1000.0;
+    if report.duration_secs > MAX_DURATION_SECS {
+        report.errors.push(format!(
+            "Expected total duration to be at most {} minutes, got {:.1} minutes",
+            MAX_DURATION_SECS / 60.0,
+            report.duration_secs / 60.0
+        ));
+    }
+    
+    // The command count is only meaningful for frames the car can read.
+    if !report.errors.is_empty() {
+        return report;
+    }
+    match count_commands(file, &header) {
+        Ok(commands) => {
+            report.commands = commands;
+            report.memory_usage = commands as f64 / MEMORY_LIMIT as f64;
+            if commands > MEMORY_LIMIT {
+                report.errors.push(format!(
+                    "Show uses {:.0}% of the car's light show memory ({} commands, limit {})",
+                    report.memory_usage * 100.0,
+                    commands,
+                    MEMORY_LIMIT
+                ));
+            }
+        }
+        Err(e) => report.errors.push(e.to_string()),
+    }
+    
+    report
+}
+
+// What one frame asks of the car. Each field that differs from the frame
+// before costs a command.
+#[derive(PartialEq)]
+struct FrameState {
+    lights: Vec<bool>,
+    ramps: Vec<u8>,
+    closures: Vec<u8>,
+    more_closures: Vec<u8>,
+}
+
+impl FrameState {
+    // Each frame starts with 30 light channels and 16 closure channels. Only
+    // the first 14 lights can ramp.
+    fn of(frame: &[u8]) -> Self {
+        let lights = &frame[0..30];
+        let closures: Vec<u8> = frame[30..46].iter().map(|b| (b / 32).div_ceil(2)).collect();
+        
+        FrameState {
+            lights: lights.iter().map(|b| *b > 127).collect(),
+            ramps: lights[..14].iter()
+                .map(|b| {
+                    let level = if *b > 127 { 255 - b } else { *b };
+                    (level / 13).div_ceil(2).min(3)
+                })
+                .collect(),
+            more_closures: closures[10..].to_vec(),
+            closures: closures[..10].to_vec(),
+        }
+    }
+    
+    fn commands_since(&self, previous: Option<&FrameState>) -> u32 {
+        match previous {
+            None => 4,
+            Some(prev) => {
+                (prev.lights != self.lights) as u32
+                    + (prev.ramps != self.ramps) as u32
+                    + (prev.closures != self.closures) as u32
+                    + (prev.more_closures != self.more_closures) as u32
+            }
+        }
+    }
+}
+
+fn count_commands<T: Read + Seek>(file: &mut T, header: &FseqHeader) -> Result<u32> {
+    file.seek(SeekFrom::Start(header.channel_data_offset as u64))?;
+    let mut frame = vec![0u8; header.channel_count as usize];
+    let mut previous: Option<FrameState> = None;
+    let mut commands = 0;
+    
+    for index in 0..header.frame_count {
+        file.read_exact(&mut frame).map_err(|_| {
+            anyhow::anyhow!("File is truncated: frame {} of {} is missing", index + 1, header.frame_count)
+        })?;
+        
+        let state = FrameState::of(&frame);
+        commands += state.commands_since(previous.as_ref());
+        previous = Some(state);
+    }
+    
+    Ok(commands)
+}
+
+pub async fn validate_lightshow_folder(folder: &Path) -> Result<LightShowReport> {
+    let folder = folder.to_path_buf();
+    tokio::task::spawn_blocking(move || validate_folder(&folder)).await?
+}
+
+// Accepts the LightShow folder itself or the drive root that holds it.
+pub fn resolve_lightshow_folder(path: &Path) -> Result<PathBuf> {
+    if path.file_name().is_some_and(|n| n.eq_ignore_ascii_case(LIGHTSHOW_FOLDER)) && path.is_dir() {
+        return Ok(path.to_path_buf());
+    }
+    
+    let nested = path.join(LIGHTSHOW_FOLDER);
+    if nested.is_dir() {
+        Ok(nested)
+    } else {
+        Err(anyhow::anyhow!("{} does not contain a {} folder", path.display(), LIGHTSHOW_FOLDER))
+    }
+}
+
+pub async fn find_lightshow_folder(runner: &impl crate::command::CommandRunner, device: &crate::UsbDevice) -> Result<PathBuf> {
+    let mount_points = crate::tesla::get_device_mount_points(runner, device).await?;
+    
+    mount_points.iter()
+        .find_map(|m| resolve_lightshow_folder(Path::new(m)).ok())
+        .ok_or_else(|| anyhow::anyhow!("No mounted {} folder found on {}", LIGHTSHOW_FOLDER, device.path))
+}
+
+fn validate_folder(folder: &Path) -> Result<LightShowReport> {
+    let mut shows: Vec<LightShow> = Vec::new();
+    
+    let mut entries: Vec<PathBuf> = std::fs::read_dir(folder)?
+        .map(|e| e.map(|e| e.path()))
+        .collect::<std::io::Result<_>>()?;
+    entries.sort();
+    
+    for path in entries.iter().filter(|p| p.is_file()) {
+        let (Some(stem), Some(extension)) = (path.file_stem(), path.extension()) else {
+            continue;
+        };
+        let stem = stem.to_string_lossy().into_owned();
+        let extension = extension.to_string_lossy().to_ascii_lowercase();
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+        if extension != "fseq" && !AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+            continue;
+        }
+        
+        let index = match shows.iter().position(|s| s.name == stem) {
+            Some(index) => index,
+            None => {
+                shows.push(LightShow {
+                    name: stem.clone(),
+                    fseq: None,
+                    audio: None,
+                    sequence: None,
+                    errors: Vec::new(),
+                    warnings: Vec::new(),
+                    valid: false,
+                });
+                shows.len() - 1
+            }
+        };
+        let show = &mut shows[index];
+        
+        if extension == "fseq" {
+            show.fseq = Some(file_name);
+        } else if let Some(existing) = &show.audio {
+            show.warnings.push(format!("Both {} and {} found; the car will only play one", existing, file_name));
+        } else {
+            show.audio = Some(file_name);
+        }
+    }
+    
+    for show in &mut shows {
+        match &show.fseq {
+            Some(fseq) => {
+                let report = match std::fs::File::open(folder.join(fseq)) {
+                    Ok(mut file) => validate_fseq(&mut file),
+                    Err(e) => {
+                        show.errors.push(format!("Cannot read {}: {}", fseq, e));
+                        continue;
+                    }
+                };
+                show.errors.extend(report.errors.iter().cloned());
+                show.sequence = Some(report);
+            }
+            None => show.errors.push(format!(
+                "{} has no matching {}.fseq",
+                show.audio.as_deref().unwrap_or(&show.name),
+                show.name
+            )),
+        }
+        
+        if show.fseq.is_some() && show.audio.is_none() {
+            show.warnings.push(format!("No {0}.mp3 or {0}.wav found; the show will play without music", show.name));
+        }
+        show.valid = show.errors.is_empty();
+    }
+    
+    Ok(LightShowReport {
+        folder: folder.to_string_lossy().into_owned(),
+        shows,
+    })
+}
+
+// A v2.0 uncompressed sequence with the given frames.
+#[cfg(test)]
+pub(crate) fn fseq(channels: u32, step_ms: u8, frames: &[Vec<u8>]) -> Vec<u8> {
+    let mut bytes = vec![0u8; 32];
+    bytes[0..4].copy_from_slice(b"PSEQ");
+    bytes[4..6].copy_from_slice(&32u16.to_le_bytes());
+    bytes[6] = 0;
+    bytes[7] = 2;
+    bytes[8..10].copy_from_slice(&32u16.to_le_bytes());
+    bytes[10..14].copy_from_slice(&channels.to_le_bytes());
+    bytes[14..18].copy_from_slice(&(frames.len() as u32).to_le_bytes());
+    bytes[18] = step_ms;
+    for frame in frames {
+        bytes.extend_from_slice(frame);
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    
+    fn frame(lights_on: bool) -> Vec<u8> {
+        let mut frame = vec![0u8; 48];
+        if lights_on {
+            frame[..30].fill(255);
+        }
+        frame
+    }
+    
+    #[test]
+    fn counts_commands_for_state_changes() {
+        // Off, on, on, off: four commands for the first frame, then a light
+        // state change at each toggle. Full on and full off share a ramp.
+        let show = fseq(48, 20, &[frame(false), frame(true), frame(true), frame(false)]);
+        let report = validate_fseq(&mut Cursor::new(show));
+        
+        assert_eq!(report.errors, Vec::<String>::new());
+        assert_eq!(report.commands, 6);
+        assert!((report.duration_secs - 0.08).abs() < 1e-9);
+        assert_eq!(report.header.unwrap().channel_count, 48);
+    }
+    
+    #[test]
+    fn explains_every_rejection() {
+        let mut compressed = fseq(64, 10, &[frame(false)]);
+        compressed[20] = 0x01;
+        let report = validate_fseq(&mut Cursor::new(compressed));
+        assert_eq!(report.errors, vec![
+            "Sequence is compressed; export it as FSEQ v2 uncompressed",
+            "Expected 48 or 200 channels, got 64",
+            "Expected a frame step between 15 and 100 ms, got 10 ms",
+        ]);
+        
+        let mut old = fseq(48, 50, &[frame(false)]);
+        old[7] = 1;
+        assert_eq!(validate_fseq(&mut Cursor::new(old)).errors, vec!["Unknown file format v1.0, expected FSEQ v2.0"]);
+        
+        // Six minutes at 50 ms per frame.
+        let mut long = fseq(48, 50, &[]);
+        long[14..18].copy_from_slice(&7200u32.to_le_bytes());
+        assert_eq!(
+            validate_fseq(&mut Cursor::new(long)).errors,
+            vec!["Expected total duration to be at most 5 minutes, got 6.0 minutes"]
+        );
+        
+        // A strobe: every frame toggles the lights.
+        let strobe: Vec<Vec<u8>> = (0..700).map(|i| frame(i % 2 == 1)).collect();
+        let report = validate_fseq(&mut Cursor::new(fseq(48, 20, &strobe)));
+        assert_eq!(report.commands, 703);
+        assert_eq!(report.errors, vec!["Show uses 103% of the car's light show memory (703 commands, limit 681)"]);
+        
+        let truncated = fseq(48, 20, &[frame(false)]);
+        let mut truncated = truncated[..60].to_vec();
+        truncated[14..18].copy_from_slice(&2u32.to_le_bytes());
+        assert_eq!(validate_fseq(&mut Cursor::new(truncated)).errors, vec!["File is truncated: frame 1 of 2 is missing"]);
+    }
+    
+    #[test]
+    fn pairs_sequences_with_audio() {
+        let folder = std::env::temp_dir().join(format!("teslausb-lightshow-{}", uuid::Uuid::new_v4())).join("LightShow");
+        std::fs::create_dir_all(&folder).unwrap();
+        let valid = fseq(48, 20, &[frame(false), frame(true)]);
+        for (name, contents) in [
+            ("xmas.fseq", valid.clone()),
+            ("xmas.mp3", b"ID3".to_vec()),
+            ("silent.fseq", valid),
+            ("orphan.wav", b"RIFF".to_vec()),
+            ("notes.txt", b"".to_vec()),
+        ] {
+            std::fs::write(folder.join(name), contents).unwrap();
+        }
+        
+        let report = validate_folder(&resolve_lightshow_folder(folder.parent().unwrap()).unwrap()).unwrap();
+        std::fs::remove_dir_all(folder.parent().unwrap()).unwrap();
+        
+        let shows: Vec<(&str, bool, &[String], &[String])> = report.shows.iter()
+            .map(|s| (s.name.as_str(), s.valid, s.errors.as_slice(), s.warnings.as_slice()))
+            .collect();
+        assert_eq!(shows, vec![
+            ("orphan", false, &["orphan.wav has no matching orphan.fseq".to_string()][..], &[][..]),
+            ("silent", true, &[][..], &["No silent.mp3 or silent.wav found; the show will play without music".to_string()][..]),
+            ("xmas", true, &[][..], &[][..]),
+        ]);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,426 @@
+use crate::command::CommandRunner;
+use crate::lightshow::{FseqReport, AUDIO_EXTENSIONS, LIGHTSHOW_FOLDER};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ImportReport {
+    // Base name both files were copied under.
+    pub name: String,
+    pub folder: String,
+    pub fseq: String,
+    pub audio: Option<String>,
+    pub sequence: FseqReport,
+    pub bytes_copied: u64,
+    // Space left on the partition after the copy.
+    pub free_bytes: u64,
+    pub warnings: Vec<String>,
+}
+
+// The sequence and audio found in a package, extracted to a scratch
+// folder when the package is a zip.
+struct Package {
+    fseq: PathBuf,
+    audio: Option<PathBuf>,
+    _scratch: Option<ScratchDir>,
+}
+
+struct ScratchDir(PathBuf);
+
+impl Drop for ScratchDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+pub async fn import_lightshow(
+    runner: &impl CommandRunner,
+    device: &crate::UsbDevice,
+    source: &Path,
+    name: Option<&str>,
+) -> Result<ImportReport> {
+    let source = source.to_path_buf();
+    let name = name.map(str::to_string);
+    
+    let (mut report, free_bytes) = import_to_device(runner, device, LIGHTSHOW_FOLDER, crate::lightshow::resolve_lightshow_folder, move |folder, available| {
+        import_into(&source, folder, name.as_deref(), available)
+    }).await?;
+    report.free_bytes = free_bytes;
+    Ok(report)
+}
+
+// Shared by the light show and Boombox imports: finds the destination
+// folder on the device's mounted partitions, runs `import` into it with the
+// free space there, and returns its result with the space left afterwards.
+pub async fn import_to_device<T: Send + 'static>(
+    runner: &impl CommandRunner,
+    device: &crate::UsbDevice,
+    folder_name: &str,
+    resolve: fn(&Path) -> Result<PathBuf>,
+    import: impl FnOnce(&Path, u64) -> Result<T> + Send + 'static,
+) -> Result<(T, u64)> {
+    let mount_points = crate::tesla::get_device_mount_points(runner, device).await?;
+    let (mount_point, folder) = import_destination(&mount_points, folder_name, resolve)
+        .ok_or_else(|| anyhow::anyhow!("No mounted {} or TeslaCam partition found on {}", folder_name, device.path))?;
+    
+    let (_, available) = crate::health::disk_space(runner, &mount_point).await?;
+    let result = tokio::task::spawn_blocking(move || import(&folder, available)).await??;
+    let (_, free_bytes) = crate::health::disk_space(runner, &mount_point).await?;
+    Ok((result, free_bytes))
+}
+
+// The existing folder `resolve` finds on a partition, or `folder_name` to
+// create next to TeslaCam when the drive has no partition of its own for
+// it. Returns the mount point too.
+pub fn import_destination(mount_points: &[String], folder_name: &str, resolve: fn(&Path) -> Result<PathBuf>) -> Option<(String, PathBuf)> {
+    let existing = mount_points.iter()
+        .find_map(|m| resolve(Path::new(m)).ok().map(|f| (m.clone(), f)));
+    existing.or_else(|| {
+        mount_points.iter()
+            .find(|m| Path::new(m).join("TeslaCam").is_dir())
+            .map(|m| (m.clone(), Path::new(m).join(folder_name)))
+    })
+}
+
+// Validates the package at `source` and copies it into `folder`, refusing
+// invalid sequences, duplicates and packages that don't fit in `available`.
+pub fn import_into(source: &Path, folder: &Path, name: Option<&str>, available: u64) -> Result<ImportReport> {
+    let package = open_package(source)?;
+    
+    let sequence = crate::lightshow::validate_fseq(&mut File::open(&package.fseq)?);
+    if !sequence.errors.is_empty() {
+        return Err(anyhow::anyhow!("{} is not a valid light show: {}", file_name(&package.fseq), sequence.errors.join("; ")));
+    }
+    
+    let requested = match name {
+        Some(name) => name.to_string(),
+        None => package.fseq.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+    };
+    let name = sanitize_name(&requested)
+        .ok_or_else(|| anyhow::anyhow!("\"{}\" can't be used as a light show name", requested))?;
+    
+    let mut warnings = Vec::new();
+    if package.audio.is_none() {
+        warnings.push("No audio file in the package; the show will play without music".to_string());
+    }
+    
+    std::fs::create_dir_all(folder)?;
+    check_duplicates(folder, &name, &package.fseq)?;
+    
+    let mut files = vec![(package.fseq.clone(), format!("{}.fseq", name))];
+    if let Some(audio) = &package.audio {
+        let extension = audio.extension().unwrap_or_default().to_string_lossy().to_ascii_lowercase();
+        files.push((audio.clone(), format!("{}.{}", name, extension)));
+    }
+    
+    let needed: u64 = files.iter()
+        .map(|(path, _)| std::fs::metadata(path).map(|m| m.len()))
+        .sum::<std::io::Result<u64>>()?;
+    if needed > available {
+        return Err(anyhow::anyhow!(
+            "{} needs {} MB but only {} MB is free on the drive",
+            name, needed.div_ceil(1 << 20), available / (1 << 20)
+        ));
+    }
+    
+    let mut copied: Vec<PathBuf> = Vec::new();
+    for (from, to) in &files {
+        let target = folder.join(to);
+        if let Err(e) = std::fs::copy(from, &target) {
+            // Don't leave half a show behind for the car to reject.
+            let _ = std::fs::remove_file(&target);
+            for path in &copied {
+                let _ = std::fs::remove_file(path);
+            }
+            return Err(anyhow::anyhow!("Failed to copy {} to {}: {}", from.display(), target.display(), e));
+        }
+        copied.push(target);
+    }
+    
+    Ok(ImportReport {
+        folder: folder.to_string_lossy().into_owned(),
+        fseq: files[0].1.clone(),
+        audio: files.get(1).map(|(_, to)| to.clone()),
+        sequence,
+        bytes_copied: needed,
+        free_bytes: available - needed,
+        warnings,
+        name,
+    })
+}
+
+fn open_package(source: &Path) -> Result<Package> {
+    let is_zip = source.extension().is_some_and(|e| e.eq_ignore_ascii_case("zip"));
+    
+    if source.is_dir() {
+        find_show_files(source, None)
+    } else if is_zip && source.is_file() {
+        let scratch = ScratchDir(std::env::temp_dir().join(format!("teslausb-lightshow-{}", uuid::Uuid::new_v4().simple())));
+        extract_show_files(source, &scratch.0)?;
+        let root = scratch.0.clone();
+        find_show_files(&root, Some(scratch))
+    } else {
+        Err(anyhow::anyhow!("{} is neither a folder nor a .zip file", source.display()))
+    }
+}
+
+// Pulls just the sequence and audio files out of the zip; paths that would
+// escape the scratch folder are skipped.
+fn extract_show_files(zip_path: &Path, destination: &Path) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(File::open(zip_path)?)
+        .map_err(|e| anyhow::anyhow!("Failed to read {}: {}", zip_path.display(), e))?;
+    
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let Some(relative) = entry.enclosed_name() else {
+            continue;
+        };
+        if show_file_kind(&relative).is_none() {
+            continue;
+        }
+        
+        let target = destination.join(relative);
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::io::copy(&mut entry, &mut File::create(&target)?)?;
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ShowFile {
+    Sequence,
+    Audio,
+}
+
+fn show_file_kind(path: &Path) -> Option<ShowFile> {
+    // macOS resource forks (__MACOSX/, ._name) look like show files but aren't.
+    let hidden = path.components().any(|c| {
+        let c = c.as_os_str().to_string_lossy();
+        c.starts_with('.') || c == "__MACOSX"
+    });
+    if hidden {
+        return None;
+    }
+    
+    let extension = path.extension()?.to_string_lossy().to_ascii_lowercase();
+    if extension == "fseq" {
+        Some(ShowFile::Sequence)
+    } else if AUDIO_EXTENSIONS.contains(&extension.as_str()) {
+        Some(ShowFile::Audio)
+    } else {
+        None
+    }
+}
+
+fn find_show_files(root: &Path, scratch: Option<ScratchDir>) -> Result<Package> {
+    let mut sequences = Vec::new();
+    let mut audio = Vec::new();
+    
+    let mut pending = vec![root.to_path_buf()];
+    while let Some(dir) = pending.pop() {
+        for entry in std::fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.is_dir() {
+                pending.push(path);
+                continue;
+            }
+            match show_file_kind(path.strip_prefix(root).unwrap_or(&path)) {
+                Some(ShowFile::Sequence) => sequences.push(path),
+                Some(ShowFile::Audio) => audio.push(path),
+                None => {}
+            }
+        }
+    }
+    sequences.sort();
+    audio.sort();
+    
+    let fseq = match sequences.len() {
+        0 => return Err(anyhow::anyhow!("No .fseq file found in the package")),
+        1 => sequences.remove(0),
+        _ => {
+            let names: Vec<String> = sequences.iter().map(|p| file_name(p)).collect();
+            return Err(anyhow::anyhow!("The package holds several sequences ({}); import them one at a time", names.join(", ")));
+        }
+    };
+    
+    // Prefer audio named after the sequence; otherwise a lone audio file.
+    let stem = fseq.file_stem().unwrap_or_default().to_string_lossy().to_lowercase();
+    let matching = audio.iter()
+        .position(|p| p.file_stem().unwrap_or_default().to_string_lossy().to_lowercase() == stem);
+    let audio = match (matching, audio.len()) {
+        (Some(index), _) => Some(audio.remove(index)),
+        (None, 0) => None,
+        (None, 1) => Some(audio.remove(0)),
+        (None, _) => {
+            return Err(anyhow::anyhow!("The package holds several audio files and none is named after {}", file_name(&fseq)));
+        }
+    };
+    
+    Ok(Package { fseq, audio, _scratch: scratch })
+}
+
+// Keeps names FAT and exFAT accept and that read the same on every OS.
+pub fn sanitize_name(name: &str) -> Option<String> {
+    let mut sanitized = String::new();
+    for c in name.trim().chars() {
+        if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+            sanitized.push(c);
+        } else if (c.is_whitespace() || c == '.') && !sanitized.ends_with('_') {
+            sanitized.push('_');
+        }
+    }
+    let sanitized = sanitized.trim_matches('_').to_string();
+    (!sanitized.is_empty()).then_some(sanitized)
+}
+
+// Refuses a show already on the drive, whether under the same name or as an
+// identical sequence saved under another one.
+fn check_duplicates(folder: &Path, name: &str, fseq: &Path) -> Result<()> {
+    let size = std::fs::metadata(fseq)?.len();
+    let mut contents: Option<Vec<u8>> = None;
+    
+    for entry in std::fs::read_dir(folder)? {
+        let path = entry?.path();
+        let Some(kind) = show_file_kind(path.strip_prefix(folder).unwrap_or(&path)) else {
+            continue;
+        };
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        
+        if stem.eq_ignore_ascii_case(name) {
+            return Err(anyhow::anyhow!("A light show named {} is already on the drive", name));
+        }
+        if kind == ShowFile::Sequence && std::fs::metadata(&path)?.len() == size {
+            let ours = match &contents {
+                Some(bytes) => bytes,
+                None => contents.insert(std::fs::read(fseq)?),
+            };
+            if std::fs::read(&path)? == *ours {
+                return Err(anyhow::anyhow!("This sequence is already on the drive as {}", file_name(&path)));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().unwrap_or_default().to_string_lossy().into_owned()
+}
+
+// A fresh directory under the system temp dir for import tests.
+#[cfg(test)]
+pub(crate) fn scratch(prefix: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("teslausb-{}-{}", prefix, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    
+    // A valid 48-channel, 20 ms, one-second sequence.
+    fn sequence(seed: u8) -> Vec<u8> {
+        let mut frames = vec![vec![0u8; 48]; 50];
+        frames[0][0] = seed;
+        crate::lightshow::fseq(48, 20, &frames)
+    }
+    
+    fn zip(path: &Path, files: &[(&str, &[u8])]) {
+        let mut writer = zip::ZipWriter::new(File::create(path).unwrap());
+        let options = zip::write::SimpleFileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+        for (name, contents) in files {
+            writer.start_file(*name, options).unwrap();
+            writer.write_all(contents).unwrap();
+        }
+        writer.finish().unwrap();
+    }
+    
+    #[test]
+    fn imports_a_zip_under_a_consistent_name() {
+        let dir = scratch("lightshow-zip");
+        let package = dir.join("show.zip");
+        let fseq = sequence(255);
+        zip(&package, &[
+            ("Jingle Bells v2/Jingle Bells v2.fseq", &fseq),
+            ("Jingle Bells v2/Jingle Bells v2.MP3", b"ID3 audio"),
+            ("Jingle Bells v2/readme.txt", b"Enjoy"),
+            ("__MACOSX/Jingle Bells v2/._Jingle Bells v2.fseq", b"junk"),
+        ]);
+        
+        let folder = dir.join("drive").join(LIGHTSHOW_FOLDER);
+        let report = import_into(&package, &folder, None, 1 << 30).unwrap();
+        
+        assert_eq!(report.name, "Jingle_Bells_v2");
+        assert_eq!(report.fseq, "Jingle_Bells_v2.fseq");
+        assert_eq!(report.audio.as_deref(), Some("Jingle_Bells_v2.mp3"));
+        assert_eq!(report.bytes_copied, fseq.len() as u64 + 9);
+        assert_eq!(std::fs::read(folder.join("Jingle_Bells_v2.fseq")).unwrap(), fseq);
+        assert!(folder.join("Jingle_Bells_v2.mp3").is_file());
+        
+        // Same name again, or the same sequence renamed, is refused.
+        let error = import_into(&package, &folder, None, 1 << 30).unwrap_err().to_string();
+        assert!(error.contains("already on the drive"), "{}", error);
+        let error = import_into(&package, &folder, Some("Other"), 1 << 30).unwrap_err().to_string();
+        assert!(error.contains("as Jingle_Bells_v2.fseq"), "{}", error);
+        assert!(!folder.join("Other.fseq").exists());
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn refuses_invalid_or_oversized_packages() {
+        let dir = scratch("lightshow-folder");
+        let package = dir.join("package");
+        std::fs::create_dir_all(&package).unwrap();
+        let folder = dir.join(LIGHTSHOW_FOLDER);
+        
+        let mut bad = sequence(1);
+        bad[18] = 5;
+        std::fs::write(package.join("fast.fseq"), &bad).unwrap();
+        let error = import_into(&package, &folder, None, 1 << 30).unwrap_err().to_string();
+        assert!(error.contains("not a valid light show"), "{}", error);
+        
+        std::fs::write(package.join("fast.fseq"), sequence(1)).unwrap();
+        std::fs::write(package.join("one.wav"), b"RIFF").unwrap();
+        std::fs::write(package.join("two.wav"), b"RIFF").unwrap();
+        assert!(import_into(&package, &folder, None, 1 << 30).is_err());
+        
+        std::fs::remove_file(package.join("two.wav")).unwrap();
+        assert!(import_into(&package, &folder, None, 100).unwrap_err().to_string().contains("MB is free"));
+        
+        let report = import_into(&package, &folder, Some("  My show!  "), 1 << 30).unwrap();
+        assert_eq!((report.fseq.as_str(), report.audio.as_deref()), ("My_show.fseq", Some("My_show.wav")));
+        assert!(import_into(&package, &folder, Some("???"), 1 << 30).is_err());
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    
+    #[test]
+    fn imports_next_to_teslacam_without_a_lightshow_partition() {
+        let dir = scratch("lightshow-mounts");
+        let cam = dir.join("cam");
+        let music = dir.join("music");
+        std::fs::create_dir_all(cam.join("TeslaCam")).unwrap();
+        std::fs::create_dir_all(&music).unwrap();
+        let mounts = vec![music.to_string_lossy().into_owned(), cam.to_string_lossy().into_owned()];
+        
+        let destination = |mounts: &[String]| import_destination(mounts, LIGHTSHOW_FOLDER, crate::lightshow::resolve_lightshow_folder);
+        let (mount, folder) = destination(&mounts).unwrap();
+        assert_eq!(Path::new(&mount), cam);
+        assert_eq!(folder, cam.join(LIGHTSHOW_FOLDER));
+        
+        std::fs::create_dir_all(music.join(LIGHTSHOW_FOLDER)).unwrap();
+        assert_eq!(destination(&mounts).unwrap().1, music.join(LIGHTSHOW_FOLDER));
+        assert!(destination(&[]).is_none());
+        
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
\ No newline at end of file
@@ -0,0 +1,337 @@
+use crate::command::CommandRunner;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// The car plays this from the root of the drive when locking.
+pub const LOCK_CHIME_FILE: &str = "LockChime.wav";
+
+// The limits the car enforces before it will use a custom chime.
+const MAX_FILE_BYTES: u64 = 1_000_000;
+const MAX_DURATION_SECS: f64 = 5.0;
+const SUPPORTED_BITS: &[u16] = &[8, 16, 24];
+// Other rates play, but pitched or not at all on some firmware.
+const RECOMMENDED_SAMPLE_RATES: &[u32] = &[44_100, 48_000];
+
+pub const FORMAT_PCM: u16 = 1;
+const FORMAT_FLOAT: u16 = 3;
+const FORMAT_EXTENSIBLE: u16 = 0xFFFE;
+const HEADER_BYTES: usize = 44;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WavInfo {
+    // PCM or float; extensible headers report their sub-format.
+    pub format: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+    pub data_bytes: u64,
+    pub duration_secs: f64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChimeReport {
+    pub info: Option<WavInfo>,
+    pub size: u64,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    pub valid: bool,
+}
+
+// Fixes applied before installing, so an almost-right file doesn't have to
+// go through an audio editor first.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ChimeOptions {
+    // Cut the chime to the duration and size limits.
+    pub trim: bool,
+    // Re-encode float or 32-bit audio as 16-bit PCM.
+    pub convert: bool,
+}
+
+// A chime to put on the drive while preparing it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LockChimeConfig {
+    pub source: String,
+    #[serde(default, flatten)]
+    pub options: ChimeOptions,
+}
+
+// Where the fmt and data chunks are, so trimming can keep the samples as
+// they are.
+struct Wav {
+    info: WavInfo,
+    block_align: usize,
+    data: std::ops::Range<usize>,
+}
+
+fn parse(bytes: &[u8]) -> Result<Wav> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(anyhow::anyhow!("Not a WAV file"));
+    }
+    
+    let u16_at = |at: usize| u16::from_le_bytes([bytes[at], bytes[at + 1]]);
+    let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+    
+    let mut fmt = None;
+    let mut data = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let id = &bytes[at..at + 4];
+        let size = u32_at(at + 4) as usize;
+        let body = at + 8;
+        // Editors sometimes leave a data size past the end of the file.
+        let end = body.saturating_add(size).min(bytes.len());
+        
+        match id {
+            b"fmt " if end - body >= 16 => {
+                let mut format = u16_at(body);
+                if format == FORMAT_EXTENSIBLE && end - body >= 26 {
+                    format = u16_at(body + 24);
+                }
+                fmt = Some((format, u16_at(body + 2), u32_at(body + 4), u16_at(body + 12), u16_at(body + 14)));
+            }
+            b"data" => data = Some(body..end),
+            _ => {}
+        }
+        // Chunks are padded to an even length.
+        at = body.saturating_add(size).saturating_add(size & 1);
+    }
+    
+    let (format, channels, sample_rate, block_align, bits_per_sample) = fmt
+        .ok_or_else(|| anyhow::anyhow!("WAV file has no fmt chunk"))?;
+    let data = data.ok_or_else(|| anyhow::anyhow!("WAV file has no data chunk"))?;
+    if channels == 0 || sample_rate == 0 || block_align == 0 {
+        return Err(anyhow::anyhow!("WAV header is corrupt"));
+    }
+    
+    let data_bytes = (data.end - data.start) as u64;
+    Ok(Wav {
+        info: WavInfo {
+            format,
+            channels,
+            sample_rate,
+            bits_per_sample,
+            data_bytes,
+            duration_secs: data_bytes as f64 / block_align as f64 / sample_rate as f64,
+        },
+        block_align: block_align as usize,
+        data,
+    })
+}
+
+pub fn parse_wav(bytes: &[u8]) -> Result<WavInfo> {
+    parse(bytes).map(|wav| wav.info)
+}
+
+pub fn validate_chime(bytes: &[u8]) -> ChimeReport {
+    let mut report = ChimeReport {
+        info: None,
+        size: bytes.len() as u64,
+        errors: Vec::new(),
+        warnings: Vec::new(),
+        valid: false,
+    };
+    
+    let info = match parse_wav(bytes) {
+        Ok(info) => info,
+        Err(e) => {
+            report.errors.push(e.to_string());
+            return report;
+        }
+    };
+    
+    if info.format != FORMAT_PCM {
+        report.errors.push("Audio must be uncompressed PCM".to_string());
+    } else if !SUPPORTED_BITS.contains(&info.bits_per_sample) {
+        report.errors.push(format!("Expected 8, 16 or 24-bit samples, got {}-bit", info.bits_per_sample));
+    }
+    if info.channels > 2 {
+        report.errors.push(format!("Expected mono or stereo, got {} channels", info.channels));
+    }
+    if info.duration_secs > MAX_DURATION_SECS {
+        report.errors.push(format!("Chime is {:.1} s long; the car allows {} s", info.duration_secs, MAX_DURATION_SECS));
+    }
+    if report.size > MAX_FILE_BYTES {
+        report.errors.push(format!("File is {} KB; the car allows {} KB", report.size / 1000, MAX_FILE_BYTES / 1000));
+    }
+    if !RECOMMENDED_SAMPLE_RATES.contains(&info.sample_rate) {
+        report.warnings.push(format!("{} Hz audio may not play correctly; 44100 or 48000 Hz is recommended", info.sample_rate));
+    }
+    
+    report.info = Some(info);
+    report.valid = report.errors.is_empty();
+    report
+}
+
+// Applies the requested fixes and returns the file to install, or the
+// reasons the car would still reject it.
+pub fn prepare_chime(bytes: &[u8], options: &ChimeOptions) -> Result<(Vec<u8>, ChimeReport)> {
+    let mut prepared = bytes.to_vec();
+    
+    if options.convert {
+        let info = parse_wav(&prepared)?;
+        let needs_conversion = info.format != FORMAT_PCM || !SUPPORTED_BITS.contains(&info.bits_per_sample);
+        if needs_conversion {
+            prepared = to_pcm16(&prepared)?;
+        }
+    }
+    if options.trim {
+        prepared = trim(&prepared)?;
+    }
+    
+    let report = validate_chime(&prepared);
+    if !report.valid {
+        return Err(anyhow::anyhow!("Lock chime can't be used: {}", report.errors.join("; ")));
+    }
+    Ok((prepared, report))
+}
+
+pub async fn validate_chime_file(path: &Path) -> Result<ChimeReport> {
+    Ok(validate_chime(&tokio::fs::read(path).await?))
+}
+
+// Cuts the samples, on a frame boundary, to whichever of the duration and
+// size limits comes first.
+fn trim(bytes: &[u8]) -> Result<Vec<u8>> {
+    let wav = parse(bytes)?;
+    let by_duration = (MAX_DURATION_SECS * wav.info.sample_rate as f64) as usize;
+    let by_size = (MAX_FILE_BYTES as usize - HEADER_BYTES) / wav.block_align;
+    let frames = (wav.data.len() / wav.block_align).min(by_duration).min(by_size);
+    
+    let samples = &bytes[wav.data.start..wav.data.start + frames * wav.block_align];
+    Ok(encode(&wav.info, wav.block_align as u16, samples))
+}
+
+fn to_pcm16(bytes: &[u8]) -> Result<Vec<u8>> {
+    let wav = parse(bytes)?;
+    let info = &wav.info;
+    let width = info.bits_per_sample as usize / 8;
+    if width == 0 || wav.block_align != width * info.channels as usize {
+        return Err(anyhow::anyhow!("Can't convert {}-bit audio", info.bits_per_sample));
+    }
+    
+    let mut samples = Vec::with_capacity(wav.data.len() / width * 2);
+    for sample in bytes[wav.data.clone()].chunks_exact(width) {
+        let value: i16 = match (info.format, width) {
+            (FORMAT_FLOAT, 4) => {
+                let f = f32::from_le_bytes(sample.try_into().unwrap());
+                (f.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+            }
+            (FORMAT_FLOAT, 8) => {
+                let f = f64::from_le_bytes(sample.try_into().unwrap());
+                (f.clamp(-1.0, 1.0) * i16::MAX as f64) as i16
+            }
+            (FORMAT_PCM, 1) => ((sample[0] as i16) - 128) << 8,
+            // Keep the most significant two bytes.
+            (FORMAT_PCM, _) => i16::from_le_bytes([sample[width - 2], sample[width - 1]]),
+            _ => return Err(anyhow::anyhow!("Can't convert audio format {}", info.format)),
+        };
+        samples.extend_from_slice(&value.to_le_bytes());
+    }
+    
+    let converted = WavInfo {
+        format: FORMAT_PCM,
+        bits_per_sample: 16,
+        ..info.clone()
+    };
+    Ok(encode(&converted, info.channels * 2, &samples))
+}
+
+// A plain 44-byte header followed by the samples.
+fn encode(info: &WavInfo, block_align: u16, samples: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(HEADER_BYTES + samples.len());
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&((HEADER_BYTES - 8 + samples.len()) as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&16u32.to_le_bytes());
+    out.extend_from_slice(&info.format.to_le_bytes());
+    out.extend_from_slice(&info.channels.to_le_bytes());
+    out.extend_from_slice(&info.sample_rate.to_le_bytes());
+    out.extend_from_slice(&(info.sample_rate * block_align as u32).to_le_bytes());
+    out.extend_from_slice(&block_align.to_le_bytes());
+    out.extend_from_slice(&info.bits_per_sample.to_le_bytes());
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(samples.len() as u32).to_le_bytes());
+    out.extend_from_slice(samples);
+    out
+}
+
+// Prepares the chime and writes it to the root of the drive's TeslaCam
+// partition, replacing any earlier one.
+pub async fn install_lock_chime(
+    runner: &impl CommandRunner,
+    device: &crate::UsbDevice,
+    source: &Path,
+    options: &ChimeOptions,
+) -> Result<ChimeReport> {
+    let (chime, report) = prepare_chime(&tokio::fs::read(source).await?, options)?;
+    
+    let mount_points = crate::tesla::get_device_mount_points(runner, device).await?;
+    let root = mount_points.iter()
+        .find(|m| Path::new(m).join("TeslaCam").is_dir())
+        .or(mount_points.first())
+        .ok_or_else(|| anyhow::anyhow!("No mounted partition found on {}", device.path))?;
+    
+    tokio::fs::write(Path::new(root).join(LOCK_CHIME_FILE), chime).await?;
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    fn wav(format: u16, channels: u16, rate: u32, bits: u16, samples: &[u8]) -> Vec<u8> {
+        let block_align = channels * bits / 8;
+        let info = WavInfo {
+            format,
+            channels,
+            sample_rate: rate,
+            bits_per_sample: bits,
+            data_bytes: 0,
+            duration_secs: 0.0,
+        };
+        encode(&info, block_align, samples)
+    }
+    
+    #[test]
+    fn reads_headers_and_reports_the_car_limits() {
+        // A LIST chunk before fmt, as many editors write.
+        let mut bytes = b"RIFF\0\0\0\0WAVELIST\x03\0\0\0abc\0".to_vec();
+        bytes.extend_from_slice(&wav(FORMAT_PCM, 2, 44_100, 16, &vec![0; 44_100 * 4])[12..]);
+        
+        let report = validate_chime(&bytes);
+        assert!(report.valid, "{:?}", report.errors);
+        let info = report.info.unwrap();
+        assert_eq!((info.channels, info.sample_rate, info.bits_per_sample), (2, 44_100, 16));
+        assert!((info.duration_secs - 1.0).abs() < 1e-9);
+        
+        // Six seconds of 22 kHz mono float: too long, not PCM, odd rate.
+        let report = validate_chime(&wav(FORMAT_FLOAT, 1, 22_050, 32, &vec![0; 22_050 * 4 * 6]));
+        assert!(!report.valid);
+        assert_eq!(report.errors.len(), 2, "{:?}", report.errors);
+        assert_eq!(report.warnings.len(), 1);
+        
+        assert!(!validate_chime(b"ID3\x04 not a wav").valid);
+    }
+    
+    #[test]
+    fn converts_and_trims_into_an_installable_chime() {
+        let mut samples = Vec::new();
+        for value in [0.5f32, -1.0].iter().cycle().take(48_000 * 2 * 8) {
+            samples.extend_from_slice(&value.to_le_bytes());
+        }
+        let source = wav(FORMAT_FLOAT, 2, 48_000, 32, &samples);
+        
+        assert!(prepare_chime(&source, &ChimeOptions::default()).is_err());
+        assert!(prepare_chime(&source, &ChimeOptions { trim: true, convert: false }).is_err());
+        
+        let (chime, report) = prepare_chime(&source, &ChimeOptions { trim: true, convert: true }).unwrap();
+        let info = report.info.unwrap();
+        assert_eq!((info.format, info.bits_per_sample, info.channels), (FORMAT_PCM, 16, 2));
+        // 192 KB a second, so the five-second limit cuts first.
+        assert_eq!(chime.len(), 44 + 48_000 * 4 * 5);
+        assert!((info.duration_secs - MAX_DURATION_SECS).abs() < 1e-9);
+        assert_eq!(&chime[44..48], [16_383i16.to_le_bytes(), (-32_767i16).to_le_bytes()].concat());
+    }
+}
\ No newline at end of file
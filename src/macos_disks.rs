@@ -0,0 +1,209 @@
+use crate::command::CommandRunner;
+use crate::plist::Plist;
+use anyhow::Result;
+
+// Typed views of `diskutil info -plist` and `diskutil list -plist`. The
+// plist keys are stable across macOS releases and locales, unlike the
+// human-readable output.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct DiskInfo {
+    pub identifier: String,
+    pub node: String,
+    pub media_name: Option<String>,
+    pub size: u64,
+    pub internal: bool,
+    pub removable: bool,
+    pub whole_disk: bool,
+    pub parent_whole_disk: Option<String>,
+    pub protocol: Option<String>,
+    pub content: Option<String>,
+    pub volume_name: Option<String>,
+    pub filesystem: Option<String>,
+    pub mount_point: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskList {
+    pub whole_disks: Vec<String>,
+    pub disks: Vec<ListedDisk>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct ListedDisk {
+    pub identifier: String,
+    pub size: u64,
+    pub content: Option<String>,
+    pub partitions: Vec<ListedPartition>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct ListedPartition {
+    pub identifier: String,
+    pub size: u64,
+    pub content: Option<String>,
+    pub volume_name: Option<String>,
+}
+
+pub async fn disk_info(runner: &impl CommandRunner, device_path: &str) -> Result<DiskInfo> {
+    let output = runner.run("diskutil", &["info", "-plist", device_path]).await?;
+    
+    // diskutil reports unknown disks on stdout with a non-zero exit code.
+    if !output.success {
+        return Err(anyhow::anyhow!(
+            "Device not found: {}: {}{}",
+            device_path,
+            output.stdout.trim(),
+            output.stderr.trim()
+        ));
+    }
+    
+    parse_disk_info(&output.stdout)
+}
+
+pub async fn list_external_disks(runner: &impl CommandRunner) -> Result<DiskList> {
+    list(runner, &["list", "-plist", "external", "physical"]).await
+}
+
+pub async fn list_disk(runner: &impl CommandRunner, device_path: &str) -> Result<DiskList> {
+    list(runner, &["list", "-plist", device_path]).await
+}
+
+async fn list(runner: &impl CommandRunner, args: &[&str]) -> Result<DiskList> {
+    let output = runner.run("diskutil", args).await?;
+    
+    if !output.success {
+        return Err(anyhow::anyhow!(
+            "Failed to list disks: {}{}",
+            output.stdout.trim(),
+            output.stderr.trim()
+        ));
+    }
+    
+    parse_disk_list(&output.stdout)
+}
+
+pub fn parse_disk_info(xml: &str) -> Result<DiskInfo> {
+    let plist = crate::plist::parse(xml)?;
+    let identifier = plist.str_at("DeviceIdentifier")
+        .ok_or_else(|| anyhow::anyhow!("diskutil info is missing DeviceIdentifier"))?
+        .to_string();
+    
+    Ok(DiskInfo {
+        node: plist.str_at("DeviceNode")
+            .map(str::to_string)
+            .unwrap_or_else(|| format!("/dev/{}", identifier)),
+        identifier,
+        media_name: non_empty(plist.str_at("MediaName")),
+        // Older releases only report TotalSize.
+        size: plist.u64_at("Size").or_else(|| plist.u64_at("TotalSize")).unwrap_or(0),
+        internal: plist.bool_at("Internal").unwrap_or(false),
+        removable: plist.bool_at("RemovableMedia")
+            .or_else(|| plist.bool_at("Removable"))
+            .unwrap_or(false),
+        whole_disk: plist.bool_at("WholeDisk").unwrap_or(false),
+        parent_whole_disk: non_empty(plist.str_at("ParentWholeDisk")),
+        protocol: non_empty(plist.str_at("BusProtocol")),
+        content: non_empty(plist.str_at("Content")),
+        volume_name: non_empty(plist.str_at("VolumeName")),
+        filesystem: non_empty(plist.str_at("FilesystemType")),
+        mount_point: non_empty(plist.str_at("MountPoint")),
+    })
+}
+
+pub fn parse_disk_list(xml: &str) -> Result<DiskList> {
+    let plist = crate::plist::parse(xml)?;
+    
+    let whole_disks = plist.array_at("WholeDisks")
+        .iter()
+        .filter_map(|disk| disk.as_str().map(str::to_string))
+        .collect();
+    
+    let disks = plist.array_at("AllDisksAndPartitions")
+        .iter()
+        .filter_map(|disk| Some(ListedDisk {
+            identifier: disk.str_at("DeviceIdentifier")?.to_string(),
+            size: disk.u64_at("Size").unwrap_or(0),
+            content: non_empty(disk.str_at("Content")),
+            partitions: disk.array_at("Partitions")
+                .iter()
+                .filter_map(listed_partition)
+                .collect(),
+        }))
+        .collect();
+    
+    Ok(DiskList { whole_disks, disks })
+}
+
+fn listed_partition(partition: &Plist) -> Option<ListedPartition> {
+    Some(ListedPartition {
+        identifier: partition.str_at("DeviceIdentifier")?.to_string(),
+        size: partition.u64_at("Size").unwrap_or(0),
+        content: non_empty(partition.str_at("Content")),
+        volume_name: non_empty(partition.str_at("VolumeName")),
+    })
+}
+
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value.map(str::trim).filter(|v| !v.is_empty()).map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn reads_whole_usb_disk() {
+        let info = parse_disk_info(include_str!("../tests/fixtures/macos/diskutil_info_disk4.plist")).unwrap();
+        
+        assert_eq!(info.identifier, "disk4");
+        assert_eq!(info.node, "/dev/disk4");
+        assert_eq!(info.media_name.as_deref(), Some("Ultra Fit"));
+        assert_eq!(info.size, 61530439680);
+        assert_eq!(info.protocol.as_deref(), Some("USB"));
+        assert!(info.whole_disk);
+        assert!(!info.internal);
+        assert!(info.removable);
+        assert_eq!(info.mount_point, None);
+    }
+    
+    #[test]
+    fn reads_mounted_partition() {
+        let info = parse_disk_info(include_str!("../tests/fixtures/macos/diskutil_info_disk4s2.plist")).unwrap();
+        
+        assert!(!info.whole_disk);
+        assert_eq!(info.parent_whole_disk.as_deref(), Some("disk4"));
+        assert_eq!(info.filesystem.as_deref(), Some("exfat"));
+        assert_eq!(info.volume_name.as_deref(), Some("TeslaCam"));
+        assert_eq!(info.mount_point.as_deref(), Some("/Volumes/TeslaCam"));
+    }
+    
+    #[test]
+    fn reads_internal_disk_flags() {
+        let info = parse_disk_info(include_str!("../tests/fixtures/macos/diskutil_info_disk0.plist")).unwrap();
+        
+        assert!(info.internal);
+        assert!(!info.removable);
+        assert_eq!(info.protocol.as_deref(), Some("Apple Fabric"));
+    }
+    
+    #[test]
+    fn lists_whole_disks_and_partitions() {
+        let list = parse_disk_list(include_str!("../tests/fixtures/macos/diskutil_list_external.plist")).unwrap();
+        
+        assert_eq!(list.whole_disks, vec!["disk4", "disk6"]);
+        assert_eq!(list.disks.len(), 2);
+        
+        let partitions: Vec<(&str, Option<&str>)> = list.disks[0].partitions.iter()
+            .map(|p| (p.identifier.as_str(), p.volume_name.as_deref()))
+            .collect();
+        assert_eq!(partitions, vec![
+            ("disk4s1", Some("EFI")),
+            ("disk4s2", Some("TeslaCam")),
+            ("disk4s3", Some("TESLAMUSIC")),
+        ]);
+        assert_eq!(list.disks[1].content.as_deref(), Some("FDisk_partition_scheme"));
+    }
+}
\ No newline at end of file
@@ -3,10 +3,38 @@
     windows_subsystem = "windows"
 )]
 
+mod command;
 mod usb;
 mod partitions;
 mod tesla;
+mod mounts;
+mod volume;
+mod gpt;
+mod target;
+mod archive;
+mod auto_archive;
+mod benchmark;
+mod boombox;
+mod capacity;
+mod clip_export;
+mod clips;
+mod erase;
+mod health;
+mod lightshow;
+mod lightshow_import;
+mod lockchime;
+mod progress;
+mod retention;
+mod signatures;
+mod surface_scan;
+#[cfg(any(target_os = "windows", test))]
+mod windows_disks;
+#[cfg(any(target_os = "macos", test))]
+mod macos_disks;
+#[cfg(any(target_os = "macos", test))]
+mod plist;
 
+use command::SystemRunner;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use tauri::State;
@@ -34,60 +62,504 @@ pub struct TeslaConfig {
     pub sentry_size_gb: u32,
     pub music_size_gb: u32,
     pub lightshow_size_gb: u32,
+    // Optional counterfeit check run before anything is partitioned.
+    #[serde(default)]
+    pub capacity_check: Option<capacity::CapacityMode>,
+    #[serde(default)]
+    pub surface_scan: Option<surface_scan::ScanMode>,
+    // Erase the old contents before partitioning.
+    #[serde(default)]
+    pub erase: Option<erase::EraseOptions>,
+    // Custom LockChime.wav to put on the drive.
+    #[serde(default)]
+    pub lock_chime: Option<lockchime::LockChimeConfig>,
+    // Create the Boombox folder for custom sounds.
+    #[serde(default)]
+    pub boombox: bool,
 }
 
-type DeviceState = Mutex<HashMap<String, UsbDevice>>;
+#[derive(Debug, Clone)]
+pub struct SelectedDevice {
+    pub device: UsbDevice,
+    pub identity: usb::DeviceIdentity,
+}
+
+type DeviceState = Mutex<HashMap<String, SelectedDevice>>;
 
 #[tauri::command]
 async fn get_usb_devices(state: State<'_, DeviceState>) -> Result<Vec<UsbDevice>, String> {
-    let devices = usb::list_usb_devices().await.map_err(|e| e.to_string())?;
+    let devices = usb::list_usb_devices(&SystemRunner).await.map_err(|e| e.to_string())?;
     
     let mut device_map = state.lock().await;
-    device_map.clear();
-    for device in &devices {
-        device_map.insert(device.path.clone(), device.clone());
+    // Image files aren't discovered by listing, so keep any the user opened.
+    device_map.retain(|path, _| target::is_image_file(path));
+    let mut listed = Vec::new();
+    for device in devices {
+        // Every command checks the identity before writing, so a stick that
+        // can't be read is left out instead of failing the whole listing.
+        let identity = match usb::read_device_identity(&SystemRunner, &device.path).await {
+            Ok(identity) => identity,
+            Err(_) => continue,
+        };
+        device_map.insert(device.path.clone(), SelectedDevice {
+            device: device.clone(),
+            identity,
+        });
+        listed.push(device);
     }
     
-    Ok(devices)
+    Ok(listed)
+}
+
+// Destructive commands leave a different layout behind than the one the
+// selection was confirmed with. Keep it current, so the next command on
+// the same stick isn't refused as a swapped drive.
+async fn refresh_identity(selected: &mut SelectedDevice) -> Result<(), String> {
+    selected.identity = usb::reread_device_identity(&SystemRunner, &selected.device.path, &selected.identity)
+        .await
+        .map_err(|e| e.to_string())?;
+    Ok(())
 }
 
 #[tauri::command]
 async fn format_tesla_usb(
     device_path: String,
     config: TeslaConfig,
+    window: tauri::Window,
     state: State<'_, DeviceState>,
 ) -> Result<String, String> {
-    let device_map = state.lock().await;
-    let device = device_map.get(&device_path)
+    let mut device_map = state.lock().await;
+    let selected = device_map.get_mut(&device_path)
         .ok_or("Device not found".to_string())?;
     
-    tesla::format_for_tesla(device, &config)
+    let progress = progress::Progress::for_window("format", window);
+    let erased = tesla::format_for_tesla(&SystemRunner, &selected.device, &selected.identity, &config, &progress)
         .await
         .map_err(|e| e.to_string())?;
+    refresh_identity(selected).await?;
     
-    Ok("USB formatted successfully for Tesla".to_string())
+    Ok(match erased {
+        Some(report) => format!("USB formatted successfully for Tesla ({})", erase::summarize(&report)),
+        None => "USB formatted successfully for Tesla".to_string(),
+    })
 }
 
 #[tauri::command]
 async fn create_custom_partitions(
     device_path: String,
     partitions: Vec<PartitionConfig>,
+    erase: Option<erase::EraseOptions>,
+    window: tauri::Window,
     state: State<'_, DeviceState>,
 ) -> Result<String, String> {
+    let mut device_map = state.lock().await;
+    let selected = device_map.get_mut(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let erased = match erase {
+        Some(options) => {
+            let progress = progress::Progress::for_window("erase", window);
+            let report = erase::erase_device(&SystemRunner, &selected.device, &selected.identity, &options, &progress)
+                .await
+                .map_err(|e| e.to_string())?;
+            refresh_identity(selected).await?;
+            Some(report)
+        }
+        None => None,
+    };
+    
+    partitions::create_partitions(&SystemRunner, &selected.device, &selected.identity, &partitions)
+        .await
+        .map_err(|e| e.to_string())?;
+    refresh_identity(selected).await?;
+    
+    Ok(match erased {
+        Some(report) => format!("Partitions created successfully ({})", erase::summarize(&report)),
+        None => "Partitions created successfully".to_string(),
+    })
+}
+
+#[tauri::command]
+async fn open_disk_image(path: String, state: State<'_, DeviceState>) -> Result<UsbDevice, String> {
+    let device = target::open_image(&path).await.map_err(|e| e.to_string())?;
+    select_image(device, state).await
+}
+
+#[tauri::command]
+async fn create_disk_image(path: String, size_gb: u32, state: State<'_, DeviceState>) -> Result<UsbDevice, String> {
+    let device = target::create_image(&path, size_gb).await.map_err(|e| e.to_string())?;
+    select_image(device, state).await
+}
+
+async fn select_image(device: UsbDevice, state: State<'_, DeviceState>) -> Result<UsbDevice, String> {
+    let identity = usb::read_device_identity(&SystemRunner, &device.path)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    state.lock().await.insert(device.path.clone(), SelectedDevice {
+        device: device.clone(),
+        identity,
+    });
+    
+    Ok(device)
+}
+
+#[tauri::command]
+async fn verify_tesla_drive(
+    device_path: String,
+    state: State<'_, DeviceState>,
+) -> Result<Vec<tesla::PartitionCheck>, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    tesla::verify_tesla_drive(&selected.device)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_mount_points(
+    device_path: String,
+    state: State<'_, DeviceState>,
+) -> Result<Vec<String>, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    tesla::get_device_mount_points(&SystemRunner, &selected.device)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn benchmark_write_speed(
+    device_path: String,
+    target: benchmark::BenchmarkTarget,
+    options: Option<benchmark::BenchmarkOptions>,
+    state: State<'_, DeviceState>,
+) -> Result<benchmark::BenchmarkReport, String> {
+    let mut device_map = state.lock().await;
+    let selected = device_map.get_mut(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let report = benchmark::run_benchmark(&SystemRunner, &selected.device, &selected.identity, &target, &options.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())?;
+    refresh_identity(selected).await?;
+    Ok(report)
+}
+
+#[tauri::command]
+async fn check_device_capacity(
+    device_path: String,
+    mode: capacity::CapacityMode,
+    state: State<'_, DeviceState>,
+) -> Result<capacity::CapacityReport, String> {
+    let mut device_map = state.lock().await;
+    let selected = device_map.get_mut(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let report = capacity::check_capacity(&SystemRunner, &selected.device, &selected.identity, mode)
+        .await
+        .map_err(|e| e.to_string())?;
+    refresh_identity(selected).await?;
+    Ok(report)
+}
+
+#[tauri::command]
+async fn scan_device_surface(
+    device_path: String,
+    mode: surface_scan::ScanMode,
+    window: tauri::Window,
+    state: State<'_, DeviceState>,
+) -> Result<surface_scan::SurfaceScanReport, String> {
+    let mut device_map = state.lock().await;
+    let selected = device_map.get_mut(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let progress = progress::Progress::for_window("surface-scan", window);
+    let report = surface_scan::scan_surface(&SystemRunner, &selected.device, &selected.identity, mode, &progress)
+        .await
+        .map_err(|e| e.to_string())?;
+    refresh_identity(selected).await?;
+    Ok(report)
+}
+
+#[tauri::command]
+async fn erase_device(
+    device_path: String,
+    options: erase::EraseOptions,
+    window: tauri::Window,
+    state: State<'_, DeviceState>,
+) -> Result<erase::EraseReport, String> {
+    let mut device_map = state.lock().await;
+    let selected = device_map.get_mut(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let progress = progress::Progress::for_window("erase", window);
+    let report = erase::erase_device(&SystemRunner, &selected.device, &selected.identity, &options, &progress)
+        .await
+        .map_err(|e| e.to_string())?;
+    refresh_identity(selected).await?;
+    Ok(report)
+}
+
+#[tauri::command]
+async fn wipe_signatures(
+    device_path: String,
+    state: State<'_, DeviceState>,
+) -> Result<Vec<signatures::FoundSignature>, String> {
+    let mut device_map = state.lock().await;
+    let selected = device_map.get_mut(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let report = signatures::wipe_device_signatures(&SystemRunner, &selected.device, &selected.identity)
+        .await
+        .map_err(|e| e.to_string())?;
+    refresh_identity(selected).await?;
+    Ok(report)
+}
+
+#[tauri::command]
+async fn index_device_clips(
+    device_path: String,
+    filter: Option<clips::ClipFilter>,
+    state: State<'_, DeviceState>,
+) -> Result<clips::ClipIndex, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let teslacam = clips::find_teslacam_folder(&SystemRunner, &selected.device)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    clips::index_clips(&teslacam, &filter.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn index_clip_folder(path: String, filter: Option<clips::ClipFilter>) -> Result<clips::ClipIndex, String> {
+    let teslacam = clips::resolve_teslacam_folder(std::path::Path::new(&path)).map_err(|e| e.to_string())?;
+    
+    clips::index_clips(&teslacam, &filter.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_clip_events(
+    events: Vec<clips::ClipEvent>,
+    format: clip_export::ExportFormat,
+    destination: String,
+) -> Result<clip_export::ExportSummary, String> {
+    clip_export::export_events(&events, format, std::path::Path::new(&destination))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn archive_device_clips(
+    device_path: String,
+    options: archive::ArchiveOptions,
+    window: tauri::Window,
+    state: State<'_, DeviceState>,
+) -> Result<archive::ArchiveReport, String> {
     let device_map = state.lock().await;
-    let device = device_map.get(&device_path)
+    let selected = device_map.get(&device_path)
         .ok_or("Device not found".to_string())?;
     
-    partitions::create_partitions(device, &partitions)
+    let teslacam = clips::find_teslacam_folder(&SystemRunner, &selected.device)
         .await
         .map_err(|e| e.to_string())?;
     
-    Ok("Partitions created successfully".to_string())
+    let progress = progress::Progress::for_window("archive", window);
+    archive::archive_events(&teslacam, &options, &progress)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn archive_clip_folder(
+    path: String,
+    options: archive::ArchiveOptions,
+    window: tauri::Window,
+) -> Result<archive::ArchiveReport, String> {
+    let teslacam = clips::resolve_teslacam_folder(std::path::Path::new(&path)).map_err(|e| e.to_string())?;
+    
+    let progress = progress::Progress::for_window("archive", window);
+    archive::archive_events(&teslacam, &options, &progress)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_drive_health(device_path: String, state: State<'_, DeviceState>) -> Result<health::DriveHealth, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    health::drive_health(&SystemRunner, &selected.device)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn validate_device_lightshows(device_path: String, state: State<'_, DeviceState>) -> Result<lightshow::LightShowReport, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let folder = lightshow::find_lightshow_folder(&SystemRunner, &selected.device)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    lightshow::validate_lightshow_folder(&folder)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn validate_lightshow_folder(path: String) -> Result<lightshow::LightShowReport, String> {
+    let folder = lightshow::resolve_lightshow_folder(std::path::Path::new(&path)).map_err(|e| e.to_string())?;
+    
+    lightshow::validate_lightshow_folder(&folder)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_lightshow(device_path: String, source: String, name: Option<String>, state: State<'_, DeviceState>) -> Result<lightshow_import::ImportReport, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    lightshow_import::import_lightshow(&SystemRunner, &selected.device, std::path::Path::new(&source), name.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn validate_lock_chime(path: String) -> Result<lockchime::ChimeReport, String> {
+    lockchime::validate_chime_file(std::path::Path::new(&path))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn install_lock_chime(
+    device_path: String,
+    source: String,
+    options: Option<lockchime::ChimeOptions>,
+    state: State<'_, DeviceState>,
+) -> Result<lockchime::ChimeReport, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    lockchime::install_lock_chime(&SystemRunner, &selected.device, std::path::Path::new(&source), &options.unwrap_or_default())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn validate_device_boombox(device_path: String, state: State<'_, DeviceState>) -> Result<boombox::BoomboxReport, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    let folder = boombox::find_boombox_folder(&SystemRunner, &selected.device)
+        .await
+        .map_err(|e| e.to_string())?;
+    
+    boombox::validate_boombox_folder(&folder)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn validate_boombox_folder(path: String) -> Result<boombox::BoomboxReport, String> {
+    let folder = boombox::resolve_boombox_folder(std::path::Path::new(&path)).map_err(|e| e.to_string())?;
+    
+    boombox::validate_boombox_folder(&folder)
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn import_boombox_sound(device_path: String, source: String, name: Option<String>, state: State<'_, DeviceState>) -> Result<boombox::BoomboxImport, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    boombox::import_boombox_sound(&SystemRunner, &selected.device, std::path::Path::new(&source), name.as_deref())
+        .await
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn estimate_dashcam_retention(
+    dashcam_size_gb: u32,
+    recording: Option<retention::Recording>,
+) -> Result<retention::RetentionEstimate, String> {
+    retention::estimate_retention(dashcam_size_gb, &recording.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn suggest_dashcam_size(
+    goal: retention::RetentionGoal,
+    recording: Option<retention::Recording>,
+) -> Result<retention::SizeSuggestion, String> {
+    retention::suggest_dashcam_size(&goal, &recording.unwrap_or_default()).map_err(|e| e.to_string())
+}
+
+fn config_dir(app: &tauri::AppHandle) -> Result<std::path::PathBuf, String> {
+    app.path_resolver()
+        .app_config_dir()
+        .ok_or("No configuration directory available".to_string())
+}
+
+#[tauri::command]
+async fn get_auto_archive_policies(app: tauri::AppHandle) -> Result<Vec<auto_archive::DrivePolicy>, String> {
+    auto_archive::load_policies(&config_dir(&app)?).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_auto_archive_policy(policy: auto_archive::DrivePolicy, app: tauri::AppHandle) -> Result<(), String> {
+    auto_archive::save_policy(&config_dir(&app)?, policy).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn remove_auto_archive_policy(serial: String, app: tauri::AppHandle) -> Result<(), String> {
+    auto_archive::remove_policy(&config_dir(&app)?, &serial).map_err(|e| e.to_string())
+}
+
+// Called by the UI once it is listening; later calls are no-ops.
+#[tauri::command]
+async fn start_auto_archive(app: tauri::AppHandle, window: tauri::Window) -> Result<(), String> {
+    let config_dir = config_dir(&app)?;
+    if auto_archive::claim_watcher() {
+        tokio::spawn(async move {
+            auto_archive::watch(&SystemRunner, config_dir, window).await;
+        });
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn get_device_identity(device_path: String, state: State<'_, DeviceState>) -> Result<usb::DeviceIdentity, String> {
+    let device_map = state.lock().await;
+    let selected = device_map.get(&device_path)
+        .ok_or("Device not found".to_string())?;
+    
+    Ok(selected.identity.clone())
 }
 
 #[tauri::command]
 async fn get_device_info(device_path: String) -> Result<UsbDevice, String> {
-    usb::get_device_info(&device_path)
+    usb::get_device_info(&SystemRunner, &device_path)
         .await
         .map_err(|e| e.to_string())
 }
@@ -99,6 +571,36 @@ fn main() {
             get_usb_devices,
             format_tesla_usb,
             create_custom_partitions,
+            open_disk_image,
+            create_disk_image,
+            verify_tesla_drive,
+            get_mount_points,
+            benchmark_write_speed,
+            check_device_capacity,
+            scan_device_surface,
+            erase_device,
+            wipe_signatures,
+            index_device_clips,
+            index_clip_folder,
+            export_clip_events,
+            archive_device_clips,
+            archive_clip_folder,
+            get_drive_health,
+            validate_device_lightshows,
+            validate_lightshow_folder,
+            import_lightshow,
+            validate_lock_chime,
+            install_lock_chime,
+            validate_device_boombox,
+            validate_boombox_folder,
+            import_boombox_sound,
+            estimate_dashcam_retention,
+            suggest_dashcam_size,
+            get_auto_archive_policies,
+            set_auto_archive_policy,
+            remove_auto_archive_policy,
+            start_auto_archive,
+            get_device_identity,
             get_device_info
         ])
         .run(tauri::generate_context!())
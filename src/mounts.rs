@@ -0,0 +1,709 @@
+use crate::UsbDevice;
+use crate::command::CommandRunner;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MountedPartition {
+    pub source: String,
+    pub mount_point: String,
+    pub filesystem: String,
+    pub read_only: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeviceHolder {
+    pub pid: u32,
+    pub name: String,
+}
+
+// Holds an exclusive handle on the target device. The handle is released
+// when the lock is dropped, so callers keep it alive for as long as nobody
+// else may touch the disk.
+pub struct DeviceLock {
+    #[cfg(not(target_os = "windows"))]
+    _file: std::fs::File,
+    #[cfg(target_os = "windows")]
+    handles: Vec<winapi::um::winnt::HANDLE>,
+}
+
+#[cfg(target_os = "windows")]
+unsafe impl Send for DeviceLock {}
+
+#[cfg(target_os = "windows")]
+impl Drop for DeviceLock {
+    fn drop(&mut self) {
+        for handle in &self.handles {
+            unsafe {
+                winapi::um::handleapi::CloseHandle(*handle);
+            }
+        }
+    }
+}
+
+pub async fn prepare_device_for_write(runner: &impl CommandRunner, device: &UsbDevice) -> Result<DeviceLock> {
+    let mounted = unmount_all(runner, device).await?;
+    
+    match lock_device(device, &mounted) {
+        Ok(lock) => Ok(lock),
+        Err(e) => Err(anyhow::anyhow!(
+            "Could not get exclusive access to {}: {}{}",
+            device.path,
+            e,
+            describe_holders(&find_device_holders(runner, device).await)
+        )),
+    }
+}
+
+pub async fn unmount_all(runner: &impl CommandRunner, device: &UsbDevice) -> Result<Vec<MountedPartition>> {
+    let mounted = find_mounted_partitions(runner, device).await?;
+    
+    #[cfg(target_os = "macos")]
+    {
+        if !mounted.is_empty() {
+            let output = runner.run("diskutil", &["unmountDisk", &device.path]).await?;
+            
+            if !output.success {
+                return Err(anyhow::anyhow!(
+                    "Failed to unmount {}: {}{}",
+                    device.path,
+                    output.stderr.trim(),
+                    describe_holders(&find_device_holders(runner, device).await)
+                ));
+            }
+        }
+    }
+    
+    #[cfg(target_os = "linux")]
+    {
+        // Unmount deepest mount points first so nested mounts don't keep
+        // their parents busy.
+        let mut ordered = mounted.clone();
+        ordered.sort_by_key(|partition| std::cmp::Reverse(partition.mount_point.len()));
+        
+        for partition in &ordered {
+            let output = runner.run("umount", &[&partition.mount_point]).await?;
+            
+            if !output.success {
+                return Err(anyhow::anyhow!(
+                    "Failed to unmount {} from {}: {}{}",
+                    partition.source,
+                    partition.mount_point,
+                    output.stderr.trim(),
+                    describe_holders(&find_device_holders(runner, device).await)
+                ));
+            }
+        }
+    }
+    
+    // On Windows each volume is dismounted by FSCTL_DISMOUNT_VOLUME in
+    // lock_device, which also keeps it from being remounted while locked.
+    
+    Ok(mounted)
+}
+
+pub async fn find_mounted_partitions(runner: &impl CommandRunner, device: &UsbDevice) -> Result<Vec<MountedPartition>> {
+    if crate::target::is_image_file(&device.path) {
+        return Ok(Vec::new());
+    }
+    
+    #[cfg(target_os = "linux")]
+    {
+        let _ = runner;
+        let block_numbers = linux_block_numbers(&device.path)?;
+        let mountinfo = tokio::fs::read_to_string("/proc/self/mountinfo").await?;
+        
+        Ok(parse_mountinfo(&mountinfo)
+            .into_iter()
+            .filter(|entry| block_numbers.contains(&entry.block_number))
+            .map(|entry| MountedPartition {
+                source: entry.source,
+                mount_point: entry.mount_point,
+                filesystem: entry.filesystem,
+                read_only: entry.read_only,
+            })
+            .collect())
+    }
+    
+    #[cfg(target_os = "macos")]
+    {
+        let output = runner.run("mount", &[]).await?;
+        
+        Ok(parse_bsd_mount_output(&output.stdout)
+            .into_iter()
+            .filter(|entry| is_same_disk(&device.path, &entry.source))
+            .collect())
+    }
+    
+    #[cfg(target_os = "windows")]
+    {
+        // Every volume on the same physical disk, not just the selected one.
+        use crate::windows_disks::{parse_drive_letter, parse_physical_drive};
+        
+        let map = crate::windows_disks::read_disk_map(runner).await?;
+        let disk_number = parse_physical_drive(&device.path).or_else(|| {
+            parse_drive_letter(&device.path)
+                .and_then(|letter| map.disk_for_letter(letter))
+                .map(|disk| disk.number)
+        });
+        
+        Ok(disk_number
+            .map(|number| map.letters_for_disk(number))
+            .unwrap_or_default()
+            .into_iter()
+            .map(|letter| MountedPartition {
+                source: format!("{}:", letter),
+                mount_point: format!("{}:\\", letter),
+                filesystem: String::new(),
+                read_only: false,
+            })
+            .collect())
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn lock_device(device: &UsbDevice, _mounted: &[MountedPartition]) -> Result<DeviceLock> {
+    use std::os::unix::fs::OpenOptionsExt;
+    
+    // O_EXCL on a block device fails with EBUSY while any partition is
+    // mounted or exclusively opened, and keeps the automounter out.
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_EXCL)
+        .open(&device.path)?;
+    
+    Ok(DeviceLock { _file: file })
+}
+
+#[cfg(target_os = "macos")]
+fn lock_device(device: &UsbDevice, _mounted: &[MountedPartition]) -> Result<DeviceLock> {
+    use std::os::unix::fs::OpenOptionsExt;
+    
+    let file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .custom_flags(libc::O_EXLOCK | libc::O_NONBLOCK)
+        .open(&device.path)?;
+    
+    Ok(DeviceLock { _file: file })
+}
+
+// Windows has no exclusive open for a whole disk, so every volume on it is
+// locked and dismounted instead. Physical drives without volumes need none.
+#[cfg(target_os = "windows")]
+fn lock_device(_device: &UsbDevice, mounted: &[MountedPartition]) -> Result<DeviceLock> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::fileapi::{CreateFileW, OPEN_EXISTING};
+    use winapi::um::handleapi::INVALID_HANDLE_VALUE;
+    use winapi::um::ioapiset::DeviceIoControl;
+    use winapi::um::winioctl::{FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME};
+    use winapi::um::winnt::{FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, GENERIC_WRITE};
+    
+    // Handles already taken are released by Drop if a later volume fails.
+    let mut lock = DeviceLock { handles: Vec::new() };
+    
+    for volume in mounted {
+        let volume_path = format!("\\\\.\\{}", volume.source.trim_end_matches('\\'));
+        let wide: Vec<u16> = std::ffi::OsStr::new(&volume_path)
+            .encode_wide()
+            .chain(std::iter::once(0))
+            .collect();
+        
+        unsafe {
+            let handle = CreateFileW(
+                wide.as_ptr(),
+                GENERIC_READ | GENERIC_WRITE,
+                FILE_SHARE_READ | FILE_SHARE_WRITE,
+                std::ptr::null_mut(),
+                OPEN_EXISTING,
+                0,
+                std::ptr::null_mut(),
+            );
+            if handle == INVALID_HANDLE_VALUE {
+                return Err(anyhow::anyhow!("{}: {}", volume.source, std::io::Error::last_os_error()));
+            }
+            lock.handles.push(handle);
+            
+            let mut returned = 0u32;
+            for control in [FSCTL_LOCK_VOLUME, FSCTL_DISMOUNT_VOLUME] {
+                let ok = DeviceIoControl(
+                    handle,
+                    control,
+                    std::ptr::null_mut(),
+                    0,
+                    std::ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    std::ptr::null_mut(),
+                );
+                if ok == 0 {
+                    return Err(anyhow::anyhow!("{}: {}", volume.source, std::io::Error::last_os_error()));
+                }
+            }
+        }
+    }
+    
+    Ok(lock)
+}
+
+pub async fn find_device_holders(runner: &impl CommandRunner, device: &UsbDevice) -> Vec<DeviceHolder> {
+    #[cfg(target_os = "linux")]
+    {
+        let mounted = find_mounted_partitions(runner, device).await.unwrap_or_default();
+        let mut targets: Vec<String> = linux_partition_paths(&device.path);
+        targets.extend(mounted.into_iter().map(|m| m.mount_point));
+        
+        tokio::task::spawn_blocking(move || scan_proc_for_holders(&targets))
+            .await
+            .unwrap_or_default()
+    }
+    
+    #[cfg(target_os = "macos")]
+    {
+        let mut targets = vec![device.path.clone()];
+        if let Ok(mounted) = find_mounted_partitions(runner, device).await {
+            targets.extend(mounted.into_iter().map(|m| m.mount_point));
+        }
+        
+        let mut holders = Vec::new();
+        for target in targets {
+            if let Ok(output) = runner.run("lsof", &["-F", "pc", &target]).await {
+                holders.extend(parse_lsof_fields(&output.stdout));
+            }
+        }
+        holders.sort_by_key(|h| h.pid);
+        holders.dedup_by_key(|h| h.pid);
+        holders
+    }
+    
+    #[cfg(target_os = "windows")]
+    {
+        let _ = (runner, device);
+        Vec::new()
+    }
+}
+
+fn describe_holders(holders: &[DeviceHolder]) -> String {
+    if holders.is_empty() {
+        return String::new();
+    }
+    
+    let list: Vec<String> = holders.iter()
+        .map(|h| format!("{} ({})", h.name, h.pid))
+        .collect();
+    format!(". Device is in use by: {}", list.join(", "))
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct MountInfoEntry {
+    block_number: String,
+    mount_point: String,
+    filesystem: String,
+    source: String,
+    read_only: bool,
+}
+
+// Format: id parent major:minor root mount-point options [optional...] - fstype source super-options
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+fn parse_mountinfo(content: &str) -> Vec<MountInfoEntry> {
+    let mut entries = Vec::new();
+    
+    for line in content.lines() {
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        let separator = match fields.iter().position(|f| *f == "-") {
+            Some(index) => index,
+            None => continue,
+        };
+        if fields.len() < 5 || fields.len() < separator + 3 {
+            continue;
+        }
+        
+        entries.push(MountInfoEntry {
+            block_number: fields[2].to_string(),
+            mount_point: unescape_mount_path(fields[4]),
+            filesystem: fields[separator + 1].to_string(),
+            source: unescape_mount_path(fields[separator + 2]),
+            read_only: fields[5].split(',').any(|option| option == "ro"),
+        });
+    }
+    
+    entries
+}
+
+// The kernel escapes space, tab, newline and backslash as \ooo octal.
+fn unescape_mount_path(raw: &str) -> String {
+    let bytes = raw.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() && bytes[i + 1..i + 4].iter().all(|b| (b'0'..=b'7').contains(b)) {
+            let value = (bytes[i + 1] - b'0') * 64 + (bytes[i + 2] - b'0') * 8 + (bytes[i + 3] - b'0');
+            out.push(value);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_bsd_mount_output(content: &str) -> Vec<MountedPartition> {
+    let mut entries = Vec::new();
+    
+    for line in content.lines() {
+        let (source, rest) = match line.split_once(" on ") {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let (mount_point, options) = match rest.rsplit_once(" (") {
+            Some(parts) => parts,
+            None => (rest, ""),
+        };
+        let mut options = options.trim_end_matches(')').split(',').map(str::trim);
+        let filesystem = options.next().unwrap_or("");
+        let read_only = options.any(|option| option == "read-only");
+        
+        entries.push(MountedPartition {
+            source: source.to_string(),
+            mount_point: mount_point.to_string(),
+            filesystem: filesystem.to_string(),
+            read_only,
+        });
+    }
+    
+    entries
+}
+
+// "/dev/disk4s2" belongs to "/dev/disk4", but "/dev/disk40s1" does not.
+// Linux style names work too: "sda1", and "nvme0n1p1" for disks whose name
+// already ends in a digit.
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn is_same_disk(disk: &str, source: &str) -> bool {
+    let disk = disk.trim_start_matches("/dev/").trim_start_matches('r');
+    let source = source.trim_start_matches("/dev/").trim_start_matches('r');
+    
+    let rest = match source.strip_prefix(disk) {
+        Some("") => return true,
+        Some(rest) => rest,
+        None => return false,
+    };
+    let number = if disk.ends_with(|c: char| c.is_ascii_digit()) {
+        rest.strip_prefix('s').or_else(|| rest.strip_prefix('p'))
+    } else {
+        Some(rest)
+    };
+    number.is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+}
+
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+fn parse_lsof_fields(content: &str) -> Vec<DeviceHolder> {
+    let mut holders = Vec::new();
+    let mut pid = None;
+    
+    for line in content.lines() {
+        if let Some(value) = line.strip_prefix('p') {
+            pid = value.parse::<u32>().ok();
+        } else if let Some(name) = line.strip_prefix('c') {
+            if let Some(pid) = pid.take() {
+                holders.push(DeviceHolder { pid, name: name.to_string() });
+            }
+        }
+    }
+    
+    holders
+}
+
+#[cfg(target_os = "linux")]
+fn linux_disk_name(device_path: &str) -> Result<String> {
+    let resolved = std::fs::canonicalize(device_path)?;
+    resolved.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .ok_or_else(|| anyhow::anyhow!("Invalid device path: {}", device_path))
+}
+
+#[cfg(target_os = "linux")]
+fn linux_partition_names(disk: &str) -> Vec<String> {
+    let mut names = vec![disk.to_string()];
+    
+    if let Ok(entries) = std::fs::read_dir(format!("/sys/block/{}", disk)) {
+        for entry in entries.flatten() {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if name.starts_with(disk) && entry.path().join("partition").exists() {
+                names.push(name);
+            }
+        }
+    }
+    
+    names
+}
+
+#[cfg(target_os = "linux")]
+fn linux_partition_paths(device_path: &str) -> Vec<String> {
+    match linux_disk_name(device_path) {
+        Ok(disk) => linux_partition_names(&disk)
+            .into_iter()
+            .map(|name| format!("/dev/{}", name))
+            .collect(),
+        Err(_) => vec![device_path.to_string()],
+    }
+}
+
+// Mounts are matched by major:minor rather than by source path, so
+// /dev/disk/by-id symlinks and mapper names don't hide a mounted partition.
+#[cfg(target_os = "linux")]
+fn linux_block_numbers(device_path: &str) -> Result<Vec<String>> {
+    let disk = linux_disk_name(device_path)?;
+    let mut numbers = Vec::new();
+    
+    for name in linux_partition_names(&disk) {
+        if let Ok(dev) = std::fs::read_to_string(format!("/sys/class/block/{}/dev", name)) {
+            numbers.push(dev.trim().to_string());
+        }
+    }
+    
+    if numbers.is_empty() {
+        return Err(anyhow::anyhow!("{} is not a block device", device_path));
+    }
+    
+    Ok(numbers)
+}
+
+#[cfg(target_os = "linux")]
+fn scan_proc_for_holders(targets: &[String]) -> Vec<DeviceHolder> {
+    let mut holders = Vec::new();
+    let entries = match std::fs::read_dir("/proc") {
+        Ok(entries) => entries,
+        Err(_) => return holders,
+    };
+    
+    let matches = |link: &std::path::Path| {
+        let link = link.to_string_lossy();
+        targets.iter().any(|t| link == t.as_str() || link.starts_with(&format!("{}/", t)))
+    };
+    
+    for entry in entries.flatten() {
+        let pid = match entry.file_name().to_string_lossy().parse::<u32>() {
+            Ok(pid) if pid != std::process::id() => pid,
+            _ => continue,
+        };
+        let proc_dir = entry.path();
+        
+        let mut holding = std::fs::read_link(proc_dir.join("cwd")).map(|p| matches(&p)).unwrap_or(false);
+        if !holding {
+            if let Ok(fds) = std::fs::read_dir(proc_dir.join("fd")) {
+                holding = fds.flatten().any(|fd| std::fs::read_link(fd.path()).map(|p| matches(&p)).unwrap_or(false));
+            }
+        }
+        
+        if holding {
+            let name = std::fs::read_to_string(proc_dir.join("comm"))
+                .map(|s| s.trim().to_string())
+                .unwrap_or_else(|_| "unknown".to_string());
+            holders.push(DeviceHolder { pid, name });
+        }
+    }
+    
+    holders
+}
+
+#[derive(Debug, Clone)]
+pub struct PartitionMount {
+    pub mount_point: String,
+    temporary: bool,
+}
+
+// Mounts a partition we just created so folders can be written into it.
+// On Linux nothing automounts a fresh filesystem, so it goes to a private
+// temporary directory; macOS and Windows mount new volumes themselves.
+pub async fn mount_partition(runner: &impl CommandRunner, partition_path: &str, filesystem: &str) -> Result<PartitionMount> {
+    #[cfg(target_os = "linux")]
+    {
+        let mount_dir = std::env::temp_dir().join(format!("tesla-usb-{}", uuid::Uuid::new_v4()));
+        {
+            use std::os::unix::fs::DirBuilderExt;
+            std::fs::DirBuilder::new().mode(0o700).create(&mount_dir)?;
+        }
+        
+        let fs_type = match filesystem {
+            "fat32" => "vfat",
+            other => other,
+        };
+        let mount_point = mount_dir.to_string_lossy().into_owned();
+        
+        let output = runner.run("mount", &["-t", fs_type, partition_path, &mount_point]).await?;
+        
+        if !output.success {
+            let _ = std::fs::remove_dir(&mount_dir);
+            return Err(anyhow::anyhow!(
+                "Failed to mount {}: {}",
+                partition_path,
+                output.stderr.trim()
+            ));
+        }
+        
+        Ok(PartitionMount { mount_point, temporary: true })
+    }
+    
+    #[cfg(target_os = "macos")]
+    {
+        let _ = filesystem;
+        let output = runner.run("diskutil", &["mount", partition_path]).await?;
+        
+        if !output.success {
+            return Err(anyhow::anyhow!(
+                "Failed to mount {}: {}",
+                partition_path,
+                output.stderr.trim()
+            ));
+        }
+        
+        let mount_output = runner.run("mount", &[]).await?;
+        
+        parse_bsd_mount_output(&mount_output.stdout)
+            .into_iter()
+            .find(|entry| entry.source == partition_path)
+            .map(|entry| PartitionMount { mount_point: entry.mount_point, temporary: false })
+            .ok_or_else(|| anyhow::anyhow!("{} was not mounted", partition_path))
+    }
+    
+    #[cfg(target_os = "windows")]
+    {
+        let _ = (runner, filesystem);
+        Ok(PartitionMount {
+            mount_point: format!("{}\\", partition_path.trim_end_matches('\\')),
+            temporary: false,
+        })
+    }
+}
+
+pub async fn release_partition_mount(runner: &impl CommandRunner, mount: PartitionMount) -> Result<()> {
+    if !mount.temporary {
+        return Ok(());
+    }
+    
+    #[cfg(target_os = "linux")]
+    {
+        let output = runner.run("sync", &[]).await?;
+        
+        if !output.success {
+            return Err(anyhow::anyhow!("Failed to flush {}", mount.mount_point));
+        }
+        
+        let output = runner.run("umount", &[&mount.mount_point]).await?;
+        
+        if !output.success {
+            return Err(anyhow::anyhow!(
+                "Failed to unmount {}: {}",
+                mount.mount_point,
+                output.stderr.trim()
+            ));
+        }
+        
+        tokio::fs::remove_dir(&mount.mount_point).await?;
+    }
+    
+    #[cfg(not(target_os = "linux"))]
+    let _ = runner;
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn parses_mountinfo_with_escaped_paths() {
+        let entries = parse_mountinfo(include_str!("../tests/fixtures/linux/mountinfo.txt"));
+        
+        assert_eq!(entries.len(), 7);
+        let usb: Vec<&MountInfoEntry> = entries.iter().filter(|e| e.block_number.starts_with("8:")).collect();
+        assert_eq!(usb, vec![
+            &MountInfoEntry {
+                block_number: "8:17".to_string(),
+                mount_point: "/media/alex/TESLA DRIVE".to_string(),
+                filesystem: "exfat".to_string(),
+                source: "/dev/sdb1".to_string(),
+                read_only: false,
+            },
+            // Optional fields before the separator vary in number.
+            &MountInfoEntry {
+                block_number: "8:18".to_string(),
+                mount_point: "/media/alex/Music\tand\\Shows".to_string(),
+                filesystem: "vfat".to_string(),
+                source: "/dev/sdb2".to_string(),
+                read_only: true,
+            },
+        ]);
+        assert_eq!(entries[6].mount_point, "/mnt/sd card");
+        assert_eq!(entries[6].source, "/dev/mmcblk0p1");
+    }
+    
+    #[test]
+    fn unescapes_only_three_digit_octal_sequences() {
+        assert_eq!(unescape_mount_path("/media/TESLA\\040DRIVE"), "/media/TESLA DRIVE");
+        assert_eq!(unescape_mount_path("a\\012b\\134c"), "a\nb\\c");
+        assert_eq!(unescape_mount_path("/mnt/caf\\303\\251"), "/mnt/caf\u{e9}");
+        assert_eq!(unescape_mount_path("end\\040"), "end ");
+        // Not escapes: not octal, or cut short by the end of the field.
+        assert_eq!(unescape_mount_path("back\\slash\\09x\\04"), "back\\slash\\09x\\04");
+    }
+    
+    #[test]
+    fn matches_partitions_to_their_disk() {
+        for (disk, source) in [
+            ("/dev/disk4", "/dev/disk4s2"),
+            ("/dev/disk4", "/dev/rdisk4s1"),
+            ("/dev/disk4", "/dev/disk4"),
+            ("/dev/sda", "/dev/sda1"),
+            ("/dev/sdb", "/dev/sdb12"),
+            ("/dev/nvme0n1", "/dev/nvme0n1p1"),
+            ("/dev/mmcblk0", "/dev/mmcblk0p1"),
+        ] {
+            assert!(is_same_disk(disk, source), "{} {}", disk, source);
+        }
+        
+        for (disk, source) in [
+            ("/dev/disk4", "/dev/disk40s1"),
+            ("/dev/disk4", "/dev/disk4s"),
+            ("/dev/sda", "/dev/sdab1"),
+            ("/dev/sda", "/dev/sdb1"),
+            ("/dev/nvme0n1", "/dev/nvme0n10"),
+            ("/dev/nvme0n1", "/dev/nvme0n1p"),
+            ("/dev/mmcblk0", "/dev/mmcblk0boot0"),
+            ("/dev/mmcblk0", "/dev/sda1"),
+        ] {
+            assert!(!is_same_disk(disk, source), "{} {}", disk, source);
+        }
+    }
+    
+    #[test]
+    fn parses_bsd_mount_output_for_one_disk() {
+        let mounted: Vec<(String, String, String, bool)> = parse_bsd_mount_output(include_str!("../tests/fixtures/macos/mount.txt"))
+            .into_iter()
+            .filter(|entry| is_same_disk("/dev/disk4", &entry.source))
+            .map(|entry| (entry.source, entry.mount_point, entry.filesystem, entry.read_only))
+            .collect();
+        
+        assert_eq!(mounted, vec![
+            ("/dev/disk4s1".to_string(), "/Volumes/EFI".to_string(), "msdos".to_string(), false),
+            ("/dev/disk4s2".to_string(), "/Volumes/TeslaCam".to_string(), "exfat".to_string(), false),
+            ("/dev/disk4s3".to_string(), "/Volumes/Tesla Music".to_string(), "msdos".to_string(), true),
+        ]);
+    }
+    
+    #[test]
+    fn parses_lsof_fields_for_several_processes() {
+        let holders = parse_lsof_fields(include_str!("../tests/fixtures/macos/lsof_fields.txt"));
+        let holders: Vec<(u32, &str)> = holders.iter().map(|h| (h.pid, h.name.as_str())).collect();
+        
+        assert_eq!(holders, vec![(412, "Finder"), (98, "mds_stores"), (1203, "QuickTime Player")]);
+        assert!(parse_lsof_fields("").is_empty());
+        assert_eq!(describe_holders(&parse_lsof_fields("p7\ncbash\n")), ". Device is in use by: bash (7)");
+    }
+}
\ No newline at end of file
@@ -1,122 +1,196 @@
 use crate::{UsbDevice, PartitionConfig};
+use crate::command::CommandRunner;
+use crate::usb::DeviceIdentity;
 use anyhow::Result;
-use tokio::process::Command as TokioCommand;
+use serde::{Deserialize, Serialize};
 
-#[cfg(target_os = "windows")]
-use std::os::windows::process::CommandExt;
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CreatedPartition {
+    pub config: PartitionConfig,
+    pub path: String,
+    pub offset: u64,
+}
 
-pub async fn create_partitions(device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<()> {
+pub async fn create_partitions(
+    runner: &impl CommandRunner,
+    device: &UsbDevice,
+    expected: &DeviceIdentity,
+    partitions: &[PartitionConfig],
+) -> Result<Vec<CreatedPartition>> {
     validate_partition_config(device, partitions)?;
+    // Before unmounting anything, so a swapped stick is left alone.
+    crate::usb::verify_device_unchanged(runner, &device.path, expected).await?;
+    
+    if crate::target::is_image_file(&device.path) {
+        crate::signatures::wipe_signatures(&device.path, device.size).await?;
+        return crate::target::create_image_partitions(device, partitions).await;
+    }
+    
+    let lock = crate::mounts::prepare_device_for_write(runner, device).await?;
+    
+    // A new partition table alone leaves old superblocks and the backup GPT
+    // behind, and the OS may still detect the previous filesystem.
+    crate::signatures::wipe_signatures(&device.path, device.size).await?;
     
     #[cfg(target_os = "windows")]
     {
-        create_windows_partitions(device, partitions).await
+        // diskpart takes its own volume locks, so ours only guarantees the
+        // volume was dismounted and not in use when we started.
+        drop(lock);
+        create_windows_partitions(runner, device, partitions).await
     }
     
     #[cfg(target_os = "macos")]
     {
-        create_macos_partitions(device, partitions).await
+        // diskutil goes through DiskArbitration and needs the device node itself.
+        drop(lock);
+        create_macos_partitions(runner, device, partitions).await
     }
     
     #[cfg(target_os = "linux")]
     {
-        create_linux_partitions(device, partitions).await
+        let created = create_linux_partitions(runner, device, partitions).await?;
+        
+        // mkfs opens each partition exclusively, which the kernel refuses while
+        // the whole disk is held. Release it and unmount anything the desktop
+        // picked up from the new partition table.
+        drop(lock);
+        crate::mounts::unmount_all(runner, device).await?;
+        
+        format_linux_partitions(runner, &created).await?;
+        Ok(created)
     }
 }
 
 fn validate_partition_config(device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<()> {
+    for partition in partitions {
+        validate_label(&partition.name, &partition.filesystem)?;
+    }
+    
     let total_size: u64 = partitions.iter()
-        .map(|p| p.size_gb as u64 * 1024 * 1024 * 1024)
+        .map(|p| p.size_gb as u64 * GIB)
         .sum();
     
     if total_size > device.size {
         return Err(anyhow::anyhow!(
             "Total partition size ({} GB) exceeds device capacity ({} GB)",
-            total_size / (1024 * 1024 * 1024),
-            device.size / (1024 * 1024 * 1024)
+            total_size / GIB,
+            device.size / GIB
+        ));
+    }
+    
+    Ok(())
+}
+
+// Partitions are named after their volume label, which mkfs, diskutil and
+// diskpart all refuse past the filesystem's limit. Checked up front so a
+// bad name fails before the disk is wiped rather than halfway through.
+pub fn validate_label(name: &str, filesystem: &str) -> Result<()> {
+    let (units, max) = match filesystem {
+        "fat32" => (name.len(), 11),
+        "ntfs" => (name.encode_utf16().count(), 32),
+        "ext3" | "ext4" => (name.len(), 16),
+        "hfs+" => (name.encode_utf16().count(), 255),
+        _ => (name.encode_utf16().count(), 11),
+    };
+    
+    // FAT labels are stored in the OEM code page, so ASCII only.
+    if name.is_empty() || units > max || (filesystem == "fat32" && !name.is_ascii()) {
+        return Err(anyhow::anyhow!(
+            "Partition name \"{}\" is not a valid {} label (1 to {} characters)",
+            name,
+            filesystem,
+            max
         ));
     }
     
     Ok(())
 }
 
+const GIB: u64 = 1024 * 1024 * 1024;
+
 #[cfg(target_os = "windows")]
-async fn create_windows_partitions(device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<()> {
-    let disk_part_script = create_diskpart_script(device, partitions)?;
+async fn create_windows_partitions(runner: &impl CommandRunner, device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<Vec<CreatedPartition>> {
+    let disk_number = crate::windows_disks::resolve_disk_number(runner, &device.path).await?;
+    let letters = free_drive_letters(unsafe { winapi::um::fileapi::GetLogicalDrives() });
+    let disk_part_script = create_diskpart_script(disk_number, partitions, &letters)?;
     
-    let temp_file = std::env::temp_dir().join("diskpart_script.txt");
+    let temp_file = std::env::temp_dir().join(format!("diskpart-{}.txt", uuid::Uuid::new_v4()));
     tokio::fs::write(&temp_file, disk_part_script).await?;
     
-    let output = TokioCommand::new("diskpart")
-        .args(&["/s", temp_file.to_str().unwrap()])
-        .creation_flags(0x08000000)
-        .output()
-        .await?;
+    let output = runner.run("diskpart", &["/s", &temp_file.to_string_lossy()]).await;
     
     tokio::fs::remove_file(&temp_file).await?;
+    let output = output?;
     
-    if !output.status.success() {
-        return Err(anyhow::anyhow!(
-            "Diskpart failed: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
+    // diskpart reports its errors on stdout.
+    if !output.success {
+        return Err(anyhow::anyhow!("Diskpart failed: {}{}", output.stdout.trim(), output.stderr.trim()));
     }
     
-    Ok(())
+    Ok(partitions.iter()
+        .zip(&letters)
+        .map(|(partition, letter)| CreatedPartition {
+            config: partition.clone(),
+            path: format!("{}:", letter),
+            offset: 0,
+        })
+        .collect())
 }
 
-#[cfg(target_os = "windows")]
-fn create_diskpart_script(device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<String> {
+// After "create partition" the new partition has focus, so format and
+// assign apply to it directly. Selecting by index would be off by one,
+// since "convert gpt" adds a Microsoft Reserved partition first.
+#[cfg(any(target_os = "windows", test))]
+fn create_diskpart_script(disk_number: u32, partitions: &[PartitionConfig], letters: &[char]) -> Result<String> {
+    if letters.len() < partitions.len() {
+        return Err(anyhow::anyhow!(
+            "Not enough free drive letters for {} partitions",
+            partitions.len()
+        ));
+    }
+    
     let mut script = String::new();
     
-    script.push_str(&format!("select disk {}\n", extract_disk_number(&device.path)?));
+    script.push_str(&format!("select disk {}\n", disk_number));
     script.push_str("clean\n");
     script.push_str("convert gpt\n");
     
-    for (i, partition) in partitions.iter().enumerate() {
-        script.push_str(&format!("create partition primary size={}\n", partition.size_gb * 1024));
-        script.push_str(&format!("select partition {}\n", i + 1));
-        script.push_str("active\n");
-        
-        let format_cmd = match partition.filesystem.as_str() {
-            "exfat" => "format fs=exfat quick",
-            "fat32" => "format fs=fat32 quick",
-            "ntfs" => "format fs=ntfs quick",
-            _ => "format fs=exfat quick",
+    for (partition, letter) in partitions.iter().zip(letters) {
+        let filesystem = match partition.filesystem.as_str() {
+            "fat32" => "fat32",
+            "ntfs" => "ntfs",
+            _ => "exfat",
         };
         
-        script.push_str(&format!("{}\n", format_cmd));
-        script.push_str(&format!("assign letter={}\n", get_available_drive_letter()));
-        script.push_str(&format!("label={}\n", partition.name));
+        validate_label(&partition.name, filesystem)?;
+        
+        if partition.name.contains('"') {
+            return Err(anyhow::anyhow!("Partition name {} can't contain quotes", partition.name));
+        }
+        
+        script.push_str(&format!("create partition primary size={}\n", partition.size_gb as u64 * 1024));
+        script.push_str(&format!("format fs={} label=\"{}\" quick\n", filesystem, partition.name));
+        script.push_str(&format!("assign letter={}\n", letter));
     }
     
     script.push_str("exit\n");
     Ok(script)
 }
 
-#[cfg(target_os = "windows")]
-fn extract_disk_number(path: &str) -> Result<u32> {
-    Ok(0)
-}
-
-#[cfg(target_os = "windows")]
-fn get_available_drive_letter() -> char {
-    'Z'
+// Letters not set in the GetLogicalDrives() mask, skipping A-C.
+#[cfg(any(target_os = "windows", test))]
+fn free_drive_letters(used: u32) -> Vec<char> {
+    ('D'..='Z')
+        .filter(|letter| used & (1 << (*letter as u32 - 'A' as u32)) == 0)
+        .collect()
 }
 
-#[cfg(target_os = "macos")]
-async fn create_macos_partitions(device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<()> {
-    let output = TokioCommand::new("diskutil")
-        .args(&["eraseDisk", "GPT", "TempName", &device.path])
-        .output()
-        .await?;
-    
-    if !output.status.success() {
-        return Err(anyhow::anyhow!(
-            "Failed to erase disk: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ));
-    }
+// A single partitionDisk call lays out the whole disk; running it once per
+// partition would wipe the previous ones each time.
+#[cfg(any(target_os = "macos", test))]
+async fn create_macos_partitions(runner: &impl CommandRunner, device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<Vec<CreatedPartition>> {
+    let mut args = vec!["partitionDisk".to_string(), device.path.clone(), "GPT".to_string()];
     
     for partition in partitions {
         let filesystem = match partition.filesystem.as_str() {
@@ -126,89 +200,116 @@ async fn create_macos_partitions(device: &UsbDevice, partitions: &[PartitionConf
             _ => "ExFAT",
         };
         
-        let output = TokioCommand::new("diskutil")
-            .args(&[
-                "partitionDisk",
-                &device.path,
-                "GPT",
-                filesystem,
-                &partition.name,
-                &format!("{}GB", partition.size_gb)
-            ])
-            .output()
-            .await?;
-        
-        if !output.status.success() {
-            return Err(anyhow::anyhow!(
-                "Failed to create partition {}: {}",
-                partition.name,
-                String::from_utf8_lossy(&output.stderr)
-            ));
-        }
+        // Exact byte sizes, since diskutil reads "GB" as powers of ten.
+        args.push(filesystem.to_string());
+        args.push(partition.name.clone());
+        args.push(format!("{}B", partition.size_gb as u64 * GIB));
     }
     
-    Ok(())
-}
-
-#[cfg(target_os = "linux")]
-async fn create_linux_partitions(device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<()> {
-    let output = TokioCommand::new("parted")
-        .args(&[&device.path, "mklabel", "gpt"])
-        .output()
-        .await?;
-    
-    if !output.status.success() {
+    let used: u64 = partitions.iter().map(|p| p.size_gb as u64 * GIB).sum();
+    if used < device.size {
+        args.extend(["free".to_string(), "Unused".to_string(), "R".to_string()]);
+    }
+    
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    let output = runner.run("diskutil", &args).await?;
+    
+    if !output.success {
         return Err(anyhow::anyhow!(
-            "Failed to create GPT label: {}",
-            String::from_utf8_lossy(&output.stderr)
+            "Failed to partition {}: {}",
+            device.path,
+            output.stderr.trim()
         ));
     }
     
-    let mut start_sector = 1;
+    // diskutil puts the EFI system partition at slice 1, so data starts at 2.
+    Ok(partitions.iter()
+        .enumerate()
+        .map(|(i, partition)| CreatedPartition {
+            config: partition.clone(),
+            path: format!("{}s{}", device.path, i + 2),
+            offset: 0,
+        })
+        .collect())
+}
+
+// Writes the GPT and partition entries. Partitions start on 1 MiB
+// boundaries, which is what flash controllers and the car expect.
+#[cfg(any(target_os = "linux", test))]
+async fn create_linux_partitions(runner: &impl CommandRunner, device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<Vec<CreatedPartition>> {
+    const ALIGNMENT: u64 = 2048;
     
-    for partition in partitions {
-        let end_sector = start_sector + (partition.size_gb * 1024 * 1024 * 1024 / 512);
-        
-        let output = TokioCommand::new("parted")
-            .args(&[
-                &device.path,
-                "mkpart",
-                "primary",
-                &format!("{}s", start_sector),
-                &format!("{}s", end_sector)
-            ])
-            .output()
-            .await?;
-        
-        if !output.status.success() {
+    let output = runner.run("parted", &["-s", &device.path, "mklabel", "gpt"]).await?;
+    
+    if !output.success {
+        return Err(anyhow::anyhow!("Failed to create GPT label: {}", output.stderr.trim()));
+    }
+    
+    let mut start_sector = ALIGNMENT;
+    let mut created = Vec::new();
+    
+    for (i, partition) in partitions.iter().enumerate() {
+        let end_sector = start_sector + partition.size_gb as u64 * GIB / 512 - 1;
+        
+        // For GPT the first mkpart argument is the partition name. The
+        // filesystem hint picks the Basic Data type for FAT and exFAT.
+        let fs_hint = match partition.filesystem.as_str() {
+            "ext4" => "ext4",
+            _ => "fat32",
+        };
+        
+        let output = runner.run("parted", &[
+            "-s",
+            &device.path,
+            "mkpart",
+            &partition.name,
+            fs_hint,
+            &format!("{}s", start_sector),
+            &format!("{}s", end_sector),
+        ]).await?;
+        
+        if !output.success {
             return Err(anyhow::anyhow!(
-                "Failed to create partition: {}",
-                String::from_utf8_lossy(&output.stderr)
+                "Failed to create partition {}: {}",
+                partition.name,
+                output.stderr.trim()
             ));
         }
         
-        start_sector = end_sector + 1;
+        created.push(CreatedPartition {
+            config: partition.clone(),
+            path: linux_partition_path(&device.path, i + 1),
+            offset: 0,
+        });
+        
+        start_sector = (end_sector + 1).div_ceil(ALIGNMENT) * ALIGNMENT;
     }
     
-    for (i, partition) in partitions.iter().enumerate() {
-        let partition_path = format!("{}p{}", device.path, i + 1);
-        let mkfs_cmd = match partition.filesystem.as_str() {
-            "exfat" => "mkfs.exfat",
-            "fat32" => "mkfs.fat",
-            "ext4" => "mkfs.ext4",
-            _ => "mkfs.exfat",
+    // Wait for udev to create the new partition nodes before formatting.
+    runner.run("udevadm", &["settle"]).await?;
+    
+    Ok(created)
+}
+
+#[cfg(any(target_os = "linux", test))]
+async fn format_linux_partitions(runner: &impl CommandRunner, created: &[CreatedPartition]) -> Result<()> {
+    for partition in created {
+        let name = partition.config.name.as_str();
+        let path = partition.path.as_str();
+        
+        let (program, args): (&str, Vec<&str>) = match partition.config.filesystem.as_str() {
+            "fat32" => ("mkfs.fat", vec!["-F", "32", "-n", name, path]),
+            "ext4" => ("mkfs.ext4", vec!["-F", "-L", name, path]),
+            _ => ("mkfs.exfat", vec!["-L", name, path]),
         };
         
-        let output = TokioCommand::new(mkfs_cmd)
-            .args(&["-L", &partition.name, &partition_path])
-            .output()
-            .await?;
+        let output = runner.run(program, &args).await?;
         
-        if !output.status.success() {
+        if !output.success {
             return Err(anyhow::anyhow!(
                 "Failed to format partition {}: {}",
-                partition.name,
-                String::from_utf8_lossy(&output.stderr)
+                name,
+                output.stderr.trim()
             ));
         }
     }
@@ -216,6 +317,16 @@ async fn create_linux_partitions(device: &UsbDevice, partitions: &[PartitionConf
     Ok(())
 }
 
+// /dev/sdb -> /dev/sdb1, but /dev/mmcblk0 and /dev/nvme0n1 -> ...p1
+#[cfg(any(target_os = "linux", test))]
+fn linux_partition_path(device_path: &str, number: usize) -> String {
+    if device_path.ends_with(|c: char| c.is_ascii_digit()) {
+        format!("{}p{}", device_path, number)
+    } else {
+        format!("{}{}", device_path, number)
+    }
+}
+
 pub fn get_recommended_tesla_partitions(total_size_gb: u32) -> Vec<PartitionConfig> {
     let mut partitions = Vec::new();
     
@@ -241,7 +352,7 @@ pub fn get_recommended_tesla_partitions(total_size_gb: u32) -> Vec<PartitionConf
         let lightshow_size = remaining - music_size;
         if lightshow_size >= 2 {
             partitions.push(PartitionConfig {
-                name: "TeslaLightshow".to_string(),
+                name: "LightShow".to_string(),
                 size_gb: lightshow_size,
                 filesystem: "exfat".to_string(),
                 purpose: "Lightshow files".to_string(),
@@ -250,4 +361,203 @@ pub fn get_recommended_tesla_partitions(total_size_gb: u32) -> Vec<PartitionConf
     }
     
     partitions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::FakeRunner;
+    
+    fn device(path: &str, size_gb: u64) -> UsbDevice {
+        UsbDevice {
+            name: "Test stick".to_string(),
+            path: path.to_string(),
+            size: size_gb * GIB,
+            is_removable: true,
+        }
+    }
+    
+    fn partition(name: &str, size_gb: u32, filesystem: &str) -> PartitionConfig {
+        PartitionConfig {
+            name: name.to_string(),
+            size_gb,
+            filesystem: filesystem.to_string(),
+            purpose: String::new(),
+        }
+    }
+    
+    // What format_for_tesla creates.
+    fn tesla_layout() -> Vec<PartitionConfig> {
+        vec![
+            partition("TeslaCam", 32, "exfat"),
+            partition("TeslaMusic", 16, "exfat"),
+            partition("LightShow", 8, "exfat"),
+        ]
+    }
+    
+    #[tokio::test]
+    async fn linux_partitions_are_aligned_and_named() {
+        let runner = FakeRunner::new();
+        
+        let created = create_linux_partitions(&runner, &device("/dev/sdb", 64), &tesla_layout()).await.unwrap();
+        
+        runner.assert_calls(include_str!("../tests/fixtures/linux/create_partitions_calls.json"));
+        let paths: Vec<&str> = created.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(paths, vec!["/dev/sdb1", "/dev/sdb2", "/dev/sdb3"]);
+    }
+    
+    #[tokio::test]
+    async fn linux_partitions_use_p_suffix_after_digits() {
+        let runner = FakeRunner::new();
+        
+        let created = create_linux_partitions(&runner, &device("/dev/mmcblk0", 64), &tesla_layout()[..1]).await.unwrap();
+        
+        assert_eq!(created[0].path, "/dev/mmcblk0p1");
+    }
+    
+    #[tokio::test]
+    async fn linux_partitioning_stops_at_first_parted_failure() {
+        let runner = FakeRunner::new()
+            .fail(&["parted", "-s", "/dev/sdb", "mklabel", "gpt"], "Error: Partition(s) on /dev/sdb are being used.");
+        
+        let error = create_linux_partitions(&runner, &device("/dev/sdb", 64), &tesla_layout()).await.unwrap_err();
+        
+        assert!(error.to_string().contains("being used"));
+        assert_eq!(runner.calls().len(), 1);
+    }
+    
+    #[tokio::test]
+    async fn linux_formatting_matches_the_tesla_layout() {
+        let runner = FakeRunner::new();
+        let created = create_linux_partitions(&FakeRunner::new(), &device("/dev/sdb", 64), &tesla_layout()).await.unwrap();
+        
+        format_linux_partitions(&runner, &created).await.unwrap();
+        
+        runner.assert_calls(include_str!("../tests/fixtures/linux/format_partitions_calls.json"));
+    }
+    
+    #[tokio::test]
+    async fn linux_formatting_uses_filesystem_specific_flags() {
+        let runner = FakeRunner::new();
+        let layout = vec![partition("MUSIC", 16, "fat32"), partition("Backup", 8, "ext4")];
+        let created = create_linux_partitions(&FakeRunner::new(), &device("/dev/sdb", 64), &layout).await.unwrap();
+        
+        format_linux_partitions(&runner, &created).await.unwrap();
+        
+        assert_eq!(runner.calls(), vec![
+            vec!["mkfs.fat", "-F", "32", "-n", "MUSIC", "/dev/sdb1"],
+            vec!["mkfs.ext4", "-F", "-L", "Backup", "/dev/sdb2"],
+        ]);
+    }
+    
+    #[tokio::test]
+    async fn labels_are_checked_before_the_disk_is_touched() {
+        let runner = FakeRunner::new();
+        let layout = vec![partition("TeslaCam", 32, "exfat"), partition("TeslaLightshow", 8, "exfat")];
+        let identity = DeviceIdentity {
+            serial: None,
+            size: 64 * GIB,
+            layout: Vec::new(),
+        };
+        
+        let error = create_partitions(&runner, &device("/dev/sdb", 64), &identity, &layout).await.unwrap_err();
+        
+        assert!(error.to_string().contains("TeslaLightshow"), "{}", error);
+        assert!(runner.calls().is_empty());
+        assert!(validate_label("TeslaLights", "exfat").is_ok());
+        assert!(validate_label("TeslaLightshow", "ext4").is_ok());
+        assert!(validate_label("", "fat32").is_err());
+    }
+    
+    #[tokio::test]
+    async fn macos_partitions_are_created_in_one_call() {
+        let runner = FakeRunner::new();
+        
+        let created = create_macos_partitions(&runner, &device("/dev/disk4", 64), &tesla_layout()).await.unwrap();
+        
+        runner.assert_calls(include_str!("../tests/fixtures/macos/create_partitions_calls.json"));
+        let paths: Vec<&str> = created.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(paths, vec!["/dev/disk4s2", "/dev/disk4s3", "/dev/disk4s4"]);
+    }
+    
+    #[tokio::test]
+    async fn macos_full_disk_layout_leaves_no_free_space() {
+        let runner = FakeRunner::new();
+        
+        create_macos_partitions(&runner, &device("/dev/disk4", 32), &tesla_layout()[..1]).await.unwrap();
+        
+        let call = &runner.calls()[0];
+        assert_eq!(call.last().map(String::as_str), Some("34359738368B"));
+    }
+    
+    #[test]
+    fn diskpart_script_matches_golden_file() {
+        let script = create_diskpart_script(2, &tesla_layout()[..2], &['E', 'F']).unwrap();
+        
+        assert_eq!(script, include_str!("../tests/fixtures/windows/diskpart_tesla.txt"));
+    }
+    
+    #[test]
+    fn diskpart_script_formats_each_filesystem() {
+        let partitions = vec![
+            partition("TeslaCam", 64, "exfat"),
+            partition("MUSIC", 32, "fat32"),
+            partition("Backup", 16, "ntfs"),
+        ];
+        
+        let script = create_diskpart_script(1, &partitions, &['G', 'H', 'K']).unwrap();
+        
+        assert_eq!(script, include_str!("../tests/fixtures/windows/diskpart_filesystems.txt"));
+    }
+    
+    #[test]
+    fn diskpart_script_rejects_labels_too_long_for_fat32() {
+        let partitions = vec![partition("TeslaLightshow", 8, "fat32")];
+        
+        assert!(create_diskpart_script(1, &partitions, &['E']).is_err());
+    }
+    
+    #[test]
+    fn diskpart_script_rejects_labels_too_long_for_exfat() {
+        let partitions = vec![partition("TeslaLightshow", 8, "exfat")];
+        
+        assert!(create_diskpart_script(1, &partitions, &['E']).is_err());
+        assert!(create_diskpart_script(1, &[partition("TeslaLights", 8, "exfat")], &['E']).is_ok());
+    }
+    
+    #[test]
+    fn diskpart_script_needs_a_letter_per_partition() {
+        assert!(create_diskpart_script(1, &tesla_layout(), &['E']).is_err());
+    }
+    
+    #[test]
+    fn free_drive_letters_skip_used_and_floppy_letters() {
+        // A:, C:, D: and F: in use.
+        let used = 0b101101;
+        
+        let letters = free_drive_letters(used);
+        
+        assert_eq!(letters.first(), Some(&'E'));
+        assert_eq!(letters[1], 'G');
+        assert_eq!(letters.last(), Some(&'Z'));
+        assert_eq!(letters.len(), 21);
+    }
+    
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn swapped_drives_are_refused_before_anything_is_unmounted() {
+        let lsblk = ["lsblk", "-J", "-b", "-o", "NAME,SERIAL,SIZE,FSTYPE,LABEL,TYPE", "/dev/sdb"];
+        let runner = FakeRunner::new()
+            .respond(&lsblk, r#"{"blockdevices": [{"name": "sdb", "serial": "OTHER", "size": 68719476736, "type": "disk"}]}"#);
+        let selected = DeviceIdentity {
+            serial: Some("SELECTED".to_string()),
+            size: 64 * GIB,
+            layout: Vec::new(),
+        };
+        
+        let error = create_partitions(&runner, &device("/dev/sdb", 64), &selected, &tesla_layout()).await.unwrap_err();
+        
+        assert!(error.to_string().contains("Device changed since selection"), "{}", error);
+        assert_eq!(runner.calls(), vec![lsblk.to_vec()]);
+    }
 }
\ No newline at end of file
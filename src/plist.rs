@@ -0,0 +1,314 @@
+use anyhow::Result;
+
+// Just enough of the XML property list format to read what diskutil
+// prints with -plist: dictionaries, arrays, strings, numbers, booleans,
+// data and dates.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub enum Plist {
+    Dict(Vec<(String, Plist)>),
+    Array(Vec<Plist>),
+    String(String),
+    Integer(i64),
+    Real(f64),
+    Bool(bool),
+    Data(String),
+    Date(String),
+}
+
+impl Plist {
+    pub fn get(&self, key: &str) -> Option<&Plist> {
+        match self {
+            Plist::Dict(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+    
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Plist::String(s) => Some(s),
+            _ => None,
+        }
+    }
+    
+    pub fn as_u64(&self) -> Option<u64> {
+        match self {
+            Plist::Integer(i) => u64::try_from(*i).ok(),
+            _ => None,
+        }
+    }
+    
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Plist::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+    
+    pub fn as_array(&self) -> Option<&[Plist]> {
+        match self {
+            Plist::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+    
+    pub fn str_at(&self, key: &str) -> Option<&str> {
+        self.get(key).and_then(Plist::as_str)
+    }
+    
+    pub fn u64_at(&self, key: &str) -> Option<u64> {
+        self.get(key).and_then(Plist::as_u64)
+    }
+    
+    pub fn bool_at(&self, key: &str) -> Option<bool> {
+        self.get(key).and_then(Plist::as_bool)
+    }
+    
+    pub fn array_at(&self, key: &str) -> &[Plist] {
+        self.get(key).and_then(Plist::as_array).unwrap_or(&[])
+    }
+}
+
+pub fn parse(xml: &str) -> Result<Plist> {
+    let mut parser = Parser { input: xml, pos: 0 };
+    
+    loop {
+        match parser.next_tag()? {
+            Some(Tag::Open(name)) if name == "plist" => break,
+            Some(_) => continue,
+            None => return Err(anyhow::anyhow!("Not a property list: no <plist> element")),
+        }
+    }
+    
+    match parser.next_tag()? {
+        Some(tag) => parser.value(tag),
+        None => Err(anyhow::anyhow!("Empty property list")),
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Tag {
+    Open(String),
+    Close(String),
+    Empty(String),
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    // Skips text, comments, the XML declaration and the DOCTYPE, and
+    // returns the next element tag.
+    fn next_tag(&mut self) -> Result<Option<Tag>> {
+        loop {
+            let Some(start) = self.input[self.pos..].find('<') else {
+                return Ok(None);
+            };
+            let rest = &self.input[self.pos + start..];
+            
+            let skip_to = if rest.starts_with("<!--") {
+                Some("-->")
+            } else if rest.starts_with("<?") {
+                Some("?>")
+            } else if rest.starts_with("<!") {
+                Some(">")
+            } else {
+                None
+            };
+            
+            if let Some(terminator) = skip_to {
+                let end = rest.find(terminator)
+                    .ok_or_else(|| anyhow::anyhow!("Unterminated markup in property list"))?;
+                self.pos += start + end + terminator.len();
+                continue;
+            }
+            
+            let end = rest.find('>')
+                .ok_or_else(|| anyhow::anyhow!("Unterminated tag in property list"))?;
+            let inner = &rest[1..end];
+            self.pos += start + end + 1;
+            
+            let tag = if let Some(name) = inner.strip_prefix('/') {
+                Tag::Close(name.trim().to_string())
+            } else if let Some(body) = inner.strip_suffix('/') {
+                Tag::Empty(tag_name(body))
+            } else {
+                Tag::Open(tag_name(inner))
+            };
+            return Ok(Some(tag));
+        }
+    }
+    
+    fn text_until_close(&mut self, name: &str) -> Result<String> {
+        let close = format!("</{}>", name);
+        let end = self.input[self.pos..].find(&close)
+            .ok_or_else(|| anyhow::anyhow!("Missing {} in property list", close))?;
+        let text = unescape(&self.input[self.pos..self.pos + end])?;
+        self.pos += end + close.len();
+        Ok(text)
+    }
+    
+    fn value(&mut self, tag: Tag) -> Result<Plist> {
+        match tag {
+            Tag::Empty(name) => match name.as_str() {
+                "true" => Ok(Plist::Bool(true)),
+                "false" => Ok(Plist::Bool(false)),
+                "string" => Ok(Plist::String(String::new())),
+                "data" => Ok(Plist::Data(String::new())),
+                "array" => Ok(Plist::Array(Vec::new())),
+                "dict" => Ok(Plist::Dict(Vec::new())),
+                other => Err(anyhow::anyhow!("Unexpected <{}/> in property list", other)),
+            },
+            Tag::Open(name) => match name.as_str() {
+                "string" => Ok(Plist::String(self.text_until_close("string")?)),
+                "data" => Ok(Plist::Data(self.text_until_close("data")?.split_whitespace().collect())),
+                "date" => Ok(Plist::Date(self.text_until_close("date")?)),
+                "integer" => {
+                    let text = self.text_until_close("integer")?;
+                    let text = text.trim();
+                    let value = match text.strip_prefix("0x") {
+                        Some(hex) => i64::from_str_radix(hex, 16),
+                        None => text.parse(),
+                    };
+                    Ok(Plist::Integer(value.map_err(|_| anyhow::anyhow!("Invalid integer {} in property list", text))?))
+                }
+                "real" => {
+                    let text = self.text_until_close("real")?;
+                    Ok(Plist::Real(text.trim().parse().map_err(|_| anyhow::anyhow!("Invalid real {} in property list", text.trim()))?))
+                }
+                "true" | "false" => {
+                    self.expect_close(&name)?;
+                    Ok(Plist::Bool(name == "true"))
+                }
+                "array" => {
+                    let mut items = Vec::new();
+                    loop {
+                        match self.next_tag()? {
+                            Some(Tag::Close(close)) if close == "array" => break,
+                            Some(tag) => items.push(self.value(tag)?),
+                            None => return Err(anyhow::anyhow!("Unterminated <array> in property list")),
+                        }
+                    }
+                    Ok(Plist::Array(items))
+                }
+                "dict" => {
+                    let mut entries = Vec::new();
+                    loop {
+                        let key = match self.next_tag()? {
+                            Some(Tag::Close(close)) if close == "dict" => break,
+                            Some(Tag::Open(open)) if open == "key" => self.text_until_close("key")?,
+                            Some(Tag::Empty(empty)) if empty == "key" => String::new(),
+                            other => return Err(anyhow::anyhow!("Expected <key> in property list, found {:?}", other)),
+                        };
+                        let value = match self.next_tag()? {
+                            Some(tag) => self.value(tag)?,
+                            None => return Err(anyhow::anyhow!("Missing value for key {} in property list", key)),
+                        };
+                        entries.push((key, value));
+                    }
+                    Ok(Plist::Dict(entries))
+                }
+                other => Err(anyhow::anyhow!("Unexpected <{}> in property list", other)),
+            },
+            Tag::Close(name) => Err(anyhow::anyhow!("Unexpected </{}> in property list", name)),
+        }
+    }
+    
+    fn expect_close(&mut self, name: &str) -> Result<()> {
+        match self.next_tag()? {
+            Some(Tag::Close(close)) if close == name => Ok(()),
+            other => Err(anyhow::anyhow!("Expected </{}> in property list, found {:?}", name, other)),
+        }
+    }
+}
+
+fn tag_name(body: &str) -> String {
+    body.split_whitespace().next().unwrap_or("").to_string()
+}
+
+fn unescape(text: &str) -> Result<String> {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    
+    while let Some(start) = rest.find('&') {
+        result.push_str(&rest[..start]);
+        let end = rest[start..].find(';')
+            .ok_or_else(|| anyhow::anyhow!("Unterminated entity in property list"))?;
+        let entity = &rest[start + 1..start + end];
+        
+        let decoded = match entity {
+            "amp" => Some('&'),
+            "lt" => Some('<'),
+            "gt" => Some('>'),
+            "quot" => Some('"'),
+            "apos" => Some('\''),
+            _ => {
+                let code = if let Some(hex) = entity.strip_prefix("#x") {
+                    u32::from_str_radix(hex, 16).ok()
+                } else if let Some(dec) = entity.strip_prefix('#') {
+                    dec.parse().ok()
+                } else {
+                    None
+                };
+                code.and_then(char::from_u32)
+            }
+        };
+        
+        result.push(decoded.ok_or_else(|| anyhow::anyhow!("Unknown entity &{}; in property list", entity))?);
+        rest = &rest[start + end + 1..];
+    }
+    
+    result.push_str(rest);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn parses_nested_values() {
+        let plist = parse(r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<!-- a comment -->
+	<key>Name</key>
+	<string>Tesla &amp; Friends &#x263A;</string>
+	<key>Size</key>
+	<integer>61530439680</integer>
+	<key>Ratio</key>
+	<real>0.5</real>
+	<key>Internal</key>
+	<false/>
+	<key>Empty</key>
+	<string/>
+	<key>Items</key>
+	<array>
+		<dict>
+			<key>Id</key>
+			<string>disk4s1</string>
+		</dict>
+		<array/>
+	</array>
+</dict>
+</plist>"#).unwrap();
+
+        assert_eq!(plist.str_at("Name"), Some("Tesla & Friends \u{263A}"));
+        assert_eq!(plist.u64_at("Size"), Some(61530439680));
+        assert_eq!(plist.get("Ratio"), Some(&Plist::Real(0.5)));
+        assert_eq!(plist.bool_at("Internal"), Some(false));
+        assert_eq!(plist.str_at("Empty"), Some(""));
+        assert_eq!(plist.array_at("Items").len(), 2);
+        assert_eq!(plist.array_at("Items")[0].str_at("Id"), Some("disk4s1"));
+        assert_eq!(plist.array_at("Missing").len(), 0);
+    }
+    
+    #[test]
+    fn rejects_truncated_input() {
+        assert!(parse("<plist version=\"1.0\"><dict><key>Size</key><integer>1</integer>").is_err());
+        assert!(parse("Could not find disk: disk9").is_err());
+    }
+}
\ No newline at end of file
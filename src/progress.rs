@@ -0,0 +1,85 @@
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+// Event the UI listens on for every long-running job.
+pub const PROGRESS_EVENT: &str = "job-progress";
+
+const MIN_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JobProgress {
+    pub job_id: String,
+    pub job: String,
+    pub phase: String,
+    pub done: u64,
+    pub total: u64,
+}
+
+// Reports progress of one job. Updates are throttled so tight loops can call
+// `update` for every block without flooding the UI; the first update of a
+// phase and its completion are always delivered. Clones report to the same
+// job, so one can be handed to a blocking task.
+#[derive(Clone)]
+pub struct Progress {
+    job_id: String,
+    job: String,
+    sink: Arc<dyn Fn(&JobProgress) + Send + Sync>,
+    last: Arc<Mutex<Option<(String, Instant)>>>,
+}
+
+impl Progress {
+    pub fn new(job: &str, sink: impl Fn(&JobProgress) + Send + Sync + 'static) -> Self {
+        Self {
+            job_id: uuid::Uuid::new_v4().to_string(),
+            job: job.to_string(),
+            sink: Arc::new(sink),
+            last: Arc::new(Mutex::new(None)),
+        }
+    }
+    
+    // Emits as `PROGRESS_EVENT` on the given window.
+    pub fn for_window(job: &str, window: tauri::Window) -> Self {
+        Self::new(job, move |progress| {
+            let _ = window.emit(PROGRESS_EVENT, progress.clone());
+        })
+    }
+    
+    pub fn job_id(&self) -> &str {
+        &self.job_id
+    }
+    
+    pub fn update(&self, phase: &str, done: u64, total: u64) {
+        {
+            let mut last = self.last.lock().unwrap();
+            let now = Instant::now();
+            let due = match last.as_ref() {
+                Some((last_phase, at)) => last_phase != phase || done >= total || now.duration_since(*at) >= MIN_INTERVAL,
+                None => true,
+            };
+            if !due {
+                return;
+            }
+            *last = Some((phase.to_string(), now));
+        }
+        
+        (self.sink)(&JobProgress {
+            job_id: self.job_id.clone(),
+            job: self.job.clone(),
+            phase: phase.to_string(),
+            done,
+            total,
+        });
+    }
+}
+
+#[cfg(test)]
+impl Progress {
+    // Collects every delivered update, for asserting on in tests.
+    pub fn recording(job: &str) -> (Self, Arc<Mutex<Vec<JobProgress>>>) {
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let sink = events.clone();
+        let progress = Self::new(job, move |p| sink.lock().unwrap().push(p.clone()));
+        (progress, events)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,187 @@
+use crate::clips::Camera;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+const GIB: u64 = 1024 * 1024 * 1024;
+
+// Per camera, as measured on recent firmware: a one-minute clip is
+// typically 30-40 MB.
+const DEFAULT_BITRATE_MBPS: f64 = 4.5;
+// Honking, tapping the dashcam icon or a Sentry trigger all keep the
+// preceding ten minutes.
+const SAVED_CLIP_MINUTES: f64 = 10.0;
+const SENTRY_EVENT_MINUTES: f64 = 10.0;
+// exFAT metadata, plus the headroom the car keeps free before it starts
+// recycling RecentClips.
+const USABLE_FRACTION: f64 = 0.95;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VehicleProfile {
+    // Front, back and both repeaters.
+    #[default]
+    FourCamera,
+    // Newer cars that also record the B-pillar cameras.
+    SixCamera,
+}
+
+impl VehicleProfile {
+    pub fn cameras(self) -> &'static [Camera] {
+        match self {
+            VehicleProfile::FourCamera => &[Camera::Front, Camera::Back, Camera::LeftRepeater, Camera::RightRepeater],
+            VehicleProfile::SixCamera => &[
+                Camera::Front,
+                Camera::Back,
+                Camera::LeftRepeater,
+                Camera::RightRepeater,
+                Camera::LeftPillar,
+                Camera::RightPillar,
+            ],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Recording {
+    #[serde(default)]
+    pub vehicle: VehicleProfile,
+    // Megabits per second, per camera.
+    #[serde(default = "default_bitrate")]
+    pub bitrate_mbps: f64,
+}
+
+impl Default for Recording {
+    fn default() -> Self {
+        Self {
+            vehicle: VehicleProfile::default(),
+            bitrate_mbps: DEFAULT_BITRATE_MBPS,
+        }
+    }
+}
+
+fn default_bitrate() -> f64 {
+    DEFAULT_BITRATE_MBPS
+}
+
+impl Recording {
+    // Footage written per minute across every camera.
+    pub fn bytes_per_minute(&self) -> f64 {
+        self.bitrate_mbps * 1_000_000.0 / 8.0 * 60.0 * self.vehicle.cameras().len() as f64
+    }
+}
+
+// Each figure assumes the whole TeslaCam partition goes to that kind of
+// footage; they share the same space.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RetentionEstimate {
+    pub dashcam_size_gb: u32,
+    pub cameras: usize,
+    pub usable_bytes: u64,
+    pub bytes_per_minute: u64,
+    pub recent_hours: f64,
+    pub sentry_events: u64,
+    pub sentry_hours: f64,
+    pub saved_clips: u64,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RetentionGoal {
+    pub recent_hours: f64,
+    pub sentry_events: u64,
+    pub saved_clips: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SizeSuggestion {
+    pub dashcam_size_gb: u32,
+    pub estimate: RetentionEstimate,
+}
+
+pub fn estimate_retention(dashcam_size_gb: u32, recording: &Recording) -> Result<RetentionEstimate> {
+    let per_minute = validate(recording)?;
+    let usable = dashcam_size_gb as f64 * GIB as f64 * USABLE_FRACTION;
+    let minutes = usable / per_minute;
+    
+    Ok(RetentionEstimate {
+        dashcam_size_gb,
+        cameras: recording.vehicle.cameras().len(),
+        usable_bytes: usable as u64,
+        bytes_per_minute: per_minute as u64,
+        recent_hours: minutes / 60.0,
+        sentry_events: (minutes / SENTRY_EVENT_MINUTES) as u64,
+        sentry_hours: (minutes / SENTRY_EVENT_MINUTES).floor() * SENTRY_EVENT_MINUTES / 60.0,
+        saved_clips: (minutes / SAVED_CLIP_MINUTES) as u64,
+    })
+}
+
+// The smallest TeslaCam partition, in whole GB and never below the car's
+// minimum, that holds all of the goal at once.
+pub fn suggest_dashcam_size(goal: &RetentionGoal, recording: &Recording) -> Result<SizeSuggestion> {
+    let per_minute = validate(recording)?;
+    let minutes = goal.recent_hours * 60.0
+        + goal.sentry_events as f64 * SENTRY_EVENT_MINUTES
+        + goal.saved_clips as f64 * SAVED_CLIP_MINUTES;
+    if !minutes.is_finite() || minutes < 0.0 {
+        return Err(anyhow::anyhow!("Retention goal must not be negative"));
+    }
+    
+    let needed_gb = (minutes * per_minute / USABLE_FRACTION / GIB as f64).ceil() as u32;
+    let minimum = crate::tesla::get_tesla_requirements().min_dashcam_size_gb;
+    let dashcam_size_gb = needed_gb.max(minimum);
+    
+    Ok(SizeSuggestion {
+        dashcam_size_gb,
+        estimate: estimate_retention(dashcam_size_gb, recording)?,
+    })
+}
+
+fn validate(recording: &Recording) -> Result<f64> {
+    if !recording.bitrate_mbps.is_finite() || recording.bitrate_mbps <= 0.0 {
+        return Err(anyhow::anyhow!("Bitrate must be a positive number of Mbit/s"));
+    }
+    Ok(recording.bytes_per_minute())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    #[test]
+    fn estimates_footage_for_a_partition() {
+        let estimate = estimate_retention(64, &Recording::default()).unwrap();
+        
+        // 4 cameras at 4.5 Mbit/s: 135 MB a minute.
+        assert_eq!(estimate.bytes_per_minute, 135_000_000);
+        assert_eq!(estimate.cameras, 4);
+        assert!((estimate.recent_hours - 8.06).abs() < 0.01, "{}", estimate.recent_hours);
+        assert_eq!(estimate.sentry_events, 48);
+        assert_eq!(estimate.sentry_hours, 8.0);
+        assert_eq!(estimate.saved_clips, 48);
+        
+        let six = Recording {
+            vehicle: VehicleProfile::SixCamera,
+            ..Recording::default()
+        };
+        assert_eq!(estimate_retention(64, &six).unwrap().sentry_events, 32);
+        assert!(estimate_retention(64, &Recording { bitrate_mbps: 0.0, ..Recording::default() }).is_err());
+    }
+    
+    #[test]
+    fn suggests_a_size_that_holds_the_goal() {
+        let goal = RetentionGoal {
+            recent_hours: 1.0,
+            sentry_events: 50,
+            saved_clips: 10,
+        };
+        let suggestion = suggest_dashcam_size(&goal, &Recording::default()).unwrap();
+        
+        assert_eq!(suggestion.dashcam_size_gb, 88);
+        assert!(suggestion.estimate.recent_hours >= 11.0);
+        assert!(estimate_retention(87, &Recording::default()).unwrap().recent_hours < 11.0);
+        
+        // Small goals still get the car's minimum.
+        let small = RetentionGoal { recent_hours: 1.0, ..RetentionGoal::default() };
+        assert_eq!(suggest_dashcam_size(&small, &Recording::default()).unwrap().dashcam_size_gb, 32);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,270 @@
+use crate::UsbDevice;
+use crate::capacity::{open_raw, UncachedFile};
+use crate::command::CommandRunner;
+use crate::gpt::SECTOR_SIZE;
+use crate::usb::DeviceIdentity;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom, Write};
+
+// Raw disk nodes on macOS and Windows only accept whole, aligned sectors,
+// so magics are read and cleared through the 4 KiB block around them.
+const IO_BLOCK: u64 = 4096;
+
+const RAID_MAGIC: &[u8] = &[0xFC, 0x4E, 0x2B, 0xA9];
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct FoundSignature {
+    pub kind: String,
+    pub offset: u64,
+    pub length: u64,
+}
+
+// Partition tables, filesystems, RAID and LVM headers that make Linux and
+// Windows recognise what used to be on the stick. Like wipefs, only the
+// magic bytes are cleared; that is enough for every prober to skip them.
+pub async fn wipe_device_signatures(
+    runner: &impl CommandRunner,
+    device: &UsbDevice,
+    expected: &DeviceIdentity,
+) -> Result<Vec<FoundSignature>> {
+    crate::usb::verify_device_unchanged(runner, &device.path, expected).await?;
+    
+    if crate::target::is_image_file(&device.path) {
+        return wipe_signatures(&device.path, device.size).await;
+    }
+    
+    let lock = crate::mounts::prepare_device_for_write(runner, device).await?;
+    
+    let result = wipe_signatures(&device.path, device.size).await;
+    drop(lock);
+    result
+}
+
+// For callers that already hold the device lock.
+pub async fn wipe_signatures(path: &str, size: u64) -> Result<Vec<FoundSignature>> {
+    let path = path.to_string();
+    
+    tokio::task::spawn_blocking(move || {
+        let mut disk = UncachedFile(open_raw(&path, true)?);
+        let found = find_signatures(&mut disk, size);
+        clear_signatures(&mut disk, size, &found)?;
+        disk.flush()?;
+        Ok(found)
+    }).await?
+}
+
+// Offset and size of every partition in the GPT or, failing that, the MBR.
+// Factory-fresh sticks usually come with an MBR and one FAT32 or exFAT
+// partition.
+pub fn partition_extents<T: Read + Seek>(disk: &mut T) -> Vec<(u64, u64)> {
+    if let Ok(partitions) = crate::gpt::read_gpt(disk) {
+        return partitions.iter().map(|p| (p.offset(), p.size())).collect();
+    }
+    
+    let mut mbr = [0u8; SECTOR_SIZE as usize];
+    let read = disk.seek(SeekFrom::Start(0)).and_then(|_| disk.read_exact(&mut mbr));
+    if read.is_err() || mbr[510..512] != [0x55, 0xAA] {
+        return Vec::new();
+    }
+    
+    mbr[446..510].chunks(16)
+        .filter(|entry| entry[4] != 0)
+        .map(|entry| {
+            let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+            let sectors = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+            (start * SECTOR_SIZE, sectors * SECTOR_SIZE)
+        })
+        .filter(|(_, size)| *size > 0)
+        .collect()
+}
+
+// Magics that can appear at the start of any region, whole disk or
+// partition, as offsets relative to a region of `size` bytes.
+fn volume_magics(size: u64) -> Vec<(&'static str, u64, &'static [u8])> {
+    let mut magics: Vec<(&'static str, u64, &'static [u8])> = vec![
+        ("exfat", 3, b"EXFAT   "),
+        ("exfat", 12 * SECTOR_SIZE + 3, b"EXFAT   "),
+        ("ntfs", 3, b"NTFS    "),
+        ("vfat", 82, b"FAT32   "),
+        ("vfat", 6 * SECTOR_SIZE + 82, b"FAT32   "),
+        ("vfat", 54, b"FAT16   "),
+        ("vfat", 54, b"FAT12   "),
+        ("ext4", 1080, &[0x53, 0xEF]),
+        ("hfsplus", 1024, b"H+"),
+        ("hfsplus", 1024, b"HX"),
+        ("apfs", 32, b"NXSB"),
+        ("btrfs", 65600, b"_BHRfS_M"),
+        ("swap", 4086, b"SWAPSPACE2"),
+        ("iso9660", 32769, b"CD001"),
+        ("LVM2_member", 512, b"LABELONE"),
+        ("linux_raid_member", 0, RAID_MAGIC),
+        ("linux_raid_member", 4096, RAID_MAGIC),
+    ];
+    
+    if size >= SECTOR_SIZE {
+        // NTFS keeps a copy of its boot sector in the last sector.
+        magics.push(("ntfs", size - SECTOR_SIZE + 3, b"NTFS    "));
+    }
+    if size >= 16 * SECTOR_SIZE {
+        // md 1.0 superblock: 8 KiB from the end, 4 KiB aligned.
+        magics.push(("linux_raid_member", ((size / SECTOR_SIZE - 16) & !7) * SECTOR_SIZE, RAID_MAGIC));
+    }
+    if size >= 2 * 65536 {
+        // md 0.90 superblock: the last 64 KiB aligned block.
+        magics.push(("linux_raid_member", (size / 65536 - 1) * 65536, RAID_MAGIC));
+    }
+    
+    magics
+}
+
+// Only the whole disk carries partition tables.
+fn disk_magics(size: u64) -> Vec<(&'static str, u64, &'static [u8])> {
+    let mut magics: Vec<(&'static str, u64, &'static [u8])> = vec![
+        ("dos", 510, &[0x55, 0xAA]),
+        ("gpt", SECTOR_SIZE, b"EFI PART"),
+        // Primary header on 4Kn disks.
+        ("gpt", 4096, b"EFI PART"),
+    ];
+    
+    if size >= SECTOR_SIZE {
+        magics.push(("gpt", size - SECTOR_SIZE, b"EFI PART"));
+    }
+    if size >= 4096 {
+        magics.push(("gpt", size - 4096, b"EFI PART"));
+    }
+    
+    magics
+}
+
+pub fn find_signatures<T: Read + Seek>(disk: &mut T, size: u64) -> Vec<FoundSignature> {
+    let mut candidates: Vec<(&'static str, u64, &'static [u8])> = disk_magics(size);
+    candidates.extend(volume_magics(size));
+    
+    for (start, length) in partition_extents(disk) {
+        let length = length.min(size.saturating_sub(start));
+        candidates.extend(volume_magics(length).into_iter().map(|(kind, offset, magic)| (kind, start + offset, magic)));
+    }
+    
+    let mut found: Vec<FoundSignature> = Vec::new();
+    for (kind, offset, magic) in candidates {
+        if offset + magic.len() as u64 > size || found.iter().any(|f| f.offset == offset && f.kind == kind) {
+            continue;
+        }
+        
+        if read_bytes(disk, offset, magic.len(), size).ok().as_deref() == Some(magic) {
+            found.push(FoundSignature {
+                kind: kind.to_string(),
+                offset,
+                length: magic.len() as u64,
+            });
+        }
+    }
+    
+    found.sort_by_key(|f| f.offset);
+    found
+}
+
+pub fn clear_signatures<T: Read + Write + Seek>(disk: &mut T, size: u64, found: &[FoundSignature]) -> Result<()> {
+    for signature in found {
+        let (block_start, mut block) = read_block(disk, signature.offset, size)?;
+        let at = (signature.offset - block_start) as usize;
+        block[at..at + signature.length as usize].fill(0);
+        
+        disk.seek(SeekFrom::Start(block_start))?;
+        disk.write_all(&block)
+            .map_err(|e| anyhow::anyhow!("Failed to clear {} signature at offset {}: {}", signature.kind, signature.offset, e))?;
+    }
+    
+    Ok(())
+}
+
+fn read_block<T: Read + Seek>(disk: &mut T, offset: u64, size: u64) -> std::io::Result<(u64, Vec<u8>)> {
+    let block_start = offset / IO_BLOCK * IO_BLOCK;
+    let mut block = vec![0u8; IO_BLOCK.min(size - block_start) as usize];
+    
+    disk.seek(SeekFrom::Start(block_start))?;
+    disk.read_exact(&mut block)?;
+    Ok((block_start, block))
+}
+
+// Magics never straddle a 4 KiB boundary, so one block is always enough.
+fn read_bytes<T: Read + Seek>(disk: &mut T, offset: u64, len: usize, size: u64) -> std::io::Result<Vec<u8>> {
+    let (block_start, block) = read_block(disk, offset, size)?;
+    let at = (offset - block_start) as usize;
+    Ok(block.get(at..at + len).unwrap_or_default().to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    
+    const MIB: u64 = 1024 * 1024;
+    
+    fn put(disk: &mut [u8], offset: u64, bytes: &[u8]) {
+        disk[offset as usize..offset as usize + bytes.len()].copy_from_slice(bytes);
+    }
+    
+    // A stick that was a hybrid ISO installer, then got an MBR partition
+    // holding exFAT on top.
+    fn used_stick() -> Vec<u8> {
+        let mut disk = vec![0u8; 8 * MIB as usize];
+        
+        let entry = 446;
+        disk[entry + 4] = 0x07;
+        put(&mut disk, entry as u64 + 8, &2048u32.to_le_bytes());
+        put(&mut disk, entry as u64 + 12, &8192u32.to_le_bytes());
+        put(&mut disk, 510, &[0x55, 0xAA]);
+        put(&mut disk, 32769, b"CD001");
+        
+        put(&mut disk, MIB + 3, b"EXFAT   ");
+        put(&mut disk, MIB + 12 * 512 + 3, b"EXFAT   ");
+        put(&mut disk, 8 * MIB - 512, b"EFI PART");
+        disk
+    }
+    
+    #[test]
+    fn reads_mbr_partitions_of_a_factory_stick() {
+        assert_eq!(partition_extents(&mut Cursor::new(used_stick())), vec![(MIB, 4 * MIB)]);
+        assert_eq!(partition_extents(&mut Cursor::new(vec![0u8; 4096])), vec![]);
+    }
+    
+    #[test]
+    fn finds_table_filesystem_and_iso_signatures() {
+        let found = find_signatures(&mut Cursor::new(used_stick()), 8 * MIB);
+        let kinds: Vec<(&str, u64)> = found.iter().map(|f| (f.kind.as_str(), f.offset)).collect();
+        
+        assert_eq!(kinds, vec![
+            ("dos", 510),
+            ("iso9660", 32769),
+            ("exfat", MIB + 3),
+            ("exfat", MIB + 12 * 512 + 3),
+            ("gpt", 8 * MIB - 512),
+        ]);
+    }
+    
+    #[test]
+    fn clearing_leaves_nothing_to_detect() {
+        let mut disk = Cursor::new(used_stick());
+        let found = find_signatures(&mut disk, 8 * MIB);
+        
+        clear_signatures(&mut disk, 8 * MIB, &found).unwrap();
+        
+        assert_eq!(find_signatures(&mut disk, 8 * MIB), vec![]);
+        // Only the magics are touched; the partition entry survives.
+        assert_eq!(disk.get_ref()[446 + 4], 0x07);
+    }
+    
+    #[test]
+    fn finds_raid_superblocks_at_the_end() {
+        let mut disk = vec![0u8; 4 * MIB as usize];
+        put(&mut disk, 4 * MIB - 8192, RAID_MAGIC);
+        put(&mut disk, 4 * MIB - 65536, RAID_MAGIC);
+        
+        let found = find_signatures(&mut Cursor::new(disk), 4 * MIB);
+        
+        assert_eq!(found.iter().map(|f| f.offset).collect::<Vec<_>>(), vec![4 * MIB - 65536, 4 * MIB - 8192]);
+        assert!(found.iter().all(|f| f.kind == "linux_raid_member"));
+    }
+}
\ No newline at end of file
@@ -0,0 +1,354 @@
+use crate::UsbDevice;
+use crate::benchmark::LatencyPercentiles;
+use crate::capacity::{block_pattern, open_raw, UncachedFile, BLOCK_SIZE};
+use crate::command::CommandRunner;
+use crate::progress::Progress;
+use crate::usb::DeviceIdentity;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{Duration, Instant};
+
+// Failed blocks are re-read in pieces this size to narrow down the bad area.
+const PROBE_SIZE: u64 = 4096;
+const SLOW_BLOCK: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScanMode {
+    ReadOnly,
+    // Writes a test pattern over the whole device and verifies it, which
+    // also finds cells that accept data but don't keep it. Destroys
+    // everything on the device.
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct BadRange {
+    pub offset: u64,
+    pub length: u64,
+    pub error: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SurfaceScanReport {
+    pub job_id: String,
+    pub mode: ScanMode,
+    pub bytes_total: u64,
+    pub bad_ranges: Vec<BadRange>,
+    pub bad_bytes: u64,
+    pub slow_blocks: u64,
+    pub read_latency: LatencyPercentiles,
+    pub elapsed_secs: f64,
+    pub passed: bool,
+}
+
+pub async fn scan_surface(
+    runner: &impl CommandRunner,
+    device: &UsbDevice,
+    expected: &DeviceIdentity,
+    mode: ScanMode,
+    progress: &Progress,
+) -> Result<SurfaceScanReport> {
+    crate::usb::verify_device_unchanged(runner, &device.path, expected).await?;
+    
+    let size = device.size;
+    let path = device.path.clone();
+    let job = progress.clone();
+    
+    let findings = match mode {
+        ScanMode::ReadOnly => {
+            tokio::task::spawn_blocking(move || -> Result<Findings> {
+                let mut file = UncachedFile(open_raw(&path, false)?);
+                file.drop_cache();
+                
+                let mut findings = Findings::default();
+                read_pass(&mut file, size, None, &job, &mut findings);
+                Ok(findings)
+            }).await??
+        }
+        ScanMode::ReadWrite if crate::target::is_image_file(&device.path) => {
+            tokio::task::spawn_blocking(move || read_write_scan(&path, size, &job)).await??
+        }
+        ScanMode::ReadWrite => {
+            let lock = crate::mounts::prepare_device_for_write(runner, device).await?;
+            
+            let result = tokio::task::spawn_blocking(move || read_write_scan(&path, size, &job)).await?;
+            drop(lock);
+            result?
+        }
+    };
+    
+    Ok(findings.into_report(progress.job_id(), mode, size))
+}
+
+pub fn describe_failure(report: &SurfaceScanReport) -> String {
+    let first = &report.bad_ranges[0];
+    format!(
+        "Surface scan found {} bad range(s) covering {} KB, the first at offset {} ({}). \
+         The drive is not safe for recordings.",
+        report.bad_ranges.len(),
+        report.bad_bytes / 1024,
+        first.offset,
+        first.error
+    )
+}
+
+fn read_write_scan(path: &str, size: u64, progress: &Progress) -> Result<Findings> {
+    let mut file = UncachedFile(open_raw(path, true)?);
+    let nonce = uuid::Uuid::new_v4().as_u64_pair().0;
+    let mut findings = Findings::default();
+    
+    write_pass(&mut file, size, nonce, progress, &mut findings);
+    file.flush()?;
+    read_pass(&mut file, size, Some(nonce), progress, &mut findings);
+    
+    Ok(findings)
+}
+
+#[derive(Default)]
+struct Findings {
+    bad_ranges: Vec<BadRange>,
+    latencies: Vec<Duration>,
+    slow_blocks: u64,
+    started: Option<Instant>,
+}
+
+impl Findings {
+    // Adjacent ranges with the same error are merged into one.
+    fn mark_bad(&mut self, offset: u64, length: u64, error: &str) {
+        if let Some(last) = self.bad_ranges.last_mut() {
+            if last.offset + last.length == offset && last.error == error {
+                last.length += length;
+                return;
+            }
+        }
+        self.bad_ranges.push(BadRange {
+            offset,
+            length,
+            error: error.to_string(),
+        });
+    }
+    
+    fn into_report(mut self, job_id: &str, mode: ScanMode, size: u64) -> SurfaceScanReport {
+        // A block can fail on write and again on verify.
+        self.bad_ranges.sort_by_key(|r| r.offset);
+        
+        SurfaceScanReport {
+            job_id: job_id.to_string(),
+            mode,
+            bytes_total: size,
+            bad_bytes: self.bad_ranges.iter().map(|r| r.length).sum(),
+            passed: self.bad_ranges.is_empty(),
+            bad_ranges: self.bad_ranges,
+            slow_blocks: self.slow_blocks,
+            read_latency: LatencyPercentiles::from_latencies(self.latencies),
+            elapsed_secs: self.started.map(|s| s.elapsed().as_secs_f64()).unwrap_or(0.0),
+        }
+    }
+}
+
+fn write_pass<T: Write + Seek>(device: &mut T, size: u64, nonce: u64, progress: &Progress, findings: &mut Findings) {
+    findings.started.get_or_insert_with(Instant::now);
+    
+    for offset in (0..size).step_by(BLOCK_SIZE as usize) {
+        let len = BLOCK_SIZE.min(size - offset);
+        let block = block_pattern(nonce, offset);
+        
+        let written = device.seek(SeekFrom::Start(offset))
+            .and_then(|_| device.write_all(&block[..len as usize]));
+        if let Err(e) = written {
+            findings.mark_bad(offset, len, &format!("write error: {}", e));
+        }
+        
+        progress.update("write", offset + len, size);
+    }
+}
+
+// Reads every block, timing each one. With a nonce the data is also compared
+// against what `write_pass` put there.
+fn read_pass<T: Read + Seek>(device: &mut T, size: u64, nonce: Option<u64>, progress: &Progress, findings: &mut Findings) {
+    findings.started.get_or_insert_with(Instant::now);
+    let phase = if nonce.is_some() { "verify" } else { "read" };
+    let mut buffer = vec![0u8; BLOCK_SIZE as usize];
+    
+    for offset in (0..size).step_by(BLOCK_SIZE as usize) {
+        let len = BLOCK_SIZE.min(size - offset) as usize;
+        let expected = nonce.map(|n| block_pattern(n, offset));
+        
+        let started = Instant::now();
+        let read = device.seek(SeekFrom::Start(offset))
+            .and_then(|_| device.read_exact(&mut buffer[..len]));
+        let latency = started.elapsed();
+        
+        findings.latencies.push(latency);
+        if latency >= SLOW_BLOCK {
+            findings.slow_blocks += 1;
+        }
+        
+        match read {
+            Ok(()) => {
+                if let Some(expected) = &expected {
+                    if buffer[..len] != expected[..len] {
+                        compare_probes(offset, &buffer[..len], &expected[..len], findings);
+                    }
+                }
+            }
+            Err(_) => probe_block(device, offset, len, expected.as_deref(), findings),
+        }
+        
+        progress.update(phase, offset + len as u64, size);
+    }
+}
+
+fn compare_probes(offset: u64, actual: &[u8], expected: &[u8], findings: &mut Findings) {
+    for (i, (a, e)) in actual.chunks(PROBE_SIZE as usize).zip(expected.chunks(PROBE_SIZE as usize)).enumerate() {
+        if a != e {
+            findings.mark_bad(offset + i as u64 * PROBE_SIZE, a.len() as u64, "data mismatch");
+        }
+    }
+}
+
+fn probe_block<T: Read + Seek>(device: &mut T, offset: u64, len: usize, expected: Option<&[u8]>, findings: &mut Findings) {
+    let mut probe = vec![0u8; PROBE_SIZE as usize];
+    
+    for start in (0..len).step_by(PROBE_SIZE as usize) {
+        let probe_len = (PROBE_SIZE as usize).min(len - start);
+        let probe_offset = offset + start as u64;
+        
+        let read = device.seek(SeekFrom::Start(probe_offset))
+            .and_then(|_| device.read_exact(&mut probe[..probe_len]));
+        
+        match read {
+            Ok(()) => {
+                if let Some(expected) = expected {
+                    if probe[..probe_len] != expected[start..start + probe_len] {
+                        findings.mark_bad(probe_offset, probe_len as u64, "data mismatch");
+                    }
+                }
+            }
+            Err(e) => findings.mark_bad(probe_offset, probe_len as u64, &format!("read error: {}", e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::ops::Range;
+    
+    const MIB: u64 = 1024 * 1024;
+    
+    // In-memory device with an area that fails to read and an area that
+    // silently drops writes.
+    struct FaultyDevice {
+        data: Cursor<Vec<u8>>,
+        unreadable: Range<u64>,
+        dropped_writes: Range<u64>,
+    }
+    
+    impl FaultyDevice {
+        fn new(size: u64) -> Self {
+            Self {
+                data: Cursor::new(vec![0u8; size as usize]),
+                unreadable: 0..0,
+                dropped_writes: 0..0,
+            }
+        }
+    }
+    
+    impl Read for FaultyDevice {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            let start = self.data.position();
+            if start < self.unreadable.end && start + buf.len() as u64 > self.unreadable.start {
+                return Err(std::io::Error::other("I/O error"));
+            }
+            self.data.read(buf)
+        }
+    }
+    
+    impl Write for FaultyDevice {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let start = self.data.position();
+            for (i, byte) in buf.iter().enumerate() {
+                let pos = start + i as u64;
+                if !self.dropped_writes.contains(&pos) {
+                    self.data.get_mut()[pos as usize] = *byte;
+                }
+            }
+            self.data.set_position(start + buf.len() as u64);
+            Ok(buf.len())
+        }
+        
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+    
+    impl Seek for FaultyDevice {
+        fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+            self.data.seek(pos)
+        }
+    }
+    
+    #[test]
+    fn read_only_scan_narrows_down_read_errors() {
+        let mut device = FaultyDevice::new(4 * MIB);
+        device.unreadable = MIB + 8192..MIB + 12288;
+        let (progress, events) = Progress::recording("surface-scan");
+        let mut findings = Findings::default();
+        
+        read_pass(&mut device, 4 * MIB, None, &progress, &mut findings);
+        let report = findings.into_report(progress.job_id(), ScanMode::ReadOnly, 4 * MIB);
+        
+        assert!(!report.passed);
+        assert_eq!(report.bad_ranges, vec![BadRange {
+            offset: MIB + 8192,
+            length: 4096,
+            error: "read error: I/O error".to_string(),
+        }]);
+        assert_eq!(report.bad_bytes, 4096);
+        
+        let events = events.lock().unwrap();
+        let last = events.last().unwrap();
+        assert_eq!((last.phase.as_str(), last.done, last.total), ("read", 4 * MIB, 4 * MIB));
+        assert!(events.iter().all(|e| e.job_id == progress.job_id()));
+    }
+    
+    #[test]
+    fn read_write_scan_finds_cells_that_drop_writes() {
+        let mut device = FaultyDevice::new(4 * MIB);
+        device.dropped_writes = 2 * MIB..2 * MIB + 10000;
+        let (progress, events) = Progress::recording("surface-scan");
+        let mut findings = Findings::default();
+        
+        write_pass(&mut device, 4 * MIB, 7, &progress, &mut findings);
+        read_pass(&mut device, 4 * MIB, Some(7), &progress, &mut findings);
+        let report = findings.into_report(progress.job_id(), ScanMode::ReadWrite, 4 * MIB);
+        
+        assert_eq!(report.bad_ranges, vec![BadRange {
+            offset: 2 * MIB,
+            length: 12288,
+            error: "data mismatch".to_string(),
+        }]);
+        
+        let phases: Vec<String> = events.lock().unwrap().iter().map(|e| e.phase.clone()).collect();
+        assert_eq!(phases.first().map(String::as_str), Some("write"));
+        assert_eq!(phases.last().map(String::as_str), Some("verify"));
+    }
+    
+    #[test]
+    fn healthy_device_passes() {
+        let mut device = FaultyDevice::new(2 * MIB + 4096);
+        let (progress, _) = Progress::recording("surface-scan");
+        let mut findings = Findings::default();
+        
+        write_pass(&mut device, 2 * MIB + 4096, 7, &progress, &mut findings);
+        read_pass(&mut device, 2 * MIB + 4096, Some(7), &progress, &mut findings);
+        let report = findings.into_report(progress.job_id(), ScanMode::ReadWrite, 2 * MIB + 4096);
+        
+        assert!(report.passed);
+        assert_eq!(report.bad_bytes, 0);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,178 @@
+use crate::{UsbDevice, PartitionConfig};
+use crate::partitions::CreatedPartition;
+use crate::usb::{DeviceIdentity, PartitionLayout};
+use crate::volume::Volume;
+use anyhow::Result;
+use std::path::Path;
+
+// A regular file standing in for a USB drive. Image targets are laid out
+// and formatted entirely in-process, so they work without root and can be
+// written to many sticks afterwards with dd or a flashing tool.
+pub fn is_image_file(path: &str) -> bool {
+    std::fs::metadata(path).map(|m| m.is_file()).unwrap_or(false)
+}
+
+pub async fn create_image(path: &str, size_gb: u32) -> Result<UsbDevice> {
+    if Path::new(path).exists() {
+        return Err(anyhow::anyhow!("{} already exists", path));
+    }
+    
+    let file = tokio::fs::File::create(path).await?;
+    // Sparse on every filesystem that supports it, so a 64 GB image only
+    // takes the space actually written.
+    file.set_len(size_gb as u64 * 1024 * 1024 * 1024).await?;
+    
+    open_image(path).await
+}
+
+pub async fn open_image(path: &str) -> Result<UsbDevice> {
+    let metadata = tokio::fs::metadata(path).await?;
+    if !metadata.is_file() {
+        return Err(anyhow::anyhow!("{} is not a disk image file", path));
+    }
+    
+    let name = Path::new(path)
+        .file_name()
+        .map(|n| n.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.to_string());
+    
+    Ok(UsbDevice {
+        name: format!("Disk image ({})", name),
+        path: path.to_string(),
+        size: metadata.len(),
+        is_removable: true,
+    })
+}
+
+pub async fn read_image_identity(path: &str) -> Result<DeviceIdentity> {
+    let path = path.to_string();
+    
+    tokio::task::spawn_blocking(move || {
+        let mut file = std::fs::File::open(&path)?;
+        let size = file.metadata()?.len();
+        
+        let layout = match crate::gpt::read_gpt(&mut file) {
+            Ok(partitions) => partitions.iter()
+                .map(|partition| PartitionLayout {
+                    size: partition.size(),
+                    filesystem: std::fs::File::open(&path).ok()
+                        .and_then(|f| Volume::open(f, partition.offset()).ok())
+                        .map(|volume| volume.filesystem().to_string()),
+                    label: Some(partition.name.clone()),
+                })
+                .collect(),
+            Err(_) => Vec::new(),
+        };
+        
+        Ok(DeviceIdentity {
+            serial: None,
+            size,
+            layout,
+        })
+    })
+    .await?
+}
+
+pub async fn create_image_partitions(device: &UsbDevice, partitions: &[PartitionConfig]) -> Result<Vec<CreatedPartition>> {
+    for partition in partitions {
+        if partition.filesystem != "exfat" && partition.filesystem != "fat32" {
+            return Err(anyhow::anyhow!(
+                "Partition {} uses {}, but image targets only support exfat and fat32",
+                partition.name,
+                partition.filesystem
+            ));
+        }
+    }
+    
+    let device = device.clone();
+    let partitions = partitions.to_vec();
+    
+    tokio::task::spawn_blocking(move || {
+        let sizes: Vec<(String, u64)> = partitions.iter()
+            .map(|p| (p.name.clone(), p.size_gb as u64 * 1024 * 1024 * 1024))
+            .collect();
+        let layout = crate::gpt::plan_partitions(device.size, &sizes)?;
+        
+        let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&device.path)?;
+        crate::gpt::write_gpt(&mut file, device.size, &layout)?;
+        
+        let mut created = Vec::new();
+        for (partition, slot) in partitions.iter().zip(&layout) {
+            let file = std::fs::OpenOptions::new().read(true).write(true).open(&device.path)?;
+            crate::volume::format_volume(file, slot.offset(), slot.size(), &partition.filesystem, &partition.name)?;
+            
+            created.push(CreatedPartition {
+                config: partition.clone(),
+                path: device.path.clone(),
+                offset: slot.offset(),
+            });
+        }
+        
+        Ok(created)
+    })
+    .await?
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    
+    const GIB: u64 = 1024 * 1024 * 1024;
+    
+    fn partition(name: &str, filesystem: &str) -> PartitionConfig {
+        PartitionConfig {
+            name: name.to_string(),
+            size_gb: 1,
+            filesystem: filesystem.to_string(),
+            purpose: String::new(),
+        }
+    }
+    
+    #[tokio::test]
+    async fn partitioned_images_verify_as_tesla_drives() {
+        let path = std::env::temp_dir().join(format!("teslausb-target-{}.img", uuid::Uuid::new_v4()));
+        let path_str = path.to_string_lossy().into_owned();
+        let device = create_image(&path_str, 3).await.unwrap();
+        let layout = vec![partition("TeslaCam", "exfat"), partition("TeslaMusic", "fat32")];
+        
+        let created = create_image_partitions(&device, &layout).await.unwrap();
+        
+        let offsets: Vec<u64> = created.iter().map(|c| c.offset).collect();
+        assert_eq!(offsets, vec![1024 * 1024, GIB + 1024 * 1024]);
+        let partitions = crate::gpt::assert_valid_gpt(&mut std::fs::File::open(&path).unwrap(), device.size);
+        assert_eq!(partitions.len(), 2);
+        
+        let checks = crate::tesla::verify_tesla_drive(&device).await.unwrap();
+        let found: Vec<(&str, Option<&str>, u64)> = checks.iter().map(|c| (c.name.as_str(), c.filesystem.as_deref(), c.size)).collect();
+        assert_eq!(found, vec![("TeslaCam", Some("exfat"), GIB), ("TeslaMusic", Some("fat32"), GIB)]);
+        assert_eq!(checks[0].missing_folders.len(), 4);
+        
+        // Folders written at the partition's offset are found by the check.
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(&path).unwrap();
+        let mut volume = Volume::open(file, offsets[0]).unwrap();
+        volume.create_dir_all("TeslaCam/SavedClips").unwrap();
+        volume.create_dir_all("TeslaCam/SentryClips").unwrap();
+        volume.create_dir_all("TeslaCam/RecentClips").unwrap();
+        volume.flush().unwrap();
+        drop(volume);
+        
+        let checks = crate::tesla::verify_tesla_drive(&device).await.unwrap();
+        assert!(checks[0].missing_folders.is_empty(), "{:?}", checks[0]);
+        assert_eq!(checks[1].missing_folders, vec!["Music".to_string()]);
+        let identity = read_image_identity(&path_str).await.unwrap();
+        assert_eq!(identity.layout.iter().map(|l| l.filesystem.as_deref()).collect::<Vec<_>>(), vec![Some("exfat"), Some("fat32")]);
+        std::fs::remove_file(&path).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn image_partitions_need_a_filesystem_written_in_process() {
+        let path = std::env::temp_dir().join(format!("teslausb-target-{}.img", uuid::Uuid::new_v4()));
+        let device = create_image(&path.to_string_lossy(), 2).await.unwrap();
+        
+        let error = create_image_partitions(&device, &[partition("Backup", "ext4")]).await.unwrap_err();
+        
+        assert!(error.to_string().contains("ext4"), "{}", error);
+        assert!(crate::gpt::read_gpt(&mut std::fs::File::open(&path).unwrap()).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
\ No newline at end of file
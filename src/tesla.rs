@@ -1,16 +1,59 @@
 use crate::{UsbDevice, TeslaConfig, PartitionConfig};
+use crate::command::CommandRunner;
+use crate::erase::EraseReport;
+use crate::partitions::CreatedPartition;
+use crate::progress::Progress;
+use crate::usb::DeviceIdentity;
+use crate::volume::Volume;
 use anyhow::Result;
+use serde::{Deserialize, Serialize};
 use std::path::Path;
 use tokio::fs;
 
-pub async fn format_for_tesla(device: &UsbDevice, config: &TeslaConfig) -> Result<()> {
+pub async fn format_for_tesla(runner: &impl CommandRunner, device: &UsbDevice, expected: &DeviceIdentity, config: &TeslaConfig, progress: &Progress) -> Result<Option<EraseReport>> {
+    // Checked before anything is erased so a bad file fails early.
+    let lock_chime = match &config.lock_chime {
+        Some(chime) => Some(crate::lockchime::prepare_chime(&fs::read(&chime.source).await?, &chime.options)?.0),
+        None => None,
+    };
+    
+    // The capacity check, a read-write scan and the erase each leave a
+    // different layout behind than the one the user confirmed, so every
+    // later step checks against what the previous one left.
+    let mut expected = expected.clone();
+    
+    if let Some(mode) = config.capacity_check {
+        let report = crate::capacity::check_capacity(runner, device, &expected, mode).await?;
+        if !report.passed {
+            return Err(anyhow::anyhow!(crate::capacity::describe_failure(&report)));
+        }
+        expected = crate::usb::reread_device_identity(runner, &device.path, &expected).await?;
+    }
+    
+    if let Some(mode) = config.surface_scan {
+        let report = crate::surface_scan::scan_surface(runner, device, &expected, mode, progress).await?;
+        if !report.passed {
+            return Err(anyhow::anyhow!(crate::surface_scan::describe_failure(&report)));
+        }
+        expected = crate::usb::reread_device_identity(runner, &device.path, &expected).await?;
+    }
+    
+    let erased = match &config.erase {
+        Some(options) => {
+            let report = crate::erase::erase_device(runner, device, &expected, options, progress).await?;
+            expected = crate::usb::reread_device_identity(runner, &device.path, &expected).await?;
+            Some(report)
+        }
+        None => None,
+    };
+    
     let partitions = create_tesla_partitions(config);
     
-    crate::partitions::create_partitions(device, &partitions).await?;
+    let created = crate::partitions::create_partitions(runner, device, &expected, &partitions).await?;
     
-    setup_tesla_folders(device, config).await?;
+    setup_tesla_folders(runner, device, &created, config, lock_chime).await?;
     
-    Ok(())
+    Ok(erased)
 }
 
 fn create_tesla_partitions(config: &TeslaConfig) -> Vec<PartitionConfig> {
@@ -36,7 +79,7 @@ fn create_tesla_partitions(config: &TeslaConfig) -> Vec<PartitionConfig> {
     
     if config.lightshow_size_gb > 0 {
         partitions.push(PartitionConfig {
-            name: "TeslaLightshow".to_string(),
+            name: "LightShow".to_string(),
             size_gb: config.lightshow_size_gb,
             filesystem: "exfat".to_string(),
             purpose: "Lightshow files".to_string(),
@@ -46,104 +89,170 @@ fn create_tesla_partitions(config: &TeslaConfig) -> Vec<PartitionConfig> {
     partitions
 }
 
-async fn setup_tesla_folders(device: &UsbDevice, _config: &TeslaConfig) -> Result<()> {
-    let mount_points = get_device_mount_points(device).await?;
+pub const MARKER_FILE: &str = "TeslaUSBTool.txt";
+
+async fn setup_tesla_folders(runner: &impl CommandRunner, device: &UsbDevice, created: &[CreatedPartition], config: &TeslaConfig, lock_chime: Option<Vec<u8>>) -> Result<()> {
+    // Anything the OS mounted after formatting would go stale underneath
+    // the raw writes below.
+    crate::mounts::unmount_all(runner, device).await?;
+    
+    // The car looks for the lock chime and Boombox sounds at the root of
+    // the partition it records to.
+    let root_partition = created.iter().position(|p| p.config.name == "TeslaCam").unwrap_or(0);
     
-    for mount_point in mount_points {
-        let mount_path = Path::new(&mount_point);
+    for (index, partition) in created.iter().enumerate() {
+        let mut folders = partition_folders(&partition.config.name).to_vec();
+        if config.boombox && index == root_partition {
+            folders.push(crate::boombox::BOOMBOX_FOLDER);
+        }
+        let marker = format!(
+            "Prepared by Tesla USB Tool\r\nPartition: {}\r\nPurpose: {}\r\n",
+            partition.config.name,
+            partition.config.purpose
+        );
+        let mut files = vec![(MARKER_FILE, marker.into_bytes())];
+        if let Some(chime) = lock_chime.as_ref().filter(|_| index == root_partition) {
+            files.push((crate::lockchime::LOCK_CHIME_FILE, chime.clone()));
+        }
         
-        if mount_path.file_name().unwrap_or_default().to_string_lossy().contains("TeslaCam") {
-            setup_dashcam_folders(&mount_point).await?;
-        } else if mount_path.file_name().unwrap_or_default().to_string_lossy().contains("TeslaMusic") {
-            setup_music_folders(&mount_point).await?;
-        } else if mount_path.file_name().unwrap_or_default().to_string_lossy().contains("TeslaLightshow") {
-            setup_lightshow_folders(&mount_point).await?;
+        match partition.config.filesystem.as_str() {
+            "exfat" | "fat32" => {
+                let partition = partition.clone();
+                tokio::task::spawn_blocking(move || write_folders_to_volume(&partition, &folders, &files))
+                    .await??;
+            }
+            _ => {
+                let mount = crate::mounts::mount_partition(runner, &partition.path, &partition.config.filesystem).await?;
+                let result = write_folders_to_mount(&mount.mount_point, &folders, &files).await;
+                crate::mounts::release_partition_mount(runner, mount).await?;
+                result?;
+            }
         }
     }
     
     Ok(())
 }
 
-async fn setup_dashcam_folders(mount_point: &str) -> Result<()> {
-    let base_path = Path::new(mount_point);
-    
-    let teslacam_path = base_path.join("TeslaCam");
-    fs::create_dir_all(&teslacam_path).await?;
-    
-    let saved_clips_path = teslacam_path.join("SavedClips");
-    fs::create_dir_all(&saved_clips_path).await?;
-    
-    let sentry_clips_path = teslacam_path.join("SentryClips");
-    fs::create_dir_all(&sentry_clips_path).await?;
-    
-    let recent_clips_path = teslacam_path.join("RecentClips");
-    fs::create_dir_all(&recent_clips_path).await?;
-    
-    Ok(())
+fn partition_folders(name: &str) -> &'static [&'static str] {
+    match name {
+        "TeslaCam" => &["TeslaCam", "TeslaCam/SavedClips", "TeslaCam/SentryClips", "TeslaCam/RecentClips"],
+        "TeslaMusic" => &["Music"],
+        "LightShow" => &["LightShow"],
+        _ => &[],
+    }
 }
 
-async fn setup_music_folders(mount_point: &str) -> Result<()> {
-    let base_path = Path::new(mount_point);
+fn write_folders_to_volume(partition: &CreatedPartition, folders: &[&str], files: &[(&str, Vec<u8>)]) -> Result<()> {
+    // Image partitions live at an offset inside the image file rather than
+    // behind their own device node.
+    let file = if crate::target::is_image_file(&partition.path) {
+        std::fs::OpenOptions::new().read(true).write(true).open(&partition.path)?
+    } else {
+        crate::volume::open_partition(&partition.path)?
+    };
+    let mut volume = Volume::open(file, partition.offset)?;
     
-    let music_path = base_path.join("Music");
-    fs::create_dir_all(&music_path).await?;
+    for folder in folders {
+        volume.create_dir_all(folder)?;
+    }
+    for (name, data) in files {
+        volume.write_file(name, data)?;
+    }
     
-    Ok(())
+    volume.flush()
 }
 
-async fn setup_lightshow_folders(mount_point: &str) -> Result<()> {
+async fn write_folders_to_mount(mount_point: &str, folders: &[&str], files: &[(&str, Vec<u8>)]) -> Result<()> {
     let base_path = Path::new(mount_point);
     
-    let lightshow_path = base_path.join("LightShow");
-    fs::create_dir_all(&lightshow_path).await?;
+    for folder in folders {
+        fs::create_dir_all(base_path.join(folder)).await?;
+    }
+    for (name, data) in files {
+        fs::write(base_path.join(name), data).await?;
+    }
     
     Ok(())
 }
 
-async fn get_device_mount_points(device: &UsbDevice) -> Result<Vec<String>> {
-    let mut mount_points = Vec::new();
-    
-    #[cfg(target_os = "windows")]
-    {
-        mount_points.push(format!("{}\\", device.path));
-    }
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PartitionCheck {
+    pub name: String,
+    pub filesystem: Option<String>,
+    pub size: u64,
+    pub missing_folders: Vec<String>,
+    pub has_marker: bool,
+}
+
+// Reads the partition table and each volume back without mounting anything,
+// so the same check works on sticks and on image files.
+pub async fn verify_tesla_drive(device: &UsbDevice) -> Result<Vec<PartitionCheck>> {
+    let path = device.path.clone();
     
-    #[cfg(target_os = "macos")]
-    {
-        let output = tokio::process::Command::new("mount")
-            .output()
-            .await?;
+    tokio::task::spawn_blocking(move || {
+        use std::io::Seek;
+        
+        let mut disk = open_for_reading(&path)?;
+        let partitions = match crate::gpt::read_gpt(&mut disk) {
+            Ok(partitions) => partitions.into_iter()
+                .map(|p| (p.name.clone(), p.offset(), p.size()))
+                .collect(),
+            // No partition table: a single volume spanning the whole target.
+            Err(_) => vec![(String::new(), 0, disk.seek(std::io::SeekFrom::End(0))?)],
+        };
         
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        for line in output_str.lines() {
-            if line.contains(&device.path) {
-                if let Some(mount_point) = line.split(" on ").nth(1) {
-                    if let Some(mount_path) = mount_point.split(" (").next() {
-                        mount_points.push(mount_path.to_string());
+        let mut checks = Vec::new();
+        for (name, offset, size) in partitions {
+            let volume = open_for_reading(&path).and_then(|file| Volume::open(file, offset));
+            
+            let check = match volume {
+                Ok(mut volume) => {
+                    let mut missing_folders = Vec::new();
+                    for folder in partition_folders(&name) {
+                        if !volume.exists(folder)? {
+                            missing_folders.push(folder.to_string());
+                        }
+                    }
+                    
+                    PartitionCheck {
+                        name,
+                        filesystem: Some(volume.filesystem().to_string()),
+                        size,
+                        missing_folders,
+                        has_marker: volume.exists(MARKER_FILE)?,
                     }
                 }
-            }
+                Err(_) => PartitionCheck {
+                    missing_folders: partition_folders(&name).iter().map(|f| f.to_string()).collect(),
+                    name,
+                    filesystem: None,
+                    size,
+                    has_marker: false,
+                },
+            };
+            checks.push(check);
         }
-    }
-    
-    #[cfg(target_os = "linux")]
-    {
-        let output = tokio::process::Command::new("mount")
-            .output()
-            .await?;
         
-        let output_str = String::from_utf8_lossy(&output.stdout);
-        for line in output_str.lines() {
-            if line.contains(&device.path) {
-                let parts: Vec<&str> = line.split_whitespace().collect();
-                if parts.len() >= 3 {
-                    mount_points.push(parts[2].to_string());
-                }
-            }
+        Ok(checks)
+    })
+    .await?
+}
+
+pub fn open_for_reading(path: &str) -> Result<std::fs::File> {
+    #[cfg(target_os = "windows")]
+    {
+        if !crate::target::is_image_file(path) && !path.starts_with("\\\\") {
+            return Ok(std::fs::File::open(format!("\\\\.\\{}", path.trim_end_matches('\\')))?);
         }
     }
     
-    Ok(mount_points)
+    Ok(std::fs::File::open(path)?)
+}
+
+pub async fn get_device_mount_points(runner: &impl CommandRunner, device: &UsbDevice) -> Result<Vec<String>> {
+    let mounted = crate::mounts::find_mounted_partitions(runner, device).await?;
+    
+    Ok(mounted.into_iter().map(|m| m.mount_point).collect())
 }
 
 pub fn get_tesla_requirements() -> TeslaRequirements {
@@ -216,6 +325,11 @@ pub fn get_recommended_tesla_config(device_size_gb: u32) -> TeslaConfig {
             sentry_size_gb: 0,
             music_size_gb: 0,
             lightshow_size_gb: 0,
+            capacity_check: None,
+            surface_scan: None,
+            erase: None,
+            lock_chime: None,
+            boombox: false,
         }
     } else if device_size_gb < 128 {
         TeslaConfig {
@@ -223,6 +337,11 @@ pub fn get_recommended_tesla_config(device_size_gb: u32) -> TeslaConfig {
             sentry_size_gb: 0,
             music_size_gb: 16,
             lightshow_size_gb: 8,
+            capacity_check: None,
+            surface_scan: None,
+            erase: None,
+            lock_chime: None,
+            boombox: false,
         }
     } else {
         TeslaConfig {
@@ -230,6 +349,103 @@ pub fn get_recommended_tesla_config(device_size_gb: u32) -> TeslaConfig {
             sentry_size_gb: 0,
             music_size_gb: 32,
             lightshow_size_gb: 16,
+            capacity_check: None,
+            surface_scan: None,
+            erase: None,
+            lock_chime: None,
+            boombox: false,
         }
     }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::FakeRunner;
+    
+    fn config(erase: Option<crate::erase::EraseOptions>) -> TeslaConfig {
+        TeslaConfig {
+            dashcam_size_gb: 1,
+            sentry_size_gb: 0,
+            music_size_gb: 0,
+            lightshow_size_gb: 0,
+            capacity_check: None,
+            surface_scan: None,
+            erase,
+            lock_chime: None,
+            boombox: false,
+        }
+    }
+    
+    // An image just big enough for a 1 GiB TeslaCam partition, already
+    // laid out for the car, and the identity the user selected it with.
+    async fn formatted_image(name: &str) -> (std::path::PathBuf, UsbDevice, DeviceIdentity) {
+        let path = std::env::temp_dir().join(format!("teslausb-{}-{}.img", name, uuid::Uuid::new_v4()));
+        let path_str = path.to_string_lossy().into_owned();
+        let file = std::fs::File::create(&path).unwrap();
+        file.set_len((1 << 30) + (2 << 20)).unwrap();
+        
+        let device = crate::target::open_image(&path_str).await.unwrap();
+        let runner = FakeRunner::new();
+        let blank = crate::usb::read_device_identity(&runner, &path_str).await.unwrap();
+        let (progress, _) = Progress::recording("format");
+        format_for_tesla(&runner, &device, &blank, &config(None), &progress).await.unwrap();
+        
+        let identity = crate::usb::read_device_identity(&runner, &path_str).await.unwrap();
+        assert_eq!(identity.layout.len(), 1);
+        (path, device, identity)
+    }
+    
+    #[tokio::test]
+    async fn erases_and_formats_in_one_run() {
+        let (path, device, identity) = formatted_image("erase-format").await;
+        let runner = FakeRunner::new();
+        let (progress, _) = Progress::recording("format");
+        let erase = crate::erase::EraseOptions {
+            mode: crate::erase::EraseMode::Quick,
+            discard: false,
+        };
+        
+        let erased = format_for_tesla(&runner, &device, &identity, &config(Some(erase)), &progress).await.unwrap();
+        
+        assert!(erased.is_some());
+        let checks = verify_tesla_drive(&device).await.unwrap();
+        assert_eq!(checks.len(), 1);
+        assert!(checks.iter().all(|c| c.has_marker && c.missing_folders.is_empty()), "{:?}", checks);
+        std::fs::remove_file(&path).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn checks_capacity_and_formats_in_one_run() {
+        let (path, device, identity) = formatted_image("capacity-format").await;
+        let runner = FakeRunner::new();
+        let (progress, _) = Progress::recording("format");
+        let config = TeslaConfig {
+            capacity_check: Some(crate::capacity::CapacityMode::Sampled),
+            ..config(None)
+        };
+        
+        format_for_tesla(&runner, &device, &identity, &config, &progress).await.unwrap();
+        
+        let checks = verify_tesla_drive(&device).await.unwrap();
+        assert!(checks.iter().all(|c| c.has_marker && c.missing_folders.is_empty()), "{:?}", checks);
+        std::fs::remove_file(&path).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn scans_read_write_and_formats_in_one_run() {
+        let (path, device, identity) = formatted_image("scan-format").await;
+        let runner = FakeRunner::new();
+        let (progress, _) = Progress::recording("format");
+        let config = TeslaConfig {
+            surface_scan: Some(crate::surface_scan::ScanMode::ReadWrite),
+            ..config(None)
+        };
+        
+        format_for_tesla(&runner, &device, &identity, &config, &progress).await.unwrap();
+        
+        let checks = verify_tesla_drive(&device).await.unwrap();
+        assert!(checks.iter().all(|c| c.has_marker && c.missing_folders.is_empty()), "{:?}", checks);
+        std::fs::remove_file(&path).unwrap();
+    }
 }
\ No newline at end of file
@@ -1,252 +1,498 @@
 use crate::UsbDevice;
+use crate::command::CommandRunner;
 use anyhow::Result;
-use tokio::process::Command as TokioCommand;
+use serde::{Deserialize, Serialize};
 
-#[cfg(target_os = "windows")]
-use std::os::windows::process::CommandExt;
+pub async fn list_usb_devices(runner: &impl CommandRunner) -> Result<Vec<UsbDevice>> {
+    #[cfg(target_os = "windows")]
+    let devices = list_windows_devices(runner).await?;
+    
+    #[cfg(target_os = "macos")]
+    let devices = list_macos_devices(runner).await?;
+    
+    #[cfg(target_os = "linux")]
+    let devices = list_linux_devices(runner).await?;
+    
+    Ok(devices.into_iter().filter(|d| d.is_removable).collect())
+}
 
-pub async fn list_usb_devices() -> Result<Vec<UsbDevice>> {
-    let mut devices = Vec::new();
+pub async fn get_device_info(runner: &impl CommandRunner, device_path: &str) -> Result<UsbDevice> {
+    if crate::target::is_image_file(device_path) {
+        return crate::target::open_image(device_path).await;
+    }
     
     #[cfg(target_os = "windows")]
     {
-        devices = list_windows_devices().await?;
+        get_windows_device_info(runner, device_path).await
     }
     
     #[cfg(target_os = "macos")]
     {
-        devices = list_macos_devices().await?;
+        get_macos_device_info(runner, device_path).await
     }
     
     #[cfg(target_os = "linux")]
     {
-        devices = list_linux_devices().await?;
+        get_linux_device_info(runner, device_path).await
     }
-    
-    Ok(devices.into_iter().filter(|d| d.is_removable).collect())
 }
 
-pub async fn get_device_info(device_path: &str) -> Result<UsbDevice> {
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeviceIdentity {
+    pub serial: Option<String>,
+    pub size: u64,
+    pub layout: Vec<PartitionLayout>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PartitionLayout {
+    pub size: u64,
+    pub filesystem: Option<String>,
+    pub label: Option<String>,
+}
+
+pub async fn read_device_identity(runner: &impl CommandRunner, device_path: &str) -> Result<DeviceIdentity> {
+    if crate::target::is_image_file(device_path) {
+        return crate::target::read_image_identity(device_path).await;
+    }
+    
     #[cfg(target_os = "windows")]
     {
-        get_windows_device_info(device_path).await
+        read_windows_device_identity(runner, device_path).await
     }
     
     #[cfg(target_os = "macos")]
     {
-        get_macos_device_info(device_path).await
+        read_macos_device_identity(runner, device_path).await
     }
     
     #[cfg(target_os = "linux")]
     {
-        get_linux_device_info(device_path).await
+        read_linux_device_identity(runner, device_path).await
     }
 }
 
-#[cfg(target_os = "windows")]
-async fn list_windows_devices() -> Result<Vec<UsbDevice>> {
-    let output = TokioCommand::new("wmic")
-        .args(&[
-            "logicaldisk",
-            "where",
-            "drivetype=2",
-            "get",
-            "size,freespace,caption",
-            "/format:csv"
-        ])
-        .creation_flags(0x08000000) // CREATE_NO_WINDOW
-        .output()
-        .await?;
-    
-    let _output_str = String::from_utf8_lossy(&output.stdout);
-    let mut devices = Vec::new();
+// Called right before the first destructive write, so a stick swapped at the
+// same path after the user confirmed the selection is never touched.
+pub async fn verify_device_unchanged(runner: &impl CommandRunner, device_path: &str, expected: &DeviceIdentity) -> Result<()> {
+    let current = read_device_identity(runner, device_path).await?;
     
-    for line in output_str.lines().skip(1) {
-        let fields: Vec<&str> = line.split(',').collect();
-        if fields.len() >= 4 && !fields[1].is_empty() {
-            let drive_letter = fields[1].trim();
-            let size_str = fields[2].trim();
-            
-            if let Ok(size) = size_str.parse::<u64>() {
-                devices.push(UsbDevice {
-                    name: format!("Removable Disk ({})", drive_letter),
-                    path: drive_letter.to_string(),
-                    size,
-                    is_removable: true,
-                });
-            }
-        }
+    let mut differences = same_drive_differences(expected, &current);
+    if current.layout != expected.layout {
+        differences.push(format!(
+            "partition layout changed ({} partitions, now {})",
+            expected.layout.len(),
+            current.layout.len()
+        ));
     }
     
-    Ok(devices)
+    changed_error(device_path, differences)
 }
 
-#[cfg(target_os = "windows")]
-async fn get_windows_device_info(device_path: &str) -> Result<UsbDevice> {
-    let output = TokioCommand::new("wmic")
-        .args(&[
-            "logicaldisk",
-            "where",
-            &format!("caption='{}'", device_path),
-            "get",
-            "size,freespace,caption,volumename",
-            "/format:csv"
-        ])
-        .creation_flags(0x08000000)
-        .output()
-        .await?;
-    
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    let lines: Vec<&str> = output_str.lines().collect();
-    
-    if lines.len() >= 2 {
-        let fields: Vec<&str> = lines[1].split(',').collect();
-        if fields.len() >= 4 {
-            let size = fields[2].trim().parse::<u64>().unwrap_or(0);
-            let volume_name = fields[3].trim();
-            let name = if volume_name.is_empty() {
-                format!("Removable Disk ({})", device_path)
-            } else {
-                format!("{} ({})", volume_name, device_path)
-            };
-            
-            return Ok(UsbDevice {
-                name,
-                path: device_path.to_string(),
-                size,
-                is_removable: true,
-            });
-        }
+// Re-reads the identity after a step that rewrote the disk, such as an
+// erase ahead of partitioning. The layout is whatever the step left, but
+// it must still be the same stick.
+pub async fn reread_device_identity(runner: &impl CommandRunner, device_path: &str, expected: &DeviceIdentity) -> Result<DeviceIdentity> {
+    let current = read_device_identity(runner, device_path).await?;
+    changed_error(device_path, same_drive_differences(expected, &current))?;
+    Ok(current)
+}
+
+fn same_drive_differences(expected: &DeviceIdentity, current: &DeviceIdentity) -> Vec<String> {
+    let mut differences = Vec::new();
+    if current.serial != expected.serial {
+        differences.push(format!(
+            "serial {} is now {}",
+            expected.serial.as_deref().unwrap_or("unknown"),
+            current.serial.as_deref().unwrap_or("unknown")
+        ));
+    }
+    if current.size != expected.size {
+        differences.push(format!("size {} bytes is now {} bytes", expected.size, current.size));
+    }
+    differences
+}
+
+fn changed_error(device_path: &str, differences: Vec<String>) -> Result<()> {
+    if !differences.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Device changed since selection: {} ({}). Refresh the device list and select it again.",
+            device_path,
+            differences.join(", ")
+        ));
     }
     
-    Err(anyhow::anyhow!("Device not found"))
+    Ok(())
 }
 
-#[cfg(target_os = "macos")]
-async fn list_macos_devices() -> Result<Vec<UsbDevice>> {
-    let mut devices = Vec::new();
+#[cfg(any(target_os = "windows", test))]
+async fn read_windows_device_identity(runner: &impl CommandRunner, device_path: &str) -> Result<DeviceIdentity> {
+    let map = crate::windows_disks::read_disk_map(runner).await?;
+    let disk = map.disk_for_path(device_path)
+        .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device_path))?;
     
-    let external_output = TokioCommand::new("diskutil")
-        .args(&["list", "external"])
-        .output()
-        .await?;
+    let layout = map.partitions_on_disk(disk.number)
+        .into_iter()
+        .map(|partition| PartitionLayout {
+            size: partition.size,
+            filesystem: partition.filesystem.clone(),
+            label: partition.label.clone(),
+        })
+        .collect();
     
-    let external_str = String::from_utf8_lossy(&external_output.stdout);
+    Ok(DeviceIdentity {
+        serial: disk.serial.clone(),
+        size: disk.size,
+        layout,
+    })
+}
+
+#[cfg(any(target_os = "macos", test))]
+async fn read_macos_device_identity(runner: &impl CommandRunner, device_path: &str) -> Result<DeviceIdentity> {
+    let info = crate::macos_disks::disk_info(runner, device_path).await?;
+    let list = crate::macos_disks::list_disk(runner, device_path).await?;
+    
+    let layout = list.disks.iter()
+        .find(|disk| disk.identifier == info.identifier)
+        .map(|disk| disk.partitions.iter().map(|partition| PartitionLayout {
+            size: partition.size,
+            filesystem: None,
+            label: partition.volume_name.clone(),
+        }).collect())
+        .unwrap_or_default();
     
-    for line in external_str.lines() {
-        if line.contains("/dev/disk") && !line.contains("(internal") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            if let Some(disk_path) = parts.first() {
-                if let Ok(info) = get_macos_device_info(disk_path).await {
-                    devices.push(info);
-                }
+    let ioreg_output = runner.run("ioreg", &["-r", "-c", "IOUSBHostDevice", "-l", "-w", "0"]).await?;
+    let serial = usb_serial_for_disk(&ioreg_output.stdout, device_path.trim_start_matches("/dev/"));
+    
+    Ok(DeviceIdentity {
+        serial,
+        size: info.size,
+        layout,
+    })
+}
+
+// IOMedia nodes carry no serial; it lives on the IOUSBHostDevice the disk
+// hangs off. Each device is printed with its whole subtree, so the serial is
+// the first one in the smallest subtree naming the disk (a hub's subtree
+// contains the disks of everything plugged into it).
+#[cfg(any(target_os = "macos", test))]
+fn usb_serial_for_disk(ioreg: &str, bsd_name: &str) -> Option<String> {
+    let bsd_key = format!("\"BSD Name\" = \"{}\"", bsd_name);
+    let mut devices: Vec<String> = Vec::new();
+    for line in ioreg.lines() {
+        match devices.last_mut() {
+            Some(device) if !line.starts_with("+-o ") => {
+                device.push_str(line);
+                device.push('\n');
             }
+            _ => devices.push(format!("{}\n", line)),
         }
     }
     
-    Ok(devices)
+    devices.iter()
+        .filter(|device| device.contains(&bsd_key))
+        .min_by_key(|device| device.len())
+        .and_then(|device| device.lines().find(|l| l.contains("\"USB Serial Number\" =")))
+        .and_then(|line| line.split_once('=').map(|(_, value)| value))
+        .map(|value| value.trim().trim_matches('"').to_string())
+        .filter(|serial| !serial.is_empty())
 }
 
-#[cfg(target_os = "macos")]
-async fn get_macos_device_info(device_path: &str) -> Result<UsbDevice> {
-    let output = TokioCommand::new("diskutil")
-        .args(&["info", device_path])
-        .output()
-        .await?;
-    
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    let mut name = String::new();
-    let mut size = 0u64;
-    let mut is_removable = false;
-    
-    for line in output_str.lines() {
-        if line.contains("Device / Media Name:") {
-            name = line.split(':').nth(1).unwrap_or("").trim().to_string();
-        } else if line.contains("Disk Size:") {
-            if let Some(size_part) = line.split('(').nth(1) {
-                if let Some(bytes_str) = size_part.split_whitespace().next() {
-                    size = bytes_str.parse::<u64>().unwrap_or(0);
-                }
-            }
-        } else if line.contains("Removable Media:") {
-            is_removable = line.contains("Yes");
-        }
+#[cfg(target_os = "linux")]
+async fn read_linux_device_identity(runner: &impl CommandRunner, device_path: &str) -> Result<DeviceIdentity> {
+    let output = runner.run("lsblk", &["-J", "-b", "-o", "NAME,SERIAL,SIZE,FSTYPE,LABEL,TYPE", device_path]).await?;
+    
+    if !output.success {
+        return Err(anyhow::anyhow!(
+            "Failed to read identity of {}: {}",
+            device_path,
+            output.stderr
+        ));
     }
     
-    if name.is_empty() {
-        name = format!("USB Device {}", device_path);
+    let value: serde_json::Value = serde_json::from_str(&output.stdout)?;
+    let disk = value["blockdevices"].get(0)
+        .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device_path))?;
+    
+    let layout = disk["children"].as_array()
+        .map(|children| children.iter().map(|child| PartitionLayout {
+            size: lsblk_size(&child["size"]),
+            filesystem: non_empty(child["fstype"].as_str()),
+            label: non_empty(child["label"].as_str()),
+        }).collect())
+        .unwrap_or_default();
+    
+    Ok(DeviceIdentity {
+        serial: non_empty(disk["serial"].as_str()),
+        size: lsblk_size(&disk["size"]),
+        layout,
+    })
+}
+
+// Older util-linux prints sizes as strings even with -J -b.
+#[cfg(any(target_os = "linux", test))]
+fn lsblk_size(value: &serde_json::Value) -> u64 {
+    value.as_u64()
+        .or_else(|| value.as_str().and_then(|s| s.parse().ok()))
+        .unwrap_or(0)
+}
+
+#[cfg(any(target_os = "linux", test))]
+fn non_empty(value: Option<&str>) -> Option<String> {
+    value.map(str::trim).filter(|v| !v.is_empty()).map(str::to_string)
+}
+
+// Physical disks rather than drive letters, so a stick with several
+// partitions (or none) shows up once, addressed as \\.\PhysicalDriveN.
+#[cfg(any(target_os = "windows", test))]
+async fn list_windows_devices(runner: &impl CommandRunner) -> Result<Vec<UsbDevice>> {
+    let map = crate::windows_disks::read_disk_map(runner).await?;
+    
+    Ok(map.disks.iter().map(windows_usb_device).collect())
+}
+
+#[cfg(target_os = "windows")]
+async fn get_windows_device_info(runner: &impl CommandRunner, device_path: &str) -> Result<UsbDevice> {
+    let map = crate::windows_disks::read_disk_map(runner).await?;
+    
+    map.disk_for_path(device_path)
+        .map(windows_usb_device)
+        .ok_or_else(|| anyhow::anyhow!("Device not found"))
+}
+
+#[cfg(any(target_os = "windows", test))]
+fn windows_usb_device(disk: &crate::windows_disks::PhysicalDisk) -> UsbDevice {
+    UsbDevice {
+        name: match &disk.friendly_name {
+            Some(name) => format!("{} ({})", name, disk.bus_type),
+            None => format!("{} Disk {}", disk.bus_type, disk.number),
+        },
+        path: disk.path(),
+        size: disk.size,
+        is_removable: disk.is_removable(),
+    }
+}
+
+#[cfg(any(target_os = "macos", test))]
+async fn list_macos_devices(runner: &impl CommandRunner) -> Result<Vec<UsbDevice>> {
+    let list = crate::macos_disks::list_external_disks(runner).await?;
+    let mut devices = Vec::new();
+    
+    for disk in &list.whole_disks {
+        if let Ok(info) = get_macos_device_info(runner, &format!("/dev/{}", disk)).await {
+            devices.push(info);
+        }
     }
     
+    Ok(devices)
+}
+
+#[cfg(any(target_os = "macos", test))]
+async fn get_macos_device_info(runner: &impl CommandRunner, device_path: &str) -> Result<UsbDevice> {
+    let info = crate::macos_disks::disk_info(runner, device_path).await?;
+    
+    // Built-in SD slots report Internal but removable media.
     Ok(UsbDevice {
-        name,
-        path: device_path.to_string(),
-        size,
-        is_removable,
+        name: info.media_name.unwrap_or_else(|| format!("USB Device {}", device_path)),
+        path: info.node,
+        size: info.size,
+        is_removable: info.whole_disk && (!info.internal || info.removable),
     })
 }
 
-#[cfg(target_os = "linux")]
-async fn list_linux_devices() -> Result<Vec<UsbDevice>> {
-    let output = TokioCommand::new("lsblk")
-        .args(&["-J", "-o", "NAME,SIZE,HOTPLUG,MOUNTPOINT,TYPE"])
-        .output()
-        .await?;
+// One lsblk call lists every whole disk with its size, model and whether
+// it is hot-pluggable or removable.
+#[cfg(any(target_os = "linux", test))]
+async fn list_linux_devices(runner: &impl CommandRunner) -> Result<Vec<UsbDevice>> {
+    let output = runner.run("lsblk", &["-J", "-b", "-d", "-o", "NAME,SIZE,MODEL,HOTPLUG,RM,TYPE"]).await?;
+    
+    if !output.success {
+        return Err(anyhow::anyhow!("Failed to list block devices: {}", output.stderr.trim()));
+    }
     
-    let output_str = String::from_utf8_lossy(&output.stdout);
+    let value: serde_json::Value = serde_json::from_str(&output.stdout)?;
     let mut devices = Vec::new();
     
-    for line in output_str.lines() {
-        if line.contains("\"hotplug\":true") && line.contains("\"type\":\"disk\"") {
-            let usb_output = TokioCommand::new("lsblk")
-                .args(&["-n", "-o", "NAME,SIZE,HOTPLUG"])
-                .output()
-                .await?;
-            
-            let usb_str = String::from_utf8_lossy(&usb_output.stdout);
-            for usb_line in usb_str.lines() {
-                let parts: Vec<&str> = usb_line.split_whitespace().collect();
-                if parts.len() >= 3 && parts[2] == "1" {
-                    let device_name = parts[0];
-                    let size_str = parts[1];
-                    let device_path = format!("/dev/{}", device_name);
-                    
-                    if let Ok(info) = get_linux_device_info(&device_path).await {
-                        devices.push(info);
-                    }
-                }
-            }
+    for disk in value["blockdevices"].as_array().into_iter().flatten() {
+        if disk["type"].as_str() != Some("disk") {
+            continue;
         }
+        
+        let Some(name) = disk["name"].as_str() else {
+            continue;
+        };
+        
+        devices.push(UsbDevice {
+            name: non_empty(disk["model"].as_str()).unwrap_or_else(|| "USB Device".to_string()),
+            path: format!("/dev/{}", name),
+            size: lsblk_size(&disk["size"]),
+            is_removable: lsblk_flag(&disk["hotplug"]) || lsblk_flag(&disk["rm"]),
+        });
     }
     
     Ok(devices)
 }
 
 #[cfg(target_os = "linux")]
-async fn get_linux_device_info(device_path: &str) -> Result<UsbDevice> {
-    let output = TokioCommand::new("lsblk")
-        .args(&["-b", "-n", "-o", "SIZE,MODEL", device_path])
-        .output()
-        .await?;
-    
-    let _output_str = String::from_utf8_lossy(&output.stdout);
-    let parts: Vec<&str> = output_str.trim().split_whitespace().collect();
-    
-    let size = if !parts.is_empty() {
-        parts[0].parse::<u64>().unwrap_or(0)
-    } else {
-        0
-    };
-    
-    let model = if parts.len() > 1 {
-        parts[1..].join(" ")
-    } else {
-        "USB Device".to_string()
-    };
+async fn get_linux_device_info(runner: &impl CommandRunner, device_path: &str) -> Result<UsbDevice> {
+    let output = runner.run("lsblk", &["-J", "-b", "-d", "-o", "NAME,SIZE,MODEL,HOTPLUG,RM,TYPE", device_path]).await?;
+    
+    if !output.success {
+        return Err(anyhow::anyhow!("Device not found: {}: {}", device_path, output.stderr.trim()));
+    }
+    
+    let value: serde_json::Value = serde_json::from_str(&output.stdout)?;
+    let disk = value["blockdevices"].get(0)
+        .ok_or_else(|| anyhow::anyhow!("Device not found: {}", device_path))?;
     
     Ok(UsbDevice {
-        name: model,
+        name: non_empty(disk["model"].as_str()).unwrap_or_else(|| "USB Device".to_string()),
         path: device_path.to_string(),
-        size,
-        is_removable: true,
+        size: lsblk_size(&disk["size"]),
+        is_removable: lsblk_flag(&disk["hotplug"]) || lsblk_flag(&disk["rm"]),
     })
+}
+
+// Booleans in newer util-linux, "0"/"1" strings in older releases.
+#[cfg(any(target_os = "linux", test))]
+fn lsblk_flag(value: &serde_json::Value) -> bool {
+    value.as_bool().unwrap_or_else(|| value.as_str() == Some("1"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::FakeRunner;
+    
+    const LSBLK_ARGS: &[&str] = &["lsblk", "-J", "-b", "-d", "-o", "NAME,SIZE,MODEL,HOTPLUG,RM,TYPE"];
+    const POWERSHELL_DISK_MAP: &[&str] = &["powershell", "-NoProfile", "-NonInteractive", "-Command", crate::windows_disks::DISK_MAP_SCRIPT];
+    
+    #[tokio::test]
+    async fn list_linux_devices_runs_a_single_lsblk() {
+        let runner = FakeRunner::new()
+            .respond(LSBLK_ARGS, include_str!("../tests/fixtures/linux/lsblk_disks.json"));
+        
+        list_linux_devices(&runner).await.unwrap();
+        
+        runner.assert_calls(include_str!("../tests/fixtures/linux/list_devices_calls.json"));
+    }
+    
+    #[tokio::test]
+    async fn list_linux_devices_reports_removable_disks() {
+        let runner = FakeRunner::new()
+            .respond(LSBLK_ARGS, include_str!("../tests/fixtures/linux/lsblk_disks.json"));
+        
+        let devices = list_linux_devices(&runner).await.unwrap();
+        let removable: Vec<(&str, &str, u64)> = devices.iter()
+            .filter(|d| d.is_removable)
+            .map(|d| (d.path.as_str(), d.name.as_str(), d.size))
+            .collect();
+        
+        assert_eq!(devices.len(), 4);
+        assert_eq!(removable, vec![
+            ("/dev/sdb", "SanDisk 3.2Gen1", 61530439680),
+            ("/dev/sdc", "USB Device", 31914983424),
+            ("/dev/mmcblk0", "SD Card", 15931539456),
+        ]);
+    }
+    
+    #[tokio::test]
+    async fn list_linux_devices_accepts_older_lsblk_output() {
+        let runner = FakeRunner::new()
+            .respond(LSBLK_ARGS, include_str!("../tests/fixtures/linux/lsblk_disks_legacy.json"));
+        
+        let devices = list_linux_devices(&runner).await.unwrap();
+        
+        assert_eq!(devices.len(), 2);
+        assert!(!devices[0].is_removable);
+        assert!(devices[1].is_removable);
+        assert_eq!(devices[1].size, 61530439680);
+    }
+    
+    #[tokio::test]
+    async fn list_windows_devices_reports_physical_disks() {
+        let runner = FakeRunner::new()
+            .respond(POWERSHELL_DISK_MAP, include_str!("../tests/fixtures/windows/disk_map.json"));
+        
+        let devices = list_windows_devices(&runner).await.unwrap();
+        let removable: Vec<(&str, &str, u64)> = devices.iter()
+            .filter(|d| d.is_removable)
+            .map(|d| (d.path.as_str(), d.name.as_str(), d.size))
+            .collect();
+        
+        assert_eq!(devices.len(), 3);
+        assert_eq!(removable, vec![
+            ("\\\\.\\PhysicalDrive1", "SanDisk Ultra Fit (USB)", 61530439680),
+            ("\\\\.\\PhysicalDrive2", "Generic- SD/MMC (SD)", 31914983424),
+        ]);
+    }
+    
+    #[tokio::test]
+    async fn windows_identity_comes_from_the_physical_disk() {
+        let runner = FakeRunner::new()
+            .respond(POWERSHELL_DISK_MAP, include_str!("../tests/fixtures/windows/disk_map.json"));
+        
+        let identity = read_windows_device_identity(&runner, "\\\\.\\PhysicalDrive1").await.unwrap();
+        
+        assert_eq!(identity.serial.as_deref(), Some("4C530001230815104233"));
+        assert_eq!(identity.size, 61530439680);
+        assert_eq!(identity.layout.len(), 3);
+        assert_eq!(identity.layout[1].filesystem.as_deref(), Some("exFAT"));
+        assert_eq!(identity.layout[1].label.as_deref(), Some("TeslaCam"));
+    }
+    
+    fn macos_runner() -> FakeRunner {
+        FakeRunner::new()
+            .respond(&["diskutil", "list", "-plist", "external", "physical"], include_str!("../tests/fixtures/macos/diskutil_list_external.plist"))
+            .respond(&["diskutil", "list", "-plist", "/dev/disk4"], include_str!("../tests/fixtures/macos/diskutil_list_external.plist"))
+            .respond(&["diskutil", "info", "-plist", "/dev/disk4"], include_str!("../tests/fixtures/macos/diskutil_info_disk4.plist"))
+            .respond(&["diskutil", "info", "-plist", "/dev/disk6"], include_str!("../tests/fixtures/macos/diskutil_info_disk6.plist"))
+            .respond(&["ioreg", "-r", "-c", "IOUSBHostDevice", "-l", "-w", "0"], include_str!("../tests/fixtures/macos/ioreg_usb_devices.txt"))
+    }
+    
+    #[tokio::test]
+    async fn list_macos_devices_reads_each_external_disk() {
+        let runner = macos_runner();
+        
+        let devices = list_macos_devices(&runner).await.unwrap();
+        let listed: Vec<(&str, &str, u64, bool)> = devices.iter()
+            .map(|d| (d.path.as_str(), d.name.as_str(), d.size, d.is_removable))
+            .collect();
+        
+        runner.assert_calls(include_str!("../tests/fixtures/macos/list_devices_calls.json"));
+        assert_eq!(listed, vec![
+            ("/dev/disk4", "Ultra Fit", 61530439680, true),
+            ("/dev/disk6", "STORAGE DEVICE", 31914983424, true),
+        ]);
+    }
+    
+    #[tokio::test]
+    async fn macos_identity_lists_partitions_of_the_disk() {
+        let runner = macos_runner();
+        
+        let identity = read_macos_device_identity(&runner, "/dev/disk4").await.unwrap();
+        let labels: Vec<Option<&str>> = identity.layout.iter().map(|p| p.label.as_deref()).collect();
+        
+        assert_eq!(identity.serial.as_deref(), Some("4C530001230815117324"));
+        assert_eq!(identity.size, 61530439680);
+        assert_eq!(labels, vec![Some("EFI"), Some("TeslaCam"), Some("TESLAMUSIC")]);
+    }
+    
+    #[test]
+    fn macos_serial_comes_from_the_usb_device_owning_the_disk() {
+        let ioreg = include_str!("../tests/fixtures/macos/ioreg_usb_devices.txt");
+        
+        assert_eq!(usb_serial_for_disk(ioreg, "disk6").as_deref(), Some("000000009833"));
+        assert_eq!(usb_serial_for_disk(ioreg, "disk4s2").as_deref(), Some("4C530001230815117324"));
+        assert_eq!(usb_serial_for_disk(ioreg, "disk9"), None);
+    }
+    
+    #[tokio::test]
+    async fn list_linux_devices_reports_lsblk_failure() {
+        let runner = FakeRunner::new().fail(LSBLK_ARGS, "lsblk: failed to access sysfs directory");
+        
+        let error = list_linux_devices(&runner).await.unwrap_err().to_string();
+        
+        assert!(error.contains("failed to access sysfs"), "{}", error);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,679 @@
+use super::{dos_timestamp, read_u16, read_u32, read_u64, split_parent, split_path, BlockDevice, SectorIo};
+use anyhow::Result;
+use std::time::SystemTime;
+
+const ENTRY_SIZE: usize = 32;
+const TYPE_BITMAP: u8 = 0x81;
+const TYPE_FILE: u8 = 0x85;
+const TYPE_STREAM: u8 = 0xC0;
+const TYPE_NAME: u8 = 0xC1;
+const ATTR_DIRECTORY: u16 = 0x10;
+const ATTR_ARCHIVE: u16 = 0x20;
+const FLAG_ALLOCATION_POSSIBLE: u8 = 0x01;
+const FLAG_NO_FAT_CHAIN: u8 = 0x02;
+const END_OF_CHAIN: u32 = 0xFFFF_FFFF;
+
+pub struct ExfatVolume<T: BlockDevice> {
+    io: SectorIo<T>,
+    cluster_size: u64,
+    fat_offset: u64,
+    heap_offset: u64,
+    cluster_count: u32,
+    root_cluster: u32,
+    bitmap: Vec<u8>,
+    bitmap_clusters: Vec<u32>,
+    bitmap_dirty: bool,
+    next_free: u32,
+}
+
+// A directory's data stream. Subdirectories also remember where their
+// entry set lives in the parent, so growing them can update its length.
+#[derive(Debug, Clone)]
+struct Directory {
+    first_cluster: u32,
+    no_fat_chain: bool,
+    length: u64,
+    entry_set: Option<(Box<Directory>, usize)>,
+}
+
+#[derive(Debug, Clone)]
+struct EntrySet {
+    index: usize,
+    name: String,
+    attributes: u16,
+    first_cluster: u32,
+    no_fat_chain: bool,
+    length: u64,
+}
+
+impl<T: BlockDevice> ExfatVolume<T> {
+    pub fn open(mut io: SectorIo<T>) -> Result<Self> {
+        let mut boot = vec![0u8; 512];
+        io.read_at(0, &mut boot)?;
+        
+        let bytes_per_sector_shift = boot[108];
+        let sectors_per_cluster_shift = boot[109];
+        if !(9..=12).contains(&bytes_per_sector_shift) || bytes_per_sector_shift + sectors_per_cluster_shift > 25 {
+            return Err(anyhow::anyhow!("Invalid exFAT boot sector"));
+        }
+        
+        let bytes_per_sector = 1u64 << bytes_per_sector_shift;
+        io.set_sector_size(bytes_per_sector);
+        
+        let mut volume = ExfatVolume {
+            io,
+            cluster_size: bytes_per_sector << sectors_per_cluster_shift,
+            fat_offset: read_u32(&boot, 80) as u64 * bytes_per_sector,
+            heap_offset: read_u32(&boot, 88) as u64 * bytes_per_sector,
+            cluster_count: read_u32(&boot, 92),
+            root_cluster: read_u32(&boot, 96),
+            bitmap: Vec::new(),
+            bitmap_clusters: Vec::new(),
+            bitmap_dirty: false,
+            next_free: 2,
+        };
+        
+        volume.load_bitmap()?;
+        Ok(volume)
+    }
+    
+    pub fn create_dir_all(&mut self, path: &str) -> Result<()> {
+        let mut dir = self.root()?;
+        
+        for part in split_path(path) {
+            dir = match self.find_entry(&dir, part)? {
+                Some(entry) if entry.attributes & ATTR_DIRECTORY != 0 => subdirectory(&dir, &entry),
+                Some(_) => return Err(anyhow::anyhow!("{} exists and is not a directory", part)),
+                None => self.create_dir(&dir, part)?,
+            };
+        }
+        
+        Ok(())
+    }
+    
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let (parents, name) = split_parent(path)?;
+        let dir = self.resolve_dir(&parents)?;
+        
+        if self.find_entry(&dir, name)?.is_some() {
+            return Err(anyhow::anyhow!("{} already exists", path));
+        }
+        
+        let first_cluster = if data.is_empty() {
+            0
+        } else {
+            let clusters = self.allocate(data.len().div_ceil(self.cluster_size as usize) as u32)?;
+            for (chunk, cluster) in data.chunks(self.cluster_size as usize).zip(&clusters) {
+                let mut buf = chunk.to_vec();
+                buf.resize(self.cluster_size as usize, 0);
+                let offset = self.cluster_offset(*cluster);
+                self.io.write_at(offset, &buf)?;
+            }
+            clusters[0]
+        };
+        
+        let entries = entry_set(name, ATTR_ARCHIVE, first_cluster, data.len() as u64)?;
+        self.add_entry_set(&dir, &entries)?;
+        Ok(())
+    }
+    
+    pub fn exists(&mut self, path: &str) -> Result<bool> {
+        let (parents, name) = match split_parent(path) {
+            Ok(parts) => parts,
+            Err(_) => return Ok(true),
+        };
+        
+        match self.resolve_dir(&parents) {
+            Ok(dir) => Ok(self.find_entry(&dir, name)?.is_some()),
+            Err(_) => Ok(false),
+        }
+    }
+    
+    // VolumeFlags bit 1, VolumeDirty.
+    pub fn is_dirty(&mut self) -> Result<bool> {
+        let mut boot = vec![0u8; 512];
+        self.io.read_at(0, &mut boot)?;
+        Ok(read_u16(&boot, 106) & 0x0002 != 0)
+    }
+    
+    pub fn flush(&mut self) -> Result<()> {
+        if self.bitmap_dirty {
+            let bitmap = self.bitmap.clone();
+            let clusters = self.bitmap_clusters.clone();
+            for (chunk, cluster) in bitmap.chunks(self.cluster_size as usize).zip(&clusters) {
+                let offset = self.cluster_offset(*cluster);
+                self.io.write_at(offset, chunk)?;
+            }
+            self.update_percent_in_use()?;
+            self.bitmap_dirty = false;
+        }
+        
+        self.io.flush()
+    }
+    
+    fn root(&mut self) -> Result<Directory> {
+        let clusters = self.chain(self.root_cluster)?;
+        Ok(Directory {
+            first_cluster: self.root_cluster,
+            no_fat_chain: false,
+            length: clusters.len() as u64 * self.cluster_size,
+            entry_set: None,
+        })
+    }
+    
+    fn resolve_dir(&mut self, parts: &[&str]) -> Result<Directory> {
+        let mut dir = self.root()?;
+        
+        for part in parts {
+            dir = match self.find_entry(&dir, part)? {
+                Some(entry) if entry.attributes & ATTR_DIRECTORY != 0 => subdirectory(&dir, &entry),
+                _ => return Err(anyhow::anyhow!("Directory not found: {}", part)),
+            };
+        }
+        
+        Ok(dir)
+    }
+    
+    fn create_dir(&mut self, parent: &Directory, name: &str) -> Result<Directory> {
+        let cluster = self.allocate(1)?[0];
+        let offset = self.cluster_offset(cluster);
+        self.io.zero(offset, self.cluster_size)?;
+        
+        let entries = entry_set(name, ATTR_DIRECTORY, cluster, self.cluster_size)?;
+        let index = self.add_entry_set(parent, &entries)?;
+        
+        Ok(Directory {
+            first_cluster: cluster,
+            no_fat_chain: false,
+            length: self.cluster_size,
+            entry_set: Some((Box::new(parent.clone()), index)),
+        })
+    }
+    
+    fn add_entry_set(&mut self, dir: &Directory, entries: &[[u8; ENTRY_SIZE]]) -> Result<usize> {
+        let mut clusters = self.dir_clusters(dir)?;
+        let data = self.read_clusters(&clusters)?;
+        let slot = find_free_slots(&data, entries.len());
+        
+        if slot + entries.len() > data.len() / ENTRY_SIZE {
+            self.grow_directory(dir, &mut clusters, slot + entries.len())?;
+        }
+        
+        for (i, entry) in entries.iter().enumerate() {
+            let pos = self.entry_position(&clusters, slot + i);
+            self.io.write_at(pos, entry)?;
+        }
+        
+        Ok(slot)
+    }
+    
+    fn grow_directory(&mut self, dir: &Directory, clusters: &mut Vec<u32>, entries_needed: usize) -> Result<()> {
+        let entries_per_cluster = self.cluster_size as usize / ENTRY_SIZE;
+        
+        // Foreign directories may be contiguous without FAT entries; give
+        // them a real chain before appending a cluster that might not be.
+        if dir.no_fat_chain {
+            for (i, cluster) in clusters.iter().enumerate() {
+                let next = clusters.get(i + 1).copied().unwrap_or(END_OF_CHAIN);
+                self.set_fat_entry(*cluster, next)?;
+            }
+        }
+        
+        while clusters.len() * entries_per_cluster < entries_needed {
+            let added = self.allocate(1)?[0];
+            let offset = self.cluster_offset(added);
+            self.io.zero(offset, self.cluster_size)?;
+            if let Some(last) = clusters.last() {
+                self.set_fat_entry(*last, added)?;
+            }
+            clusters.push(added);
+        }
+        
+        // The root directory has no entry set; its size is its cluster chain.
+        if let Some((parent, index)) = &dir.entry_set {
+            let parent_clusters = self.dir_clusters(parent)?;
+            let parent_data = self.read_clusters(&parent_clusters)?;
+            let secondary_count = parent_data[index * ENTRY_SIZE + 1] as usize;
+            let mut set = parent_data[index * ENTRY_SIZE..(index + 1 + secondary_count) * ENTRY_SIZE].to_vec();
+            
+            let length = clusters.len() as u64 * self.cluster_size;
+            let stream = &mut set[ENTRY_SIZE..ENTRY_SIZE * 2];
+            stream[1] &= !FLAG_NO_FAT_CHAIN;
+            stream[8..16].copy_from_slice(&length.to_le_bytes());
+            stream[24..32].copy_from_slice(&length.to_le_bytes());
+            let checksum = entry_set_checksum(&set);
+            set[2..4].copy_from_slice(&checksum.to_le_bytes());
+            
+            for (i, entry) in set.chunks(ENTRY_SIZE).enumerate() {
+                let pos = self.entry_position(&parent_clusters, index + i);
+                self.io.write_at(pos, entry)?;
+            }
+        }
+        
+        Ok(())
+    }
+    
+    fn find_entry(&mut self, dir: &Directory, name: &str) -> Result<Option<EntrySet>> {
+        let target = upcase(name);
+        Ok(self.read_entry_sets(dir)?
+            .into_iter()
+            .find(|entry| upcase(&entry.name) == target))
+    }
+    
+    fn read_entry_sets(&mut self, dir: &Directory) -> Result<Vec<EntrySet>> {
+        let clusters = self.dir_clusters(dir)?;
+        let data = self.read_clusters(&clusters)?;
+        let entries: Vec<&[u8]> = data.chunks(ENTRY_SIZE).collect();
+        let mut sets = Vec::new();
+        let mut i = 0;
+        
+        while i < entries.len() {
+            let entry = entries[i];
+            if entry[0] == 0x00 {
+                break;
+            }
+            if entry[0] != TYPE_FILE {
+                i += 1;
+                continue;
+            }
+            
+            let secondary_count = entry[1] as usize;
+            if secondary_count < 2 || i + secondary_count >= entries.len() || entries[i + 1][0] != TYPE_STREAM {
+                i += 1;
+                continue;
+            }
+            
+            let stream = entries[i + 1];
+            let name_length = stream[3] as usize;
+            let mut units = Vec::with_capacity(name_length);
+            for name_entry in &entries[i + 2..=i + secondary_count] {
+                if name_entry[0] != TYPE_NAME {
+                    break;
+                }
+                for pos in (2..ENTRY_SIZE).step_by(2) {
+                    if units.len() < name_length {
+                        units.push(read_u16(name_entry, pos));
+                    }
+                }
+            }
+            
+            sets.push(EntrySet {
+                index: i,
+                name: String::from_utf16_lossy(&units),
+                attributes: read_u16(entry, 4),
+                first_cluster: read_u32(stream, 20),
+                no_fat_chain: stream[1] & FLAG_NO_FAT_CHAIN != 0,
+                length: read_u64(stream, 24),
+            });
+            i += secondary_count + 1;
+        }
+        
+        Ok(sets)
+    }
+    
+    fn dir_clusters(&mut self, dir: &Directory) -> Result<Vec<u32>> {
+        if dir.no_fat_chain {
+            let count = dir.length.div_ceil(self.cluster_size) as u32;
+            Ok((dir.first_cluster..dir.first_cluster + count).collect())
+        } else {
+            self.chain(dir.first_cluster)
+        }
+    }
+    
+    fn read_clusters(&mut self, clusters: &[u32]) -> Result<Vec<u8>> {
+        let mut data = vec![0u8; clusters.len() * self.cluster_size as usize];
+        for (chunk, cluster) in data.chunks_mut(self.cluster_size as usize).zip(clusters) {
+            let offset = self.cluster_offset(*cluster);
+            self.io.read_at(offset, chunk)?;
+        }
+        Ok(data)
+    }
+    
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.heap_offset + (cluster as u64 - 2) * self.cluster_size
+    }
+    
+    fn entry_position(&self, clusters: &[u32], index: usize) -> u64 {
+        let entries_per_cluster = self.cluster_size as usize / ENTRY_SIZE;
+        let cluster = clusters[index / entries_per_cluster];
+        self.cluster_offset(cluster) + ((index % entries_per_cluster) * ENTRY_SIZE) as u64
+    }
+    
+    fn chain(&mut self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        
+        while (2..=self.cluster_count + 1).contains(&cluster) {
+            if clusters.len() > self.cluster_count as usize {
+                return Err(anyhow::anyhow!("Cluster chain loop detected at {}", first));
+            }
+            clusters.push(cluster);
+            cluster = self.fat_entry(cluster)?;
+        }
+        
+        Ok(clusters)
+    }
+    
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.io.read_at(self.fat_offset + cluster as u64 * 4, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+    
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<()> {
+        self.io.write_at(self.fat_offset + cluster as u64 * 4, &value.to_le_bytes())
+    }
+    
+    fn load_bitmap(&mut self) -> Result<()> {
+        let root = self.root()?;
+        let clusters = self.dir_clusters(&root)?;
+        let data = self.read_clusters(&clusters)?;
+        
+        let entry = data.chunks(ENTRY_SIZE)
+            .take_while(|entry| entry[0] != 0x00)
+            .find(|entry| entry[0] == TYPE_BITMAP && entry[1] & 0x01 == 0)
+            .ok_or_else(|| anyhow::anyhow!("exFAT allocation bitmap not found"))?;
+        
+        let first_cluster = read_u32(entry, 20);
+        let length = read_u64(entry, 24);
+        let count = length.div_ceil(self.cluster_size) as usize;
+        
+        // mkfs tools record the bitmap in the FAT, but fall back to a
+        // contiguous run if the chain is missing.
+        let mut bitmap_clusters = self.chain(first_cluster)?;
+        if bitmap_clusters.len() < count {
+            bitmap_clusters = (first_cluster..first_cluster + count as u32).collect();
+        }
+        bitmap_clusters.truncate(count);
+        
+        let mut bitmap = self.read_clusters(&bitmap_clusters)?;
+        bitmap.truncate(length as usize);
+        self.bitmap = bitmap;
+        self.bitmap_clusters = bitmap_clusters;
+        Ok(())
+    }
+    
+    fn is_allocated(&self, cluster: u32) -> bool {
+        let bit = (cluster - 2) as usize;
+        self.bitmap[bit / 8] & (1 << (bit % 8)) != 0
+    }
+    
+    fn mark_allocated(&mut self, cluster: u32) {
+        let bit = (cluster - 2) as usize;
+        self.bitmap[bit / 8] |= 1 << (bit % 8);
+        self.bitmap_dirty = true;
+    }
+    
+    fn allocate(&mut self, count: u32) -> Result<Vec<u32>> {
+        let max_cluster = self.cluster_count + 1;
+        let mut clusters = Vec::with_capacity(count as usize);
+        let mut cluster = self.next_free.clamp(2, max_cluster);
+        let mut scanned = 0;
+        
+        while clusters.len() < count as usize {
+            if scanned >= self.cluster_count {
+                return Err(anyhow::anyhow!("Not enough free space on volume"));
+            }
+            if !self.is_allocated(cluster) {
+                clusters.push(cluster);
+            }
+            cluster = if cluster >= max_cluster { 2 } else { cluster + 1 };
+            scanned += 1;
+        }
+        
+        for (i, cluster) in clusters.iter().enumerate() {
+            self.mark_allocated(*cluster);
+            let next = clusters.get(i + 1).copied().unwrap_or(END_OF_CHAIN);
+            self.set_fat_entry(*cluster, next)?;
+        }
+        
+        self.next_free = cluster;
+        Ok(clusters)
+    }
+    
+    // PercentInUse is excluded from the boot region checksum, so it can be
+    // updated in place on both the main and backup boot sectors.
+    fn update_percent_in_use(&mut self) -> Result<()> {
+        let used: u64 = self.bitmap.iter().map(|b| b.count_ones() as u64).sum();
+        let percent = (used * 100 / self.cluster_count.max(1) as u64) as u8;
+        let mut boot = vec![0u8; 512];
+        self.io.read_at(0, &mut boot)?;
+        let backup = 12 * (1u64 << boot[108]);
+        
+        for pos in [0, backup] {
+            self.io.write_at(pos + 112, &[percent])?;
+        }
+        Ok(())
+    }
+}
+
+fn subdirectory(parent: &Directory, entry: &EntrySet) -> Directory {
+    Directory {
+        first_cluster: entry.first_cluster,
+        no_fat_chain: entry.no_fat_chain,
+        length: entry.length,
+        entry_set: Some((Box::new(parent.clone()), entry.index)),
+    }
+}
+
+// The first index of `count` consecutive unused entries, or of the free run
+// that reaches the end of the directory when the directory has to grow.
+fn find_free_slots(data: &[u8], count: usize) -> usize {
+    let total = data.len() / ENTRY_SIZE;
+    let mut run_start = 0;
+    let mut run_len = 0;
+    
+    for (index, entry) in data.chunks(ENTRY_SIZE).enumerate() {
+        if entry[0] == 0x00 {
+            return if run_len == 0 { index } else { run_start };
+        }
+        if entry[0] & 0x80 == 0 {
+            if run_len == 0 {
+                run_start = index;
+            }
+            run_len += 1;
+            if run_len == count {
+                return run_start;
+            }
+        } else {
+            run_len = 0;
+        }
+    }
+    
+    if run_len == 0 { total } else { run_start }
+}
+
+fn entry_set(name: &str, attributes: u16, first_cluster: u32, length: u64) -> Result<Vec<[u8; ENTRY_SIZE]>> {
+    let units: Vec<u16> = name.encode_utf16().collect();
+    if units.is_empty() || units.len() > 255 {
+        return Err(anyhow::anyhow!("Invalid file name: {}", name));
+    }
+    
+    let name_entries = units.len().div_ceil(15);
+    let (date, time, centiseconds) = dos_timestamp(SystemTime::now());
+    let timestamp = ((date as u32) << 16) | time as u32;
+    
+    let mut file = [0u8; ENTRY_SIZE];
+    file[0] = TYPE_FILE;
+    file[1] = (1 + name_entries) as u8;
+    file[4..6].copy_from_slice(&attributes.to_le_bytes());
+    file[8..12].copy_from_slice(&timestamp.to_le_bytes());
+    file[12..16].copy_from_slice(&timestamp.to_le_bytes());
+    file[16..20].copy_from_slice(&timestamp.to_le_bytes());
+    file[20] = centiseconds;
+    file[21] = centiseconds;
+    // UTC offset fields: bit 7 marks the offset (zero) as valid.
+    file[22] = 0x80;
+    file[23] = 0x80;
+    file[24] = 0x80;
+    
+    let mut stream = [0u8; ENTRY_SIZE];
+    stream[0] = TYPE_STREAM;
+    stream[1] = if first_cluster == 0 { 0 } else { FLAG_ALLOCATION_POSSIBLE };
+    stream[3] = units.len() as u8;
+    stream[4..6].copy_from_slice(&name_hash(name).to_le_bytes());
+    stream[8..16].copy_from_slice(&length.to_le_bytes());
+    stream[20..24].copy_from_slice(&first_cluster.to_le_bytes());
+    stream[24..32].copy_from_slice(&length.to_le_bytes());
+    
+    let mut entries = vec![file, stream];
+    for chunk in units.chunks(15) {
+        let mut entry = [0u8; ENTRY_SIZE];
+        entry[0] = TYPE_NAME;
+        for (i, unit) in chunk.iter().enumerate() {
+            entry[2 + i * 2..4 + i * 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entries.push(entry);
+    }
+    
+    let flat: Vec<u8> = entries.iter().flatten().copied().collect();
+    let checksum = entry_set_checksum(&flat);
+    entries[0][2..4].copy_from_slice(&checksum.to_le_bytes());
+    
+    Ok(entries)
+}
+
+// SetChecksum covers every byte of the set except the checksum field itself.
+fn entry_set_checksum(set: &[u8]) -> u16 {
+    set.iter().enumerate()
+        .filter(|(i, _)| *i != 2 && *i != 3)
+        .fold(0u16, |sum, (_, b)| (if sum & 1 != 0 { 0x8000u16 } else { 0 }).wrapping_add(sum >> 1).wrapping_add(*b as u16))
+}
+
+fn name_hash(name: &str) -> u16 {
+    upcase(name).iter()
+        .flat_map(|unit| unit.to_le_bytes())
+        .fold(0u16, |hash, b| (if hash & 1 != 0 { 0x8000u16 } else { 0 }).wrapping_add(hash >> 1).wrapping_add(b as u16))
+}
+
+// Approximates the volume up-case table with Unicode simple upper-casing,
+// which matches the mandatory table for the names this tool writes.
+fn upcase(name: &str) -> Vec<u16> {
+    name.encode_utf16()
+        .map(|unit| {
+            char::from_u32(unit as u32)
+                .map(|c| {
+                    let mut upper = c.to_uppercase();
+                    match (upper.next(), upper.next()) {
+                        (Some(u), None) if (u as u32) <= 0xFFFF => u as u16,
+                        _ => unit,
+                    }
+                })
+                .unwrap_or(unit)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::format_exfat;
+    use std::io::Cursor;
+    
+    // 4 KiB clusters, 128 directory entries each.
+    const SIZE: u64 = 64 * 1024 * 1024;
+    
+    fn formatted() -> Cursor<Vec<u8>> {
+        let mut io = SectorIo::new(Cursor::new(vec![0u8; SIZE as usize]), 0, 512);
+        format_exfat(&mut io, SIZE, 0, "TeslaCam").unwrap();
+        io.inner
+    }
+    
+    fn open(disk: &mut Cursor<Vec<u8>>) -> ExfatVolume<&mut Cursor<Vec<u8>>> {
+        ExfatVolume::open(SectorIo::new(disk, 0, 512)).unwrap()
+    }
+    
+    // Every file entry set in `dir` with a valid checksum, by name.
+    fn checked_sets(volume: &mut ExfatVolume<&mut Cursor<Vec<u8>>>, dir: &Directory) -> Vec<EntrySet> {
+        let clusters = volume.dir_clusters(dir).unwrap();
+        let data = volume.read_clusters(&clusters).unwrap();
+        let sets = volume.read_entry_sets(dir).unwrap();
+        
+        for set in &sets {
+            let start = set.index * ENTRY_SIZE;
+            let count = data[start + 1] as usize + 1;
+            let bytes = &data[start..start + count * ENTRY_SIZE];
+            assert_eq!(read_u16(bytes, 2), entry_set_checksum(bytes), "{}", set.name);
+            assert_eq!(read_u16(bytes, ENTRY_SIZE + 4), name_hash(&set.name), "{}", set.name);
+        }
+        
+        sets
+    }
+    
+    #[test]
+    fn directories_grow_past_one_cluster() {
+        let mut disk = formatted();
+        let mut volume = open(&mut disk);
+        volume.create_dir_all("TeslaCam/SentryClips").unwrap();
+        // Three entries per set, so 50 files need two clusters.
+        for i in 0..50 {
+            volume.write_file(&format!("TeslaCam/SentryClips/event-{:02}.mp4", i), &[i as u8; 100]).unwrap();
+        }
+        volume.flush().unwrap();
+        
+        let mut volume = open(&mut disk);
+        let teslacam = volume.resolve_dir(&["TeslaCam"]).unwrap();
+        let sentry = checked_sets(&mut volume, &teslacam).remove(0);
+        assert_eq!(sentry.name, "SentryClips");
+        assert_eq!(sentry.length, 2 * 4096);
+        assert!(!sentry.no_fat_chain);
+        
+        let sentry = subdirectory(&teslacam, &sentry);
+        assert_eq!(volume.dir_clusters(&sentry).unwrap().len(), 2);
+        let sets = checked_sets(&mut volume, &sentry);
+        assert_eq!(sets.len(), 50);
+        assert_eq!(sets[49].name, "event-49.mp4");
+        assert_eq!(volume.read_clusters(&[sets[49].first_cluster]).unwrap()[..100], [49u8; 100]);
+        assert!(volume.exists("TESLACAM/SENTRYCLIPS/EVENT-07.MP4").unwrap());
+        assert!(!volume.is_dirty().unwrap());
+    }
+    
+    #[test]
+    fn long_and_unicode_names_round_trip() {
+        let mut disk = formatted();
+        let mut volume = open(&mut disk);
+        let long = "a".repeat(40) + ".fseq";
+        volume.create_dir_all("LightShow").unwrap();
+        volume.write_file(&format!("LightShow/{}", long), b"show").unwrap();
+        volume.write_file("LightShow/Motörhead Ünïcode.mp3", b"").unwrap();
+        volume.flush().unwrap();
+        
+        let mut volume = open(&mut disk);
+        let lightshow = volume.resolve_dir(&["LightShow"]).unwrap();
+        let sets = checked_sets(&mut volume, &lightshow);
+        let names: Vec<&str> = sets.iter().map(|set| set.name.as_str()).collect();
+        assert_eq!(names, vec![long.as_str(), "Motörhead Ünïcode.mp3"]);
+        assert_eq!((sets[1].first_cluster, sets[1].length), (0, 0));
+        assert!(volume.exists("lightshow/MOTÖRHEAD ÜNÏCODE.MP3").unwrap());
+        assert!(volume.write_file(&format!("LightShow/{}", long.to_uppercase()), b"").is_err());
+        assert!(volume.write_file(&format!("LightShow/{}", "x".repeat(256)), b"").is_err());
+    }
+    
+    #[test]
+    fn bitmap_and_fat_track_allocations() {
+        let mut disk = formatted();
+        let used_after_format = open(&mut disk).bitmap.iter().map(|b| b.count_ones()).sum::<u32>();
+        
+        let mut volume = open(&mut disk);
+        volume.create_dir_all("Music").unwrap();
+        volume.write_file("Music/track.mp3", &[3u8; 10_000]).unwrap();
+        volume.flush().unwrap();
+        
+        // Reopen so the bitmap is the one read back from disk.
+        let mut volume = open(&mut disk);
+        let used: u32 = volume.bitmap.iter().map(|b| b.count_ones()).sum();
+        assert_eq!(used, used_after_format + 1 + 3);
+        assert_eq!(volume.bitmap.len(), (volume.cluster_count as usize).div_ceil(8));
+        
+        let music = volume.resolve_dir(&["Music"]).unwrap();
+        let track = checked_sets(&mut volume, &music).remove(0);
+        let chain = volume.chain(track.first_cluster).unwrap();
+        assert_eq!(chain.len(), 3);
+        assert!(chain.iter().all(|cluster| volume.is_allocated(*cluster)));
+        assert_eq!(track.length, 10_000);
+        
+        let mut boot = vec![0u8; 512];
+        volume.io.read_at(0, &mut boot).unwrap();
+        assert_eq!(boot[112] as u32, used * 100 / volume.cluster_count);
+    }
+}
\ No newline at end of file
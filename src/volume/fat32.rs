@@ -0,0 +1,697 @@
+use super::{dos_timestamp, read_u16, read_u32, split_parent, split_path, BlockDevice, SectorIo};
+use anyhow::Result;
+use std::time::SystemTime;
+
+const ATTR_DIRECTORY: u8 = 0x10;
+const ATTR_ARCHIVE: u8 = 0x20;
+const ATTR_VOLUME_ID: u8 = 0x08;
+const ATTR_LONG_NAME: u8 = 0x0F;
+const END_OF_CHAIN: u32 = 0x0FFF_FFFF;
+const ENTRY_SIZE: usize = 32;
+
+pub struct Fat32Volume<T: BlockDevice> {
+    io: SectorIo<T>,
+    bytes_per_sector: u64,
+    cluster_size: u64,
+    fat_start: u64,
+    fat_size: u64,
+    num_fats: u64,
+    data_start: u64,
+    root_cluster: u32,
+    cluster_count: u32,
+    fsinfo_sector: u64,
+    next_free: u32,
+    allocated: u32,
+}
+
+#[derive(Debug, Clone)]
+struct Fat32Entry {
+    name: String,
+    short_name: [u8; 11],
+    attributes: u8,
+    first_cluster: u32,
+}
+
+impl<T: BlockDevice> Fat32Volume<T> {
+    pub fn open(mut io: SectorIo<T>) -> Result<Self> {
+        let mut boot = vec![0u8; 512];
+        io.read_at(0, &mut boot)?;
+        
+        let bytes_per_sector = read_u16(&boot, 11) as u64;
+        let sectors_per_cluster = boot[13] as u64;
+        let reserved_sectors = read_u16(&boot, 14) as u64;
+        let num_fats = boot[16] as u64;
+        let total_sectors = read_u32(&boot, 32) as u64;
+        let fat_size = read_u32(&boot, 36) as u64;
+        let root_cluster = read_u32(&boot, 44);
+        let fsinfo_sector = read_u16(&boot, 48) as u64;
+        
+        if !bytes_per_sector.is_power_of_two() || bytes_per_sector < 512 || sectors_per_cluster == 0 || num_fats == 0 {
+            return Err(anyhow::anyhow!("Invalid FAT32 boot sector"));
+        }
+        io.set_sector_size(bytes_per_sector);
+        
+        let data_start_sector = reserved_sectors + num_fats * fat_size;
+        if data_start_sector >= total_sectors {
+            return Err(anyhow::anyhow!("Invalid FAT32 boot sector: no room for data after the FATs"));
+        }
+        let cluster_count = ((total_sectors - data_start_sector) / sectors_per_cluster) as u32;
+        
+        let mut volume = Fat32Volume {
+            io,
+            bytes_per_sector,
+            cluster_size: bytes_per_sector * sectors_per_cluster,
+            fat_start: reserved_sectors * bytes_per_sector,
+            fat_size: fat_size * bytes_per_sector,
+            num_fats,
+            data_start: data_start_sector * bytes_per_sector,
+            root_cluster,
+            cluster_count,
+            fsinfo_sector,
+            next_free: 2,
+            allocated: 0,
+        };
+        
+        if let Some(hint) = volume.read_fsinfo_hint()? {
+            volume.next_free = hint;
+        }
+        
+        Ok(volume)
+    }
+    
+    pub fn create_dir_all(&mut self, path: &str) -> Result<()> {
+        let mut dir = self.root_cluster;
+        
+        for part in split_path(path) {
+            dir = match self.find_entry(dir, part)? {
+                Some(entry) if entry.attributes & ATTR_DIRECTORY != 0 => entry.first_cluster,
+                Some(_) => return Err(anyhow::anyhow!("{} exists and is not a directory", part)),
+                None => self.create_dir(dir, part)?,
+            };
+        }
+        
+        Ok(())
+    }
+    
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        let (parents, name) = split_parent(path)?;
+        let dir = self.resolve_dir(&parents)?;
+        
+        if self.find_entry(dir, name)?.is_some() {
+            return Err(anyhow::anyhow!("{} already exists", path));
+        }
+        
+        let first_cluster = if data.is_empty() {
+            0
+        } else {
+            let clusters = self.allocate(data.len().div_ceil(self.cluster_size as usize) as u32)?;
+            for (chunk, cluster) in data.chunks(self.cluster_size as usize).zip(&clusters) {
+                let mut buf = chunk.to_vec();
+                buf.resize(self.cluster_size as usize, 0);
+                let offset = self.cluster_offset(*cluster);
+                self.io.write_at(offset, &buf)?;
+            }
+            clusters[0]
+        };
+        
+        self.add_entry(dir, name, ATTR_ARCHIVE, first_cluster, data.len() as u32)
+    }
+    
+    pub fn exists(&mut self, path: &str) -> Result<bool> {
+        let (parents, name) = match split_parent(path) {
+            Ok(parts) => parts,
+            Err(_) => return Ok(true),
+        };
+        
+        match self.resolve_dir(&parents) {
+            Ok(dir) => Ok(self.find_entry(dir, name)?.is_some()),
+            Err(_) => Ok(false),
+        }
+    }
+    
+    // The clean-shutdown bit in FAT entry 1 is cleared while mounted;
+    // Windows also sets bit 0 of the boot sector's reserved byte.
+    pub fn is_dirty(&mut self) -> Result<bool> {
+        let mut boot = vec![0u8; 512];
+        self.io.read_at(0, &mut boot)?;
+        let mut entry = [0u8; 4];
+        self.io.read_at(self.fat_start + 4, &mut entry)?;
+        
+        Ok(u32::from_le_bytes(entry) & 0x0800_0000 == 0 || boot[65] & 0x01 != 0)
+    }
+    
+    pub fn flush(&mut self) -> Result<()> {
+        self.write_fsinfo()?;
+        self.io.flush()
+    }
+    
+    fn resolve_dir(&mut self, parts: &[&str]) -> Result<u32> {
+        let mut dir = self.root_cluster;
+        
+        for part in parts {
+            dir = match self.find_entry(dir, part)? {
+                Some(entry) if entry.attributes & ATTR_DIRECTORY != 0 => entry.first_cluster,
+                _ => return Err(anyhow::anyhow!("Directory not found: {}", part)),
+            };
+        }
+        
+        Ok(dir)
+    }
+    
+    fn create_dir(&mut self, parent: u32, name: &str) -> Result<u32> {
+        let cluster = self.allocate(1)?[0];
+        let mut buf = vec![0u8; self.cluster_size as usize];
+        
+        // "." and ".." entries; ".." points at cluster 0 when the parent is root.
+        let parent_ref = if parent == self.root_cluster { 0 } else { parent };
+        buf[..ENTRY_SIZE].copy_from_slice(&short_entry(b".          ", ATTR_DIRECTORY, cluster, 0));
+        buf[ENTRY_SIZE..ENTRY_SIZE * 2].copy_from_slice(&short_entry(b"..         ", ATTR_DIRECTORY, parent_ref, 0));
+        let offset = self.cluster_offset(cluster);
+        self.io.write_at(offset, &buf)?;
+        
+        self.add_entry(parent, name, ATTR_DIRECTORY, cluster, 0)?;
+        Ok(cluster)
+    }
+    
+    fn add_entry(&mut self, dir: u32, name: &str, attributes: u8, first_cluster: u32, size: u32) -> Result<()> {
+        let existing = self.read_entries(dir)?;
+        let short_name = generate_short_name(name, &existing);
+        let mut entries = Vec::new();
+        
+        if needs_long_name(name, &short_name) {
+            entries.extend(long_name_entries(name, &short_name)?);
+        }
+        entries.push(short_entry(&short_name, attributes, first_cluster, size));
+        
+        let mut clusters = self.chain(dir)?;
+        let slot = self.find_free_slots(&clusters, entries.len())?;
+        while slot + entries.len() > clusters.len() * self.entries_per_cluster() {
+            let added = self.extend_chain(*clusters.last().unwrap_or(&dir))?;
+            let offset = self.cluster_offset(added);
+            self.io.zero(offset, self.cluster_size)?;
+            clusters.push(added);
+        }
+        
+        for (i, entry) in entries.iter().enumerate() {
+            let pos = self.entry_position(&clusters, slot + i);
+            self.io.write_at(pos, entry)?;
+        }
+        
+        Ok(())
+    }
+    
+    // Returns the first index of `count` consecutive free entries. A free
+    // run that reaches the end of the directory counts, since the caller
+    // extends the directory to fit.
+    fn find_free_slots(&mut self, clusters: &[u32], count: usize) -> Result<usize> {
+        let mut run_start = 0;
+        let mut run_len = 0;
+        
+        for (cluster_index, cluster) in clusters.iter().enumerate() {
+            let data = self.read_cluster(*cluster)?;
+            for (i, entry) in data.chunks(ENTRY_SIZE).enumerate() {
+                let index = cluster_index * self.entries_per_cluster() + i;
+                if entry[0] == 0x00 {
+                    // Everything after the end marker is free as well.
+                    return Ok(if run_len == 0 { index } else { run_start });
+                }
+                if entry[0] == 0xE5 {
+                    if run_len == 0 {
+                        run_start = index;
+                    }
+                    run_len += 1;
+                    if run_len == count {
+                        return Ok(run_start);
+                    }
+                } else {
+                    run_len = 0;
+                }
+            }
+        }
+        
+        Ok(if run_len == 0 { clusters.len() * self.entries_per_cluster() } else { run_start })
+    }
+    
+    fn find_entry(&mut self, dir: u32, name: &str) -> Result<Option<Fat32Entry>> {
+        Ok(self.read_entries(dir)?
+            .into_iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name)))
+    }
+    
+    fn read_entries(&mut self, dir: u32) -> Result<Vec<Fat32Entry>> {
+        let mut entries = Vec::new();
+        let mut long_name: Vec<(u8, Vec<u16>)> = Vec::new();
+        
+        for cluster in self.chain(dir)? {
+            let data = self.read_cluster(cluster)?;
+            for raw in data.chunks(ENTRY_SIZE) {
+                match raw[0] {
+                    0x00 => return Ok(entries),
+                    0xE5 => {
+                        long_name.clear();
+                        continue;
+                    }
+                    _ => {}
+                }
+                
+                if raw[11] == ATTR_LONG_NAME {
+                    long_name.push((raw[13], long_name_chars(raw)));
+                    continue;
+                }
+                
+                // The volume label lives in the root directory as an entry too.
+                if raw[11] & ATTR_VOLUME_ID != 0 {
+                    long_name.clear();
+                    continue;
+                }
+                
+                let mut short_name = [0u8; 11];
+                short_name.copy_from_slice(&raw[..11]);
+                let checksum = short_name_checksum(&short_name);
+                
+                let name = if !long_name.is_empty() && long_name.iter().all(|(c, _)| *c == checksum) {
+                    let units: Vec<u16> = long_name.iter().rev().flat_map(|(_, chars)| chars.clone()).collect();
+                    String::from_utf16_lossy(&units)
+                } else {
+                    format_short_name(&short_name)
+                };
+                long_name.clear();
+                
+                entries.push(Fat32Entry {
+                    name,
+                    short_name,
+                    attributes: raw[11],
+                    first_cluster: ((read_u16(raw, 20) as u32) << 16) | read_u16(raw, 26) as u32,
+                });
+            }
+        }
+        
+        Ok(entries)
+    }
+    
+    fn read_cluster(&mut self, cluster: u32) -> Result<Vec<u8>> {
+        let mut buf = vec![0u8; self.cluster_size as usize];
+        let offset = self.cluster_offset(cluster);
+        self.io.read_at(offset, &mut buf)?;
+        Ok(buf)
+    }
+    
+    fn cluster_offset(&self, cluster: u32) -> u64 {
+        self.data_start + (cluster as u64 - 2) * self.cluster_size
+    }
+    
+    fn entries_per_cluster(&self) -> usize {
+        self.cluster_size as usize / ENTRY_SIZE
+    }
+    
+    fn entry_position(&self, clusters: &[u32], index: usize) -> u64 {
+        let cluster = clusters[index / self.entries_per_cluster()];
+        self.cluster_offset(cluster) + ((index % self.entries_per_cluster()) * ENTRY_SIZE) as u64
+    }
+    
+    fn chain(&mut self, first: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::new();
+        let mut cluster = first;
+        
+        while (2..0x0FFF_FFF8).contains(&cluster) {
+            if clusters.len() > self.cluster_count as usize {
+                return Err(anyhow::anyhow!("Cluster chain loop detected at {}", first));
+            }
+            clusters.push(cluster);
+            cluster = self.fat_entry(cluster)?;
+        }
+        
+        Ok(clusters)
+    }
+    
+    fn fat_entry(&mut self, cluster: u32) -> Result<u32> {
+        let mut buf = [0u8; 4];
+        self.io.read_at(self.fat_start + cluster as u64 * 4, &mut buf)?;
+        Ok(u32::from_le_bytes(buf) & 0x0FFF_FFFF)
+    }
+    
+    fn set_fat_entry(&mut self, cluster: u32, value: u32) -> Result<()> {
+        for fat in 0..self.num_fats {
+            let pos = self.fat_start + fat * self.fat_size + cluster as u64 * 4;
+            let mut buf = [0u8; 4];
+            self.io.read_at(pos, &mut buf)?;
+            let preserved = u32::from_le_bytes(buf) & 0xF000_0000;
+            self.io.write_at(pos, &(preserved | value).to_le_bytes())?;
+        }
+        Ok(())
+    }
+    
+    fn allocate(&mut self, count: u32) -> Result<Vec<u32>> {
+        let mut clusters = Vec::with_capacity(count as usize);
+        let max_cluster = self.cluster_count + 1;
+        let sector_entries = (self.bytes_per_sector / 4) as u32;
+        let mut cluster = self.next_free.clamp(2, max_cluster);
+        let mut wrapped = false;
+        
+        while clusters.len() < count as usize {
+            if cluster > max_cluster {
+                if wrapped {
+                    return Err(anyhow::anyhow!("Not enough free space on volume"));
+                }
+                wrapped = true;
+                cluster = 2;
+            }
+            
+            // Read the FAT a sector at a time rather than entry by entry.
+            let first = cluster - cluster % sector_entries;
+            let mut sector = vec![0u8; self.bytes_per_sector as usize];
+            self.io.read_at(self.fat_start + first as u64 * 4, &mut sector)?;
+            
+            while cluster < first + sector_entries && cluster <= max_cluster && clusters.len() < count as usize {
+                if read_u32(&sector, ((cluster - first) * 4) as usize) & 0x0FFF_FFFF == 0 {
+                    clusters.push(cluster);
+                }
+                cluster += 1;
+            }
+        }
+        
+        for (i, cluster) in clusters.iter().enumerate() {
+            let next = clusters.get(i + 1).copied().unwrap_or(END_OF_CHAIN);
+            self.set_fat_entry(*cluster, next)?;
+        }
+        
+        self.next_free = cluster;
+        self.allocated += count;
+        Ok(clusters)
+    }
+    
+    fn extend_chain(&mut self, last: u32) -> Result<u32> {
+        let added = self.allocate(1)?[0];
+        self.set_fat_entry(last, added)?;
+        Ok(added)
+    }
+    
+    fn read_fsinfo_hint(&mut self) -> Result<Option<u32>> {
+        if self.fsinfo_sector == 0 || self.fsinfo_sector == 0xFFFF {
+            return Ok(None);
+        }
+        
+        let mut buf = vec![0u8; 512];
+        self.io.read_at(self.fsinfo_sector * self.bytes_per_sector, &mut buf)?;
+        if read_u32(&buf, 0) != 0x4161_5252 || read_u32(&buf, 484) != 0x6141_7272 {
+            return Ok(None);
+        }
+        
+        let hint = read_u32(&buf, 492);
+        Ok(if (2..=self.cluster_count + 1).contains(&hint) { Some(hint) } else { None })
+    }
+    
+    fn write_fsinfo(&mut self) -> Result<()> {
+        if self.allocated == 0 || self.fsinfo_sector == 0 || self.fsinfo_sector == 0xFFFF {
+            return Ok(());
+        }
+        
+        let pos = self.fsinfo_sector * self.bytes_per_sector;
+        let mut buf = vec![0u8; 512];
+        self.io.read_at(pos, &mut buf)?;
+        if read_u32(&buf, 0) != 0x4161_5252 || read_u32(&buf, 484) != 0x6141_7272 {
+            return Ok(());
+        }
+        
+        let free = read_u32(&buf, 488);
+        if free != 0xFFFF_FFFF {
+            buf[488..492].copy_from_slice(&free.saturating_sub(self.allocated).to_le_bytes());
+        }
+        buf[492..496].copy_from_slice(&self.next_free.to_le_bytes());
+        self.io.write_at(pos, &buf)?;
+        self.allocated = 0;
+        Ok(())
+    }
+}
+
+fn short_entry(name: &[u8; 11], attributes: u8, first_cluster: u32, size: u32) -> [u8; ENTRY_SIZE] {
+    let (date, time, centiseconds) = dos_timestamp(SystemTime::now());
+    let mut entry = [0u8; ENTRY_SIZE];
+    
+    entry[..11].copy_from_slice(name);
+    entry[11] = attributes;
+    entry[13] = centiseconds;
+    entry[14..16].copy_from_slice(&time.to_le_bytes());
+    entry[16..18].copy_from_slice(&date.to_le_bytes());
+    entry[18..20].copy_from_slice(&date.to_le_bytes());
+    entry[20..22].copy_from_slice(&((first_cluster >> 16) as u16).to_le_bytes());
+    entry[22..24].copy_from_slice(&time.to_le_bytes());
+    entry[24..26].copy_from_slice(&date.to_le_bytes());
+    entry[26..28].copy_from_slice(&(first_cluster as u16).to_le_bytes());
+    entry[28..32].copy_from_slice(&size.to_le_bytes());
+    entry
+}
+
+fn is_short_name_char(c: u8) -> bool {
+    c.is_ascii_uppercase() || c.is_ascii_digit() || b"$%'-_@~`!(){}^#&".contains(&c)
+}
+
+// Builds an 8.3 alias. Names that fit exactly keep their upper-cased form;
+// anything lossy gets a numeric tail unique within the directory.
+fn generate_short_name(name: &str, existing: &[Fat32Entry]) -> [u8; 11] {
+    let upper = name.to_ascii_uppercase();
+    let (base, ext) = match upper.rfind('.') {
+        Some(dot) if dot > 0 => (&upper[..dot], &upper[dot + 1..]),
+        _ => (upper.as_str(), ""),
+    };
+    
+    let clean = |s: &str| -> Vec<u8> {
+        s.bytes().filter(|c| *c != b' ' && *c != b'.').map(|c| if is_short_name_char(c) { c } else { b'_' }).collect()
+    };
+    let base_clean = clean(base);
+    let ext_clean = clean(ext);
+    
+    let lossless = !base_clean.is_empty()
+        && base_clean.len() <= 8
+        && ext_clean.len() <= 3
+        && base_clean == base.as_bytes()
+        && ext_clean == ext.as_bytes();
+    
+    let mut short = [b' '; 11];
+    for (i, c) in ext_clean.iter().take(3).enumerate() {
+        short[8 + i] = *c;
+    }
+    
+    let taken = |candidate: &[u8; 11]| existing.iter().any(|e| &e.short_name == candidate);
+    
+    if lossless {
+        short[..base_clean.len()].copy_from_slice(&base_clean);
+        if !taken(&short) {
+            return short;
+        }
+    }
+    
+    for n in 1..1_000_000u32 {
+        let tail = format!("~{}", n);
+        let keep = (8 - tail.len()).min(base_clean.len()).max(1.min(base_clean.len()));
+        let mut candidate = [b' '; 11];
+        candidate[8..].copy_from_slice(&short[8..]);
+        candidate[..keep].copy_from_slice(&base_clean[..keep]);
+        candidate[keep..keep + tail.len()].copy_from_slice(tail.as_bytes());
+        if !taken(&candidate) {
+            return candidate;
+        }
+    }
+    
+    short
+}
+
+fn needs_long_name(name: &str, short_name: &[u8; 11]) -> bool {
+    format_short_name(short_name) != name
+}
+
+fn format_short_name(short_name: &[u8; 11]) -> String {
+    let base = String::from_utf8_lossy(&short_name[..8]).trim_end().to_string();
+    let ext = String::from_utf8_lossy(&short_name[8..]).trim_end().to_string();
+    if ext.is_empty() { base } else { format!("{}.{}", base, ext) }
+}
+
+fn short_name_checksum(short_name: &[u8; 11]) -> u8 {
+    short_name.iter().fold(0u8, |sum, c| ((sum & 1) << 7).wrapping_add(sum >> 1).wrapping_add(*c))
+}
+
+fn long_name_entries(name: &str, short_name: &[u8; 11]) -> Result<Vec<[u8; ENTRY_SIZE]>> {
+    let mut units: Vec<u16> = name.encode_utf16().collect();
+    if units.len() > 255 {
+        return Err(anyhow::anyhow!("File name too long: {}", name));
+    }
+    if !units.len().is_multiple_of(13) {
+        units.push(0);
+        while !units.len().is_multiple_of(13) {
+            units.push(0xFFFF);
+        }
+    }
+    
+    let checksum = short_name_checksum(short_name);
+    let count = units.len() / 13;
+    let mut entries = Vec::with_capacity(count);
+    
+    // Long name entries are stored last-part-first.
+    for seq in (1..=count).rev() {
+        let chars = &units[(seq - 1) * 13..seq * 13];
+        let mut entry = [0u8; ENTRY_SIZE];
+        entry[0] = seq as u8 | if seq == count { 0x40 } else { 0 };
+        entry[11] = ATTR_LONG_NAME;
+        entry[13] = checksum;
+        for (i, unit) in chars.iter().enumerate() {
+            let pos = match i {
+                0..=4 => 1 + i * 2,
+                5..=10 => 14 + (i - 5) * 2,
+                _ => 28 + (i - 11) * 2,
+            };
+            entry[pos..pos + 2].copy_from_slice(&unit.to_le_bytes());
+        }
+        entries.push(entry);
+    }
+    
+    Ok(entries)
+}
+
+fn long_name_chars(raw: &[u8]) -> Vec<u16> {
+    let positions = [1, 3, 5, 7, 9, 14, 16, 18, 20, 22, 24, 28, 30];
+    positions.iter()
+        .map(|pos| read_u16(raw, *pos))
+        .take_while(|unit| *unit != 0 && *unit != 0xFFFF)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::format_fat32;
+    use std::io::Cursor;
+    
+    // Small enough that clusters are a single sector, 16 entries each.
+    const SIZE: u64 = 64 * 1024 * 1024;
+    
+    fn formatted() -> Cursor<Vec<u8>> {
+        let mut io = SectorIo::new(Cursor::new(vec![0u8; SIZE as usize]), 0, 512);
+        format_fat32(&mut io, SIZE, 0, "TESLACAM").unwrap();
+        io.inner
+    }
+    
+    fn open(disk: &mut Cursor<Vec<u8>>) -> Fat32Volume<&mut Cursor<Vec<u8>>> {
+        Fat32Volume::open(SectorIo::new(disk, 0, 512)).unwrap()
+    }
+    
+    fn dir(volume: &mut Fat32Volume<&mut Cursor<Vec<u8>>>, path: &str) -> u32 {
+        let parts = split_path(path);
+        volume.resolve_dir(&parts).unwrap()
+    }
+    
+    #[test]
+    fn open_rejects_inconsistent_boot_sectors() {
+        let open_err = |disk: &mut Cursor<Vec<u8>>| {
+            Fat32Volume::open(SectorIo::new(disk, 0, 512)).err().map(|e| e.to_string())
+        };
+        
+        let mut disk = formatted();
+        disk.get_mut()[13] = 0;
+        assert_eq!(open_err(&mut disk).as_deref(), Some("Invalid FAT32 boot sector"));
+        
+        // The FATs claim more sectors than the whole volume has.
+        let mut disk = formatted();
+        disk.get_mut()[32..36].copy_from_slice(&1000u32.to_le_bytes());
+        assert_eq!(
+            open_err(&mut disk).as_deref(),
+            Some("Invalid FAT32 boot sector: no room for data after the FATs")
+        );
+    }
+    
+    #[test]
+    fn directories_grow_past_one_cluster() {
+        let mut disk = formatted();
+        let mut volume = open(&mut disk);
+        volume.create_dir_all("TeslaCam/SavedClips").unwrap();
+        for i in 0..40 {
+            volume.write_file(&format!("TeslaCam/SavedClips/CLIP{:02}.MP4", i), &[i as u8; 700]).unwrap();
+        }
+        volume.flush().unwrap();
+        
+        let mut volume = open(&mut disk);
+        let clips = dir(&mut volume, "TeslaCam/SavedClips");
+        // ".", ".." and 40 short-name entries at 16 per cluster.
+        assert_eq!(volume.chain(clips).unwrap().len(), 3);
+        for i in 0..40 {
+            assert!(volume.exists(&format!("teslacam/savedclips/clip{:02}.mp4", i)).unwrap());
+        }
+        assert!(!volume.exists("TeslaCam/SavedClips/CLIP40.MP4").unwrap());
+        
+        let entry = volume.find_entry(clips, "CLIP07.MP4").unwrap().unwrap();
+        assert_eq!(volume.chain(entry.first_cluster).unwrap().len(), 2);
+        assert_eq!(volume.read_cluster(entry.first_cluster).unwrap(), vec![7u8; 512]);
+        assert!(!volume.is_dirty().unwrap());
+    }
+    
+    #[test]
+    fn long_names_get_numbered_short_aliases() {
+        let mut disk = formatted();
+        let mut volume = open(&mut disk);
+        volume.create_dir_all("Music").unwrap();
+        volume.write_file("Music/Long Track Name.mp3", b"one").unwrap();
+        volume.write_file("Music/Long Track Name 2.mp3", b"two").unwrap();
+        volume.write_file("Music/a very long file name that needs several entries.flac", b"three").unwrap();
+        volume.write_file("Music/README.TXT", b"four").unwrap();
+        volume.write_file("Music/readme.txt.bak", b"five").unwrap();
+        volume.flush().unwrap();
+        
+        let mut volume = open(&mut disk);
+        let music = dir(&mut volume, "Music");
+        let names: Vec<(String, String)> = volume.read_entries(music).unwrap()
+            .into_iter()
+            .map(|entry| (entry.name, String::from_utf8_lossy(&entry.short_name).into_owned()))
+            .collect();
+        assert_eq!(names, vec![
+            (".".to_string(), ".          ".to_string()),
+            ("..".to_string(), "..         ".to_string()),
+            ("Long Track Name.mp3".to_string(), "LONGTR~1MP3".to_string()),
+            ("Long Track Name 2.mp3".to_string(), "LONGTR~2MP3".to_string()),
+            ("a very long file name that needs several entries.flac".to_string(), "AVERYL~1FLA".to_string()),
+            ("README.TXT".to_string(), "README  TXT".to_string()),
+            ("readme.txt.bak".to_string(), "README~1BAK".to_string()),
+        ]);
+        
+        assert!(volume.write_file("Music/LONG TRACK NAME.MP3", b"").is_err());
+        assert!(volume.create_dir_all("Music/README.TXT").is_err());
+    }
+    
+    #[test]
+    fn fsinfo_and_both_fats_track_allocations() {
+        let mut disk = formatted();
+        let mut volume = open(&mut disk);
+        let fat_entries = volume.cluster_count as usize + 2;
+        
+        let read_fat = |volume: &mut Fat32Volume<&mut Cursor<Vec<u8>>>, n: u64| {
+            let mut fat = vec![0u8; volume.fat_size as usize];
+            let pos = volume.fat_start + n * volume.fat_size;
+            volume.io.read_at(pos, &mut fat).unwrap();
+            fat
+        };
+        let free_in_fat = |fat: &[u8]| (2..fat_entries).filter(|c| read_u32(fat, c * 4) & 0x0FFF_FFFF == 0).count() as u32;
+        let fsinfo = |volume: &mut Fat32Volume<&mut Cursor<Vec<u8>>>| {
+            let mut buf = vec![0u8; 512];
+            volume.io.read_at(512, &mut buf).unwrap();
+            (read_u32(&buf, 488), read_u32(&buf, 492))
+        };
+        
+        let fat = read_fat(&mut volume, 0);
+        assert_eq!(fsinfo(&mut volume).0, free_in_fat(&fat));
+        
+        volume.create_dir_all("TeslaCam/RecentClips").unwrap();
+        volume.write_file("TeslaCam/RecentClips/clip.mp4", &[1u8; 5000]).unwrap();
+        volume.flush().unwrap();
+        
+        let mut volume = open(&mut disk);
+        let fat = read_fat(&mut volume, 0);
+        assert_eq!(fat, read_fat(&mut volume, 1));
+        let (free, next) = fsinfo(&mut volume);
+        assert_eq!(free, free_in_fat(&fat));
+        assert_eq!(free, volume.cluster_count - 1 - 2 - 10);
+        assert_eq!(read_u32(&fat, next as usize * 4), 0);
+        
+        // A reopened volume continues from the hint instead of reusing clusters.
+        volume.write_file("after.bin", &[2u8; 10]).unwrap();
+        let entry = volume.find_entry(volume.root_cluster, "after.bin").unwrap().unwrap();
+        assert_eq!(entry.first_cluster, next);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,391 @@
+use super::{BlockDevice, SectorIo};
+use anyhow::Result;
+
+const SECTOR_SIZE: u64 = 512;
+
+// Creates an empty exFAT filesystem in `size` bytes starting at the
+// start of `io`. `partition_offset` is the partition's first sector on the
+// disk, recorded in the boot sector as the spec asks.
+pub fn format_exfat<T: BlockDevice>(io: &mut SectorIo<T>, size: u64, partition_offset: u64, label: &str) -> Result<()> {
+    let label_units: Vec<u16> = label.encode_utf16().collect();
+    if label_units.len() > 11 {
+        return Err(anyhow::anyhow!("Volume label {} is longer than the 11 characters exFAT allows", label));
+    }
+    
+    let total_sectors = size / SECTOR_SIZE;
+    let cluster_size: u64 = if size <= 256 * 1024 * 1024 {
+        4096
+    } else if size <= 32 * 1024 * 1024 * 1024 {
+        32 * 1024
+    } else {
+        128 * 1024
+    };
+    let sectors_per_cluster = cluster_size / SECTOR_SIZE;
+    
+    // Align the FAT and the cluster heap to 1 MiB like Windows and
+    // exfatprogs do, which keeps flash erase blocks and clusters aligned.
+    let alignment = (1024 * 1024 / SECTOR_SIZE).max(sectors_per_cluster);
+    let fat_offset = alignment;
+    let mut cluster_count = (total_sectors - fat_offset) / sectors_per_cluster;
+    let fat_length = ((cluster_count + 2) * 4).div_ceil(SECTOR_SIZE);
+    let heap_offset = (fat_offset + fat_length).div_ceil(alignment) * alignment;
+    if heap_offset >= total_sectors {
+        return Err(anyhow::anyhow!("Partition too small for exFAT"));
+    }
+    cluster_count = cluster_count.min((total_sectors - heap_offset) / sectors_per_cluster);
+    
+    let bitmap_length = cluster_count.div_ceil(8);
+    let bitmap_clusters = bitmap_length.div_ceil(cluster_size);
+    let upcase = compressed_upcase_table();
+    let upcase_bytes: Vec<u8> = upcase.iter().flat_map(|u| u.to_le_bytes()).collect();
+    let upcase_clusters = (upcase_bytes.len() as u64).div_ceil(cluster_size);
+    
+    let bitmap_first = 2u32;
+    let upcase_first = bitmap_first + bitmap_clusters as u32;
+    let root_first = upcase_first + upcase_clusters as u32;
+    let used_clusters = bitmap_clusters + upcase_clusters + 1;
+    
+    // Boot region, then its backup at sector 12.
+    let mut boot = vec![0u8; SECTOR_SIZE as usize];
+    boot[0..3].copy_from_slice(&[0xEB, 0x76, 0x90]);
+    boot[3..11].copy_from_slice(b"EXFAT   ");
+    boot[64..72].copy_from_slice(&partition_offset.to_le_bytes());
+    boot[72..80].copy_from_slice(&total_sectors.to_le_bytes());
+    boot[80..84].copy_from_slice(&(fat_offset as u32).to_le_bytes());
+    boot[84..88].copy_from_slice(&(fat_length as u32).to_le_bytes());
+    boot[88..92].copy_from_slice(&(heap_offset as u32).to_le_bytes());
+    boot[92..96].copy_from_slice(&(cluster_count as u32).to_le_bytes());
+    boot[96..100].copy_from_slice(&root_first.to_le_bytes());
+    boot[100..104].copy_from_slice(&volume_serial().to_le_bytes());
+    boot[104..106].copy_from_slice(&0x0100u16.to_le_bytes());
+    boot[108] = SECTOR_SIZE.trailing_zeros() as u8;
+    boot[109] = sectors_per_cluster.trailing_zeros() as u8;
+    boot[110] = 1;
+    boot[111] = 0x80;
+    boot[112] = (used_clusters * 100 / cluster_count) as u8;
+    boot[510] = 0x55;
+    boot[511] = 0xAA;
+    
+    let mut region = vec![0u8; 12 * SECTOR_SIZE as usize];
+    region[..SECTOR_SIZE as usize].copy_from_slice(&boot);
+    for sector in 1..=8 {
+        let end = (sector + 1) * SECTOR_SIZE as usize;
+        region[end - 2] = 0x55;
+        region[end - 1] = 0xAA;
+    }
+    let checksum = boot_checksum(&region[..11 * SECTOR_SIZE as usize]);
+    for chunk in region[11 * SECTOR_SIZE as usize..].chunks_mut(4) {
+        chunk.copy_from_slice(&checksum.to_le_bytes());
+    }
+    io.write_at(0, &region)?;
+    io.write_at(12 * SECTOR_SIZE, &region)?;
+    
+    // FAT: media descriptor, reserved entry, then the three system chains.
+    io.zero(fat_offset * SECTOR_SIZE, fat_length * SECTOR_SIZE)?;
+    let mut fat = vec![0u8; ((root_first as usize) + 1) * 4];
+    let mut set_fat = |cluster: u32, value: u32| {
+        fat[cluster as usize * 4..cluster as usize * 4 + 4].copy_from_slice(&value.to_le_bytes());
+    };
+    set_fat(0, 0xFFFF_FFF8);
+    set_fat(1, 0xFFFF_FFFF);
+    for (first, count) in [(bitmap_first, bitmap_clusters), (upcase_first, upcase_clusters), (root_first, 1)] {
+        for i in 0..count as u32 {
+            let next = if i + 1 == count as u32 { 0xFFFF_FFFF } else { first + i + 1 };
+            set_fat(first + i, next);
+        }
+    }
+    io.write_at(fat_offset * SECTOR_SIZE, &fat)?;
+    
+    let heap = heap_offset * SECTOR_SIZE;
+    let cluster_pos = |cluster: u32| heap + (cluster as u64 - 2) * cluster_size;
+    
+    let mut bitmap = vec![0u8; (bitmap_clusters * cluster_size) as usize];
+    for bit in 0..used_clusters as usize {
+        bitmap[bit / 8] |= 1 << (bit % 8);
+    }
+    io.write_at(cluster_pos(bitmap_first), &bitmap)?;
+    
+    let mut upcase_data = upcase_bytes.clone();
+    upcase_data.resize((upcase_clusters * cluster_size) as usize, 0);
+    io.write_at(cluster_pos(upcase_first), &upcase_data)?;
+    
+    let mut root = vec![0u8; cluster_size as usize];
+    root[0] = 0x83;
+    root[1] = label_units.len() as u8;
+    for (i, unit) in label_units.iter().enumerate() {
+        root[2 + i * 2..4 + i * 2].copy_from_slice(&unit.to_le_bytes());
+    }
+    root[32] = 0x81;
+    root[32 + 20..32 + 24].copy_from_slice(&bitmap_first.to_le_bytes());
+    root[32 + 24..32 + 32].copy_from_slice(&bitmap_length.to_le_bytes());
+    root[64] = 0x82;
+    root[64 + 4..64 + 8].copy_from_slice(&table_checksum(&upcase_bytes).to_le_bytes());
+    root[64 + 20..64 + 24].copy_from_slice(&upcase_first.to_le_bytes());
+    root[64 + 24..64 + 32].copy_from_slice(&(upcase_bytes.len() as u64).to_le_bytes());
+    io.write_at(cluster_pos(root_first), &root)?;
+    
+    io.flush()
+}
+
+// Creates an empty FAT32 filesystem following the Microsoft FAT
+// specification's sizing rules.
+pub fn format_fat32<T: BlockDevice>(io: &mut SectorIo<T>, size: u64, partition_offset: u64, label: &str) -> Result<()> {
+    let total_sectors = size / SECTOR_SIZE;
+    if total_sectors > u32::MAX as u64 {
+        return Err(anyhow::anyhow!("Partition too large for FAT32"));
+    }
+    let label = fat_label(label)?;
+    
+    let reserved_sectors = 32u64;
+    let num_fats = 2u64;
+    let mut sectors_per_cluster: u64 = match size {
+        s if s <= 8 * 1024 * 1024 * 1024 => 8,
+        s if s <= 16 * 1024 * 1024 * 1024 => 16,
+        s if s <= 32 * 1024 * 1024 * 1024 => 32,
+        _ => 64,
+    };
+    
+    // FAT32 needs at least 65525 clusters; small partitions get smaller clusters.
+    let (fat_size, cluster_count) = loop {
+        let tmp1 = total_sectors - reserved_sectors;
+        let tmp2 = (256 * sectors_per_cluster + num_fats) / 2;
+        let fat_size = tmp1.div_ceil(tmp2);
+        let cluster_count = (total_sectors - reserved_sectors - num_fats * fat_size) / sectors_per_cluster;
+        if cluster_count >= 65525 || sectors_per_cluster == 1 {
+            break (fat_size, cluster_count);
+        }
+        sectors_per_cluster /= 2;
+    };
+    if cluster_count < 65525 {
+        return Err(anyhow::anyhow!("Partition too small for FAT32"));
+    }
+    
+    let mut boot = vec![0u8; SECTOR_SIZE as usize];
+    boot[0..3].copy_from_slice(&[0xEB, 0x58, 0x90]);
+    boot[3..11].copy_from_slice(b"MSWIN4.1");
+    boot[11..13].copy_from_slice(&(SECTOR_SIZE as u16).to_le_bytes());
+    boot[13] = sectors_per_cluster as u8;
+    boot[14..16].copy_from_slice(&(reserved_sectors as u16).to_le_bytes());
+    boot[16] = num_fats as u8;
+    boot[21] = 0xF8;
+    boot[24..26].copy_from_slice(&63u16.to_le_bytes());
+    boot[26..28].copy_from_slice(&255u16.to_le_bytes());
+    boot[28..32].copy_from_slice(&(partition_offset as u32).to_le_bytes());
+    boot[32..36].copy_from_slice(&(total_sectors as u32).to_le_bytes());
+    boot[36..40].copy_from_slice(&(fat_size as u32).to_le_bytes());
+    boot[44..48].copy_from_slice(&2u32.to_le_bytes());
+    boot[48..50].copy_from_slice(&1u16.to_le_bytes());
+    boot[50..52].copy_from_slice(&6u16.to_le_bytes());
+    boot[64] = 0x80;
+    boot[66] = 0x29;
+    boot[67..71].copy_from_slice(&volume_serial().to_le_bytes());
+    boot[71..82].copy_from_slice(&label);
+    boot[82..90].copy_from_slice(b"FAT32   ");
+    boot[510] = 0x55;
+    boot[511] = 0xAA;
+    
+    let mut fsinfo = vec![0u8; SECTOR_SIZE as usize];
+    fsinfo[0..4].copy_from_slice(&0x4161_5252u32.to_le_bytes());
+    fsinfo[484..488].copy_from_slice(&0x6141_7272u32.to_le_bytes());
+    fsinfo[488..492].copy_from_slice(&((cluster_count - 1) as u32).to_le_bytes());
+    fsinfo[492..496].copy_from_slice(&3u32.to_le_bytes());
+    fsinfo[508..512].copy_from_slice(&0xAA55_0000u32.to_le_bytes());
+    
+    io.zero(0, reserved_sectors * SECTOR_SIZE)?;
+    for base in [0, 6] {
+        io.write_at(base * SECTOR_SIZE, &boot)?;
+        io.write_at((base + 1) * SECTOR_SIZE, &fsinfo)?;
+    }
+    
+    let mut fat_head = vec![0u8; 12];
+    fat_head[0..4].copy_from_slice(&0x0FFF_FFF8u32.to_le_bytes());
+    fat_head[4..8].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+    fat_head[8..12].copy_from_slice(&0x0FFF_FFFFu32.to_le_bytes());
+    for fat in 0..num_fats {
+        let start = (reserved_sectors + fat * fat_size) * SECTOR_SIZE;
+        io.zero(start, fat_size * SECTOR_SIZE)?;
+        io.write_at(start, &fat_head)?;
+    }
+    
+    let cluster_size = sectors_per_cluster * SECTOR_SIZE;
+    let mut root = vec![0u8; cluster_size as usize];
+    root[..11].copy_from_slice(&label);
+    root[11] = 0x08;
+    io.write_at((reserved_sectors + num_fats * fat_size) * SECTOR_SIZE, &root)?;
+    
+    io.flush()
+}
+
+// FAT labels are stored upper-cased and space padded, in the OEM code page,
+// so only printable ASCII outside the characters DOS reserves is accepted.
+fn fat_label(label: &str) -> Result<[u8; 11]> {
+    let invalid = |c: u8| !(c.is_ascii_graphic() || c == b' ') || b"\"*+,./:;<=>?[\\]|".contains(&c);
+    if label.len() > 11 || label.bytes().any(invalid) {
+        return Err(anyhow::anyhow!("Volume label {} is not a valid FAT32 label (up to 11 ASCII characters)", label));
+    }
+    
+    let mut out = [b' '; 11];
+    out[..label.len()].copy_from_slice(label.to_ascii_uppercase().as_bytes());
+    Ok(out)
+}
+
+fn volume_serial() -> u32 {
+    let id = uuid::Uuid::new_v4();
+    let bytes = id.as_bytes();
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+// Boot region checksum over sectors 0-10, skipping VolumeFlags and
+// PercentInUse which may change without rewriting the checksum.
+fn boot_checksum(data: &[u8]) -> u32 {
+    data.iter().enumerate()
+        .filter(|(i, _)| !matches!(*i, 106 | 107 | 112))
+        .fold(0u32, |sum, (_, b)| (if sum & 1 != 0 { 0x8000_0000u32 } else { 0 }).wrapping_add(sum >> 1).wrapping_add(*b as u32))
+}
+
+fn table_checksum(data: &[u8]) -> u32 {
+    data.iter()
+        .fold(0u32, |sum, b| (if sum & 1 != 0 { 0x8000_0000u32 } else { 0 }).wrapping_add(sum >> 1).wrapping_add(*b as u32))
+}
+
+// Up-case table in the spec's compressed form: runs of identity mappings
+// are written as 0xFFFF followed by the run length.
+fn compressed_upcase_table() -> Vec<u16> {
+    let mut table = Vec::new();
+    let mut unit: u32 = 0;
+    
+    while unit <= 0xFFFF {
+        let upper = upcase_unit(unit as u16);
+        if upper != unit as u16 {
+            table.push(upper);
+            unit += 1;
+            continue;
+        }
+        
+        let start = unit;
+        while unit <= 0xFFFF && upcase_unit(unit as u16) == unit as u16 {
+            unit += 1;
+        }
+        let run = unit - start;
+        if run == 1 {
+            table.push(start as u16);
+        } else {
+            table.push(0xFFFF);
+            table.push(run as u16);
+        }
+    }
+    
+    table
+}
+
+fn upcase_unit(unit: u16) -> u16 {
+    char::from_u32(unit as u32)
+        .map(|c| {
+            let mut upper = c.to_uppercase();
+            match (upper.next(), upper.next()) {
+                (Some(u), None) if (u as u32) <= 0xFFFF => u as u16,
+                _ => unit,
+            }
+        })
+        .unwrap_or(unit)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::volume::{read_u32, ExfatVolume, Volume};
+    use std::io::Cursor;
+    
+    const SIZE: u64 = 64 * 1024 * 1024;
+    
+    fn exfat_image() -> Cursor<Vec<u8>> {
+        let mut io = SectorIo::new(Cursor::new(vec![0u8; SIZE as usize]), 0, 512);
+        format_exfat(&mut io, SIZE, 2048, "TeslaCam").unwrap();
+        io.inner
+    }
+    
+    // Sectors 0-10 of each boot region, the checksum sector that follows, and
+    // the backup region at sector 12 matching the main one.
+    fn assert_boot_checksums(disk: &[u8]) {
+        let region = 12 * SECTOR_SIZE as usize;
+        let checksum = boot_checksum(&disk[..11 * SECTOR_SIZE as usize]);
+        for word in disk[11 * SECTOR_SIZE as usize..region].chunks(4) {
+            assert_eq!(u32::from_le_bytes(word.try_into().unwrap()), checksum);
+        }
+        assert_eq!(disk[..region], disk[region..region * 2]);
+    }
+    
+    #[test]
+    fn exfat_boot_region_checksum_survives_writes() {
+        let mut disk = exfat_image();
+        assert_boot_checksums(disk.get_ref());
+        assert_eq!(&disk.get_ref()[64..72], &2048u64.to_le_bytes());
+        
+        // Writing files updates PercentInUse, which the checksum skips.
+        let mut volume = ExfatVolume::open(SectorIo::new(&mut disk, 0, 512)).unwrap();
+        volume.write_file("big.bin", &vec![7u8; 2 * 1024 * 1024]).unwrap();
+        volume.flush().unwrap();
+        
+        assert_eq!(disk.get_ref()[112], 3);
+        assert_boot_checksums(disk.get_ref());
+    }
+    
+    #[test]
+    fn exfat_root_describes_label_bitmap_and_upcase_table() {
+        let disk = exfat_image();
+        let disk = disk.get_ref();
+        let heap = read_u32(disk, 88) as usize * 512;
+        let cluster = |n: u32| heap + (n as usize - 2) * 4096;
+        let root = &disk[cluster(read_u32(disk, 96))..];
+        
+        assert_eq!(root[0], 0x83);
+        let label: Vec<u16> = root[2..2 + root[1] as usize * 2].chunks(2).map(|u| u16::from_le_bytes([u[0], u[1]])).collect();
+        assert_eq!(String::from_utf16(&label).unwrap(), "TeslaCam");
+        
+        assert_eq!(root[64], 0x82);
+        let length = u64::from_le_bytes(root[88..96].try_into().unwrap()) as usize;
+        let table = &disk[cluster(read_u32(root, 84))..][..length];
+        assert_eq!(read_u32(root, 68), table_checksum(table));
+        assert_eq!(table, compressed_upcase_table().iter().flat_map(|u| u.to_le_bytes()).collect::<Vec<u8>>());
+        assert_eq!(upcase_unit('a' as u16), 'A' as u16);
+        assert_eq!(upcase_unit(0x00E9), 0x00C9);
+        
+        // The bitmap covers every cluster and marks itself, the up-case
+        // table and the root directory as used.
+        assert_eq!(root[32], 0x81);
+        let cluster_count = read_u32(disk, 92) as usize;
+        assert_eq!(u64::from_le_bytes(root[56..64].try_into().unwrap()), cluster_count.div_ceil(8) as u64);
+        let bitmap = &disk[cluster(read_u32(root, 52))..][..cluster_count.div_ceil(8)];
+        let used = 1 + length.div_ceil(4096) + 1;
+        assert_eq!(bitmap.iter().map(|b| b.count_ones() as usize).sum::<usize>(), used);
+        assert_eq!(bitmap[0], (1u8 << used) - 1);
+    }
+    
+    #[test]
+    fn labels_are_rejected_rather_than_truncated() {
+        for filesystem in ["exfat", "fat32"] {
+            let mut disk = Cursor::new(vec![0u8; SIZE as usize]);
+            let error = crate::volume::format_volume(&mut disk, 0, SIZE, filesystem, "TeslaLightshow").unwrap_err();
+            
+            assert!(error.to_string().contains("TeslaLightshow"), "{}", error);
+            assert!(disk.get_ref().iter().all(|b| *b == 0), "{} wrote before failing", filesystem);
+        }
+        
+        assert!(fat_label("Music/Video").is_err());
+        assert!(fat_label("Musik\u{e4}").is_err());
+        assert_eq!(&fat_label("Tesla Cam").unwrap(), b"TESLA CAM  ");
+    }
+    
+    #[test]
+    fn fat32_label_is_in_boot_sector_and_root() {
+        let mut disk = Cursor::new(vec![0u8; SIZE as usize]);
+        crate::volume::format_volume(&mut disk, 0, SIZE, "fat32", "music").unwrap();
+        let disk = disk.into_inner();
+        
+        assert_eq!(&disk[71..82], b"MUSIC      ");
+        assert_eq!(disk[..512], disk[6 * 512..7 * 512], "backup boot sector");
+        let data_start = (32 + 2 * read_u32(&disk, 36) as usize) * 512;
+        assert_eq!(&disk[data_start..data_start + 12], b"MUSIC      \x08");
+        assert!(Volume::open(Cursor::new(disk), 0).is_ok());
+    }
+}
\ No newline at end of file
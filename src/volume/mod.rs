@@ -0,0 +1,345 @@
+mod exfat;
+mod fat32;
+mod format;
+
+pub use exfat::ExfatVolume;
+pub use fat32::Fat32Volume;
+pub use format::{format_exfat, format_fat32};
+
+use anyhow::Result;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub trait BlockDevice: Read + Write + Seek + Send {}
+impl<T: Read + Write + Seek + Send> BlockDevice for T {}
+
+// Writes directories and small files straight into a FAT32 or exFAT
+// filesystem, so preparing a drive doesn't depend on the OS mounting it.
+pub enum Volume<T: BlockDevice> {
+    Fat32(Fat32Volume<T>),
+    Exfat(ExfatVolume<T>),
+}
+
+impl<T: BlockDevice> Volume<T> {
+    pub fn open(inner: T, offset: u64) -> Result<Self> {
+        let mut io = SectorIo::new(inner, offset, 512);
+        let mut boot = vec![0u8; 512];
+        io.read_at(0, &mut boot)?;
+        
+        if &boot[3..11] == b"EXFAT   " {
+            Ok(Volume::Exfat(ExfatVolume::open(io)?))
+        } else if &boot[82..90] == b"FAT32   " {
+            Ok(Volume::Fat32(Fat32Volume::open(io)?))
+        } else {
+            Err(anyhow::anyhow!("Unsupported filesystem: expected FAT32 or exFAT"))
+        }
+    }
+    
+    pub fn create_dir_all(&mut self, path: &str) -> Result<()> {
+        match self {
+            Volume::Fat32(volume) => volume.create_dir_all(path),
+            Volume::Exfat(volume) => volume.create_dir_all(path),
+        }
+    }
+    
+    pub fn write_file(&mut self, path: &str, data: &[u8]) -> Result<()> {
+        match self {
+            Volume::Fat32(volume) => volume.write_file(path, data),
+            Volume::Exfat(volume) => volume.write_file(path, data),
+        }
+    }
+    
+    pub fn exists(&mut self, path: &str) -> Result<bool> {
+        match self {
+            Volume::Fat32(volume) => volume.exists(path),
+            Volume::Exfat(volume) => volume.exists(path),
+        }
+    }
+    
+    // Set while the filesystem is mounted read-write and cleared on a clean
+    // unmount, so a drive pulled out of the car mid-write shows as dirty.
+    pub fn is_dirty(&mut self) -> Result<bool> {
+        match self {
+            Volume::Fat32(volume) => volume.is_dirty(),
+            Volume::Exfat(volume) => volume.is_dirty(),
+        }
+    }
+    
+    pub fn filesystem(&self) -> &'static str {
+        match self {
+            Volume::Fat32(_) => "fat32",
+            Volume::Exfat(_) => "exfat",
+        }
+    }
+    
+    pub fn flush(&mut self) -> Result<()> {
+        match self {
+            Volume::Fat32(volume) => volume.flush(),
+            Volume::Exfat(volume) => volume.flush(),
+        }
+    }
+}
+
+pub fn format_volume<T: BlockDevice>(inner: T, offset: u64, size: u64, filesystem: &str, label: &str) -> Result<()> {
+    let mut io = SectorIo::new(inner, offset, 512);
+    let partition_sector = offset / 512;
+    
+    match filesystem {
+        "exfat" => format_exfat(&mut io, size, partition_sector, label),
+        "fat32" => format_fat32(&mut io, size, partition_sector, label),
+        other => Err(anyhow::anyhow!("Cannot format {} without system tools", other)),
+    }
+}
+
+// Opens a partition for raw access. On Windows the volume is locked and
+// dismounted first, since NTFS/FAT drivers reject writes to mounted volumes.
+pub fn open_partition(path: &str) -> Result<std::fs::File> {
+    #[cfg(target_os = "windows")]
+    {
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::ioapiset::DeviceIoControl;
+        use winapi::um::winioctl::{FSCTL_DISMOUNT_VOLUME, FSCTL_LOCK_VOLUME};
+        
+        let raw_path = if path.starts_with("\\\\") {
+            path.to_string()
+        } else {
+            format!("\\\\.\\{}", path.trim_end_matches('\\'))
+        };
+        let file = std::fs::OpenOptions::new().read(true).write(true).open(&raw_path)?;
+        
+        let mut returned = 0u32;
+        for control in [FSCTL_LOCK_VOLUME, FSCTL_DISMOUNT_VOLUME] {
+            let ok = unsafe {
+                DeviceIoControl(
+                    file.as_raw_handle() as _,
+                    control,
+                    std::ptr::null_mut(),
+                    0,
+                    std::ptr::null_mut(),
+                    0,
+                    &mut returned,
+                    std::ptr::null_mut(),
+                )
+            };
+            if ok == 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+        }
+        
+        Ok(file)
+    }
+    
+    #[cfg(not(target_os = "windows"))]
+    {
+        Ok(std::fs::OpenOptions::new().read(true).write(true).open(path)?)
+    }
+}
+
+// Raw devices on macOS and Windows only accept whole-sector transfers, so
+// every access is widened to sector boundaries with read-modify-write.
+pub struct SectorIo<T: BlockDevice> {
+    inner: T,
+    offset: u64,
+    sector_size: u64,
+}
+
+impl<T: BlockDevice> SectorIo<T> {
+    pub fn new(inner: T, offset: u64, sector_size: u64) -> Self {
+        SectorIo { inner, offset, sector_size }
+    }
+    
+    pub fn set_sector_size(&mut self, sector_size: u64) {
+        self.sector_size = sector_size;
+    }
+    
+    pub fn read_at(&mut self, pos: u64, buf: &mut [u8]) -> Result<()> {
+        let start = pos - pos % self.sector_size;
+        let end = round_up(pos + buf.len() as u64, self.sector_size);
+        
+        if start == pos && end == pos + buf.len() as u64 {
+            self.inner.seek(SeekFrom::Start(self.offset + pos))?;
+            self.inner.read_exact(buf)?;
+            return Ok(());
+        }
+        
+        let mut aligned = vec![0u8; (end - start) as usize];
+        self.inner.seek(SeekFrom::Start(self.offset + start))?;
+        self.inner.read_exact(&mut aligned)?;
+        let skip = (pos - start) as usize;
+        buf.copy_from_slice(&aligned[skip..skip + buf.len()]);
+        Ok(())
+    }
+    
+    pub fn write_at(&mut self, pos: u64, data: &[u8]) -> Result<()> {
+        let start = pos - pos % self.sector_size;
+        let end = round_up(pos + data.len() as u64, self.sector_size);
+        
+        if start == pos && end == pos + data.len() as u64 {
+            self.inner.seek(SeekFrom::Start(self.offset + pos))?;
+            self.inner.write_all(data)?;
+            return Ok(());
+        }
+        
+        let mut aligned = vec![0u8; (end - start) as usize];
+        self.inner.seek(SeekFrom::Start(self.offset + start))?;
+        self.inner.read_exact(&mut aligned)?;
+        let skip = (pos - start) as usize;
+        aligned[skip..skip + data.len()].copy_from_slice(data);
+        self.inner.seek(SeekFrom::Start(self.offset + start))?;
+        self.inner.write_all(&aligned)?;
+        Ok(())
+    }
+    
+    pub fn zero(&mut self, pos: u64, len: u64) -> Result<()> {
+        const CHUNK: u64 = 1024 * 1024;
+        let zeros = vec![0u8; CHUNK.min(len) as usize];
+        let mut done = 0;
+        
+        while done < len {
+            let count = CHUNK.min(len - done);
+            self.write_at(pos + done, &zeros[..count as usize])?;
+            done += count;
+        }
+        
+        Ok(())
+    }
+    
+    pub fn flush(&mut self) -> Result<()> {
+        self.inner.flush()?;
+        Ok(())
+    }
+}
+
+fn round_up(value: u64, multiple: u64) -> u64 {
+    value.div_ceil(multiple) * multiple
+}
+
+fn split_path(path: &str) -> Vec<&str> {
+    path.split(['/', '\\']).filter(|part| !part.is_empty()).collect()
+}
+
+fn split_parent(path: &str) -> Result<(Vec<&str>, &str)> {
+    let mut parts = split_path(path);
+    let name = parts.pop().ok_or_else(|| anyhow::anyhow!("Invalid path: {}", path))?;
+    Ok((parts, name))
+}
+
+// DOS packed date and time as used by FAT directory entries and exFAT
+// timestamps. There is no timezone information available here, so UTC.
+fn dos_timestamp(time: SystemTime) -> (u16, u16, u8) {
+    let secs = time.duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    let (hour, minute, second) = (rem / 3600, (rem % 3600) / 60, rem % 60);
+    
+    let year = year.clamp(1980, 2107) as u16;
+    let date = ((year - 1980) << 9) | ((month as u16) << 5) | day as u16;
+    let time = ((hour as u16) << 11) | ((minute as u16) << 5) | (second as u16 / 2);
+    let centiseconds = ((second % 2) * 100) as u8;
+    (date, time, centiseconds)
+}
+
+// Days since 1970-01-01 to (year, month, day), from Howard Hinnant's
+// date algorithms.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn read_u16(buf: &[u8], pos: usize) -> u16 {
+    u16::from_le_bytes([buf[pos], buf[pos + 1]])
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes([buf[pos], buf[pos + 1], buf[pos + 2], buf[pos + 3]])
+}
+
+fn read_u64(buf: &[u8], pos: usize) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&buf[pos..pos + 8]);
+    u64::from_le_bytes(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    
+    const MIB: u64 = 1024 * 1024;
+    
+    #[test]
+    fn formatted_volumes_round_trip_through_reopen() {
+        for filesystem in ["exfat", "fat32"] {
+            // A partition 1 MiB into the disk, with something either side
+            // that formatting and writing must not touch.
+            let mut disk = Cursor::new(vec![0xAAu8; (66 * MIB) as usize]);
+            format_volume(&mut disk, MIB, 64 * MIB, filesystem, "TeslaCam").unwrap();
+            
+            let mut volume = Volume::open(&mut disk, MIB).unwrap();
+            assert_eq!(volume.filesystem(), filesystem);
+            volume.create_dir_all("TeslaCam/SavedClips").unwrap();
+            volume.create_dir_all("/TeslaCam/SavedClips/").unwrap();
+            volume.create_dir_all("TeslaCam\\SentryClips").unwrap();
+            volume.write_file("TeslaCam/RecentClips.txt", b"marker").unwrap();
+            volume.write_file("LockChime.wav", &vec![1u8; 100_000]).unwrap();
+            assert!(volume.write_file("LockChime.wav", b"").is_err(), "{}", filesystem);
+            assert!(volume.write_file("Missing/file.txt", b"").is_err(), "{}", filesystem);
+            assert!(volume.create_dir_all("TeslaCam/RecentClips.txt/x").is_err(), "{}", filesystem);
+            volume.flush().unwrap();
+            
+            let mut volume = Volume::open(&mut disk, MIB).unwrap();
+            for path in ["TeslaCam", "teslacam/savedclips", "TeslaCam/SentryClips", "TeslaCam/RecentClips.txt", "LockChime.wav", "/"] {
+                assert!(volume.exists(path).unwrap(), "{} {}", filesystem, path);
+            }
+            for path in ["TeslaCam/RecentClips", "Missing/file.txt", "TeslaCam/SavedClips/clip.mp4"] {
+                assert!(!volume.exists(path).unwrap(), "{} {}", filesystem, path);
+            }
+            assert!(!volume.is_dirty().unwrap(), "{}", filesystem);
+            
+            let disk = disk.get_ref();
+            assert!(disk[..MIB as usize].iter().all(|b| *b == 0xAA), "{}", filesystem);
+            assert!(disk[(65 * MIB) as usize..].iter().all(|b| *b == 0xAA), "{}", filesystem);
+        }
+    }
+    
+    #[test]
+    fn open_rejects_other_filesystems() {
+        let mut disk = Cursor::new(vec![0u8; MIB as usize]);
+        assert!(Volume::open(&mut disk, 0).is_err());
+        assert!(format_volume(&mut disk, 0, MIB, "ntfs", "Data").is_err());
+    }
+    
+    #[test]
+    fn sector_io_widens_unaligned_access() {
+        let mut io = SectorIo::new(Cursor::new((0..4096u32).map(|i| i as u8).collect::<Vec<u8>>()), 1024, 512);
+        let mut buf = [0u8; 4];
+        io.read_at(510, &mut buf).unwrap();
+        assert_eq!(buf, [254, 255, 0, 1]);
+        
+        io.write_at(511, &[9, 9]).unwrap();
+        assert_eq!(&io.inner.get_ref()[1534..1538], &[254, 9, 9, 1]);
+        assert_eq!(io.inner.get_ref()[1023], 255);
+    }
+    
+    #[test]
+    fn dos_timestamps_pack_date_and_time() {
+        // 2024-03-02 18:03:21 UTC.
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(1709402601);
+        let (date, time, centiseconds) = dos_timestamp(time);
+        
+        assert_eq!(date, (44 << 9) | (3 << 5) | 2);
+        assert_eq!(time, (18 << 11) | (3 << 5) | 10);
+        assert_eq!(centiseconds, 100);
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(dos_timestamp(UNIX_EPOCH).0, 1 << 5 | 1);
+    }
+}
\ No newline at end of file
@@ -0,0 +1,277 @@
+use crate::command::CommandRunner;
+use anyhow::Result;
+
+// Physical disks and their partitions as reported by the Storage module.
+// Enumerating disks rather than logical drives is what lets us tell the
+// stick apart from the disk Windows boots from.
+// Enums such as BusType are stringified, since ConvertTo-Json would
+// otherwise emit their numeric values.
+pub const DISK_MAP_SCRIPT: &str = "@{ \
+    Disks = @(Get-Disk | Select-Object Number, FriendlyName, SerialNumber, Size, IsBoot, IsSystem, \
+        @{ Name = 'BusType'; Expression = { \"$($_.BusType)\" } }); \
+    Partitions = @(Get-Partition | ForEach-Object { \
+        $v = $_ | Get-Volume -ErrorAction SilentlyContinue; \
+        [pscustomobject]@{ DiskNumber = $_.DiskNumber; PartitionNumber = $_.PartitionNumber; Size = $_.Size; \
+            DriveLetter = \"$($_.DriveLetter)\"; FileSystem = $v.FileSystem; FileSystemLabel = $v.FileSystemLabel } }) \
+    } | ConvertTo-Json -Depth 3";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PhysicalDisk {
+    pub number: u32,
+    pub friendly_name: Option<String>,
+    pub serial: Option<String>,
+    pub size: u64,
+    pub bus_type: String,
+    pub is_boot: bool,
+    pub is_system: bool,
+}
+
+impl PhysicalDisk {
+    pub fn path(&self) -> String {
+        format!("\\\\.\\PhysicalDrive{}", self.number)
+    }
+    
+    // USB sticks and card readers; never the disk Windows runs from.
+    pub fn is_removable(&self) -> bool {
+        matches!(self.bus_type.as_str(), "USB" | "SD" | "MMC") && !self.is_boot && !self.is_system
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiskPartition {
+    pub disk_number: u32,
+    pub partition_number: u32,
+    pub size: u64,
+    pub drive_letter: Option<char>,
+    pub filesystem: Option<String>,
+    pub label: Option<String>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct DiskMap {
+    pub disks: Vec<PhysicalDisk>,
+    pub partitions: Vec<DiskPartition>,
+}
+
+impl DiskMap {
+    pub fn disk_for_letter(&self, letter: char) -> Option<&PhysicalDisk> {
+        let letter = letter.to_ascii_uppercase();
+        let partition = self.partitions.iter().find(|p| p.drive_letter == Some(letter))?;
+        self.disks.iter().find(|d| d.number == partition.disk_number)
+    }
+    
+    // The disk behind either a volume path or a physical drive path.
+    pub fn disk_for_path(&self, path: &str) -> Option<&PhysicalDisk> {
+        match parse_physical_drive(path) {
+            Some(number) => self.disks.iter().find(|d| d.number == number),
+            None => parse_drive_letter(path).and_then(|letter| self.disk_for_letter(letter)),
+        }
+    }
+    
+    pub fn partitions_on_disk(&self, number: u32) -> Vec<&DiskPartition> {
+        self.partitions.iter()
+            .filter(|p| p.disk_number == number)
+            .collect()
+    }
+    
+    pub fn letters_for_disk(&self, number: u32) -> Vec<char> {
+        self.partitions.iter()
+            .filter(|p| p.disk_number == number)
+            .filter_map(|p| p.drive_letter)
+            .collect()
+    }
+}
+
+pub async fn read_disk_map(runner: &impl CommandRunner) -> Result<DiskMap> {
+    let output = runner.run("powershell", &["-NoProfile", "-NonInteractive", "-Command", DISK_MAP_SCRIPT]).await?;
+    
+    if !output.success {
+        return Err(anyhow::anyhow!("Failed to enumerate disks: {}", output.stderr.trim()));
+    }
+    
+    parse_disk_map(&output.stdout)
+}
+
+// Accepts either a volume ("E:", "E:\", "\\.\E:") or a physical drive
+// ("\\.\PhysicalDrive2") and returns the disk number diskpart expects.
+// Refuses the disks Windows boots and runs from.
+pub async fn resolve_disk_number(runner: &impl CommandRunner, path: &str) -> Result<u32> {
+    let map = read_disk_map(runner).await?;
+    
+    let disk = map.disk_for_path(path)
+        .ok_or_else(|| anyhow::anyhow!("No physical disk found for {}", path))?;
+    
+    if disk.is_boot || disk.is_system {
+        return Err(anyhow::anyhow!(
+            "{} is on disk {}, which holds the running system. Refusing to repartition it.",
+            path,
+            disk.number
+        ));
+    }
+    
+    Ok(disk.number)
+}
+
+pub fn parse_disk_map(json: &str) -> Result<DiskMap> {
+    let value: serde_json::Value = serde_json::from_str(json)?;
+    
+    let disks = one_or_many(&value["Disks"])
+        .iter()
+        .filter_map(|disk| Some(PhysicalDisk {
+            number: disk["Number"].as_u64()? as u32,
+            friendly_name: non_empty(&disk["FriendlyName"]),
+            serial: non_empty(&disk["SerialNumber"]),
+            size: disk["Size"].as_u64().unwrap_or(0),
+            bus_type: disk["BusType"].as_str().unwrap_or("Unknown").to_string(),
+            is_boot: disk["IsBoot"].as_bool().unwrap_or(false),
+            is_system: disk["IsSystem"].as_bool().unwrap_or(false),
+        }))
+        .collect();
+    
+    let partitions = one_or_many(&value["Partitions"])
+        .iter()
+        .filter_map(|partition| Some(DiskPartition {
+            disk_number: partition["DiskNumber"].as_u64()? as u32,
+            partition_number: partition["PartitionNumber"].as_u64()? as u32,
+            size: partition["Size"].as_u64().unwrap_or(0),
+            // Partitions without a letter report "\u0000".
+            drive_letter: partition["DriveLetter"].as_str()
+                .and_then(|s| s.chars().next())
+                .filter(|c| c.is_ascii_alphabetic())
+                .map(|c| c.to_ascii_uppercase()),
+            filesystem: non_empty(&partition["FileSystem"]),
+            label: non_empty(&partition["FileSystemLabel"]),
+        }))
+        .collect();
+    
+    Ok(DiskMap { disks, partitions })
+}
+
+// ConvertTo-Json emits a bare object instead of a one-element array on
+// older PowerShell versions.
+pub fn one_or_many(value: &serde_json::Value) -> Vec<serde_json::Value> {
+    match value {
+        serde_json::Value::Array(items) => items.clone(),
+        serde_json::Value::Null => Vec::new(),
+        other => vec![other.clone()],
+    }
+}
+
+// Serial numbers of USB bridges often come padded with spaces.
+fn non_empty(value: &serde_json::Value) -> Option<String> {
+    value.as_str().map(str::trim).filter(|s| !s.is_empty()).map(str::to_string)
+}
+
+pub fn parse_drive_letter(path: &str) -> Option<char> {
+    let volume = path.trim_start_matches("\\\\.\\").trim_end_matches('\\');
+    let mut chars = volume.chars();
+    
+    match (chars.next(), chars.next(), chars.next()) {
+        (Some(letter), Some(':'), None) if letter.is_ascii_alphabetic() => Some(letter.to_ascii_uppercase()),
+        _ => None,
+    }
+}
+
+pub fn parse_physical_drive(path: &str) -> Option<u32> {
+    let name = path.trim_start_matches("\\\\.\\");
+    let prefix = "physicaldrive";
+    
+    if name.len() > prefix.len() && name[..prefix.len()].eq_ignore_ascii_case(prefix) {
+        name[prefix.len()..].parse().ok()
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::command::FakeRunner;
+    
+    fn runner_with(fixture: &str) -> FakeRunner {
+        FakeRunner::new().respond(&["powershell", "-NoProfile", "-NonInteractive", "-Command", DISK_MAP_SCRIPT], fixture)
+    }
+    
+    #[test]
+    fn maps_letters_to_disks_and_back() {
+        let map = parse_disk_map(include_str!("../tests/fixtures/windows/disk_map.json")).unwrap();
+        
+        assert_eq!(map.disks.len(), 3);
+        assert_eq!(map.disk_for_letter('e').map(|d| d.number), Some(1));
+        assert_eq!(map.disk_for_letter('C').map(|d| d.number), Some(0));
+        assert_eq!(map.disk_for_letter('Q'), None);
+        assert_eq!(map.letters_for_disk(1), vec!['E', 'F']);
+        assert_eq!(map.letters_for_disk(2), Vec::<char>::new());
+    }
+    
+    #[test]
+    fn accepts_single_objects_from_older_powershell() {
+        let map = parse_disk_map(include_str!("../tests/fixtures/windows/disk_map_single.json")).unwrap();
+        
+        assert_eq!(map.disks.len(), 1);
+        assert_eq!(map.partitions.len(), 1);
+        assert_eq!(map.disk_for_letter('D').map(|d| d.number), Some(3));
+    }
+    
+    #[test]
+    fn parses_volume_and_physical_drive_paths() {
+        assert_eq!(parse_drive_letter("e:"), Some('E'));
+        assert_eq!(parse_drive_letter("E:\\"), Some('E'));
+        assert_eq!(parse_drive_letter("\\\\.\\E:"), Some('E'));
+        assert_eq!(parse_drive_letter("\\\\.\\PhysicalDrive1"), None);
+        assert_eq!(parse_physical_drive("\\\\.\\PhysicalDrive12"), Some(12));
+        assert_eq!(parse_physical_drive("\\\\.\\PHYSICALDRIVE3"), Some(3));
+        assert_eq!(parse_physical_drive("E:"), None);
+    }
+    
+    #[tokio::test]
+    async fn resolves_removable_volume_to_its_disk() {
+        let runner = runner_with(include_str!("../tests/fixtures/windows/disk_map.json"));
+        
+        assert_eq!(resolve_disk_number(&runner, "F:").await.unwrap(), 1);
+    }
+    
+    #[tokio::test]
+    async fn resolves_physical_drive_path() {
+        let runner = runner_with(include_str!("../tests/fixtures/windows/disk_map.json"));
+        
+        assert_eq!(resolve_disk_number(&runner, "\\\\.\\PhysicalDrive2").await.unwrap(), 2);
+    }
+    
+    #[tokio::test]
+    async fn refuses_the_system_disk() {
+        let runner = runner_with(include_str!("../tests/fixtures/windows/disk_map.json"));
+        
+        let error = resolve_disk_number(&runner, "C:").await.unwrap_err().to_string();
+        
+        assert!(error.contains("Refusing"), "{}", error);
+    }
+    
+    #[test]
+    fn reads_disk_details_and_volumes() {
+        let map = parse_disk_map(include_str!("../tests/fixtures/windows/disk_map.json")).unwrap();
+        let stick = map.disk_for_path("\\\\.\\PhysicalDrive1").unwrap();
+        
+        assert_eq!(stick.friendly_name.as_deref(), Some("SanDisk Ultra Fit"));
+        assert_eq!(stick.serial.as_deref(), Some("4C530001230815104233"));
+        assert_eq!(stick.bus_type, "USB");
+        assert!(stick.is_removable());
+        assert!(!map.disks[0].is_removable());
+        
+        let volumes: Vec<(Option<char>, Option<&str>, Option<&str>)> = map.partitions_on_disk(1).iter()
+            .map(|p| (p.drive_letter, p.filesystem.as_deref(), p.label.as_deref()))
+            .collect();
+        assert_eq!(volumes, vec![
+            (None, None, None),
+            (Some('E'), Some("exFAT"), Some("TeslaCam")),
+            (Some('F'), Some("FAT32"), Some("TESLAMUSIC")),
+        ]);
+    }
+    
+    #[tokio::test]
+    async fn unknown_letter_is_an_error() {
+        let runner = runner_with(include_str!("../tests/fixtures/windows/disk_map.json"));
+        
+        assert!(resolve_disk_number(&runner, "Q:").await.is_err());
+    }
+}
\ No newline at end of file